@@ -0,0 +1,34 @@
+/*
+ * Interface of the sink plugins of DynKBacklight, loaded from a shared object with
+ * `kind = "plugin"` in the `[sink]` section of the config file.
+ *
+ * A plugin exports `dynkb_sink_plugin`, returning a `struct dynkb_sink` that lives as long as the
+ * library. Functions returning `int` return 0 on success or a negative errno, e.g. `-EIO`. They
+ * are called from one thread at a time, though not always the same one.
+ */
+
+#ifndef DYNKB_SINK_H
+#define DYNKB_SINK_H
+
+#include <stdint.h>
+
+#define DYNKB_SINK_ABI 1
+
+struct dynkb_sink {
+    /* DYNKB_SINK_ABI. */
+    uint32_t abi;
+    /* Short name, for logs. */
+    const char *name;
+    /* Opens the device with `options` from the config, returning NULL on failure. */
+    void *(*open)(const char *options);
+    /* Sets the brightness, in percent. */
+    int (*set)(void *handle, float percent);
+    /* Reads the brightness in percent into `percent`; NULL if the device can't be read. */
+    int (*get)(void *handle, float *percent);
+    /* Closes the device; NULL if there is nothing to release. */
+    void (*close)(void *handle);
+};
+
+const struct dynkb_sink *dynkb_sink_plugin(void);
+
+#endif
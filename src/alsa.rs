@@ -0,0 +1,82 @@
+//! Capturing straight from ALSA, without cpal.
+//!
+//! A minimal path for embedded systems where cpal's host abstraction is too heavy: one blocking
+//! PCM read per chunk, 16-bit interleaved, mixed down to mono.
+
+use ::alsa::pcm::{Access, Format, HwParams, PCM};
+use ::alsa::{Direction, ValueOr};
+
+use crate::error::Result;
+use crate::source::{AudioSource, DEVICE};
+use crate::{SAMPLE_RATE, mute};
+
+/// Frames read at once, about 10 ms at [`SAMPLE_RATE`].
+const PERIOD: usize = SAMPLE_RATE as usize / 100;
+
+/// Reads an ALSA capture PCM such as `default` or `hw:1,0`.
+pub struct AlsaSource {
+    name: String,
+    pcm: PCM,
+    sample_rate: u32,
+    channels: usize,
+    /// Interleaved samples of the last read.
+    frames: Vec<i16>,
+}
+
+impl AlsaSource {
+    /// Opens `name` for capture, mono at [`SAMPLE_RATE`] or as close as the hardware allows.
+    pub fn open(name: &str) -> Result<Self> {
+        let pcm = PCM::new(name, Direction::Capture, false)?;
+        let (sample_rate, channels) = {
+            let params = HwParams::any(&pcm)?;
+            params.set_access(Access::RWInterleaved)?;
+            params.set_format(Format::s16())?;
+            let channels = params.set_channels_near(1)?;
+            let sample_rate = params.set_rate_near(SAMPLE_RATE, ValueOr::Nearest)?;
+            params.set_period_size_near(PERIOD as _, ValueOr::Nearest)?;
+            pcm.hw_params(&params)?;
+            (sample_rate, channels.max(1) as usize)
+        };
+        pcm.start()?;
+        mute::watch();
+
+        info!("Capturing from ALSA {name} at {sample_rate} Hz, {channels} channel(s)");
+        *DEVICE.lock().unwrap() = Some(name.to_string());
+        Ok(Self {
+            name: name.to_string(),
+            pcm,
+            sample_rate,
+            channels,
+            frames: vec![0; PERIOD * channels],
+        })
+    }
+}
+
+impl AudioSource for AlsaSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        let io = self.pcm.io_i16()?;
+        let frames = loop {
+            match io.readi(&mut self.frames) {
+                Ok(frames) => break frames,
+                // An overrun, the samples that didn't fit are lost but capture goes on.
+                Err(e) => {
+                    debug!("Recovering the ALSA capture: {e}");
+                    self.pcm.try_recover(e, true)?;
+                }
+            }
+        };
+        for frame in self.frames[..frames * self.channels].chunks_exact(self.channels) {
+            let sum: f32 = frame.iter().map(|&sample| sample as f32 / 32_768.0).sum();
+            buf.push(sum / self.channels as f32);
+        }
+        Ok(true)
+    }
+}
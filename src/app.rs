@@ -0,0 +1,156 @@
+//! Following applications' audio streams, through PipeWire.
+//!
+//! An application's playback stream is found in `pw-dump` and recorded with `pw-record`, whose
+//! output is read like piped audio. The same introspection tells when other applications record,
+//! e.g. a call.
+
+use std::fs;
+use std::io;
+use std::process::{Child, Command, Stdio};
+
+use serde_json::Value;
+
+use crate::SAMPLE_RATE;
+use crate::error::{Error, Result};
+use crate::source::{AudioSource, PcmFormat, StdinSource};
+
+/// Media class of the streams applications play audio on.
+const PLAYBACK_CLASS: &str = "Stream/Output/Audio";
+/// Media class of the streams applications record audio from.
+const CAPTURE_CLASS: &str = "Stream/Input/Audio";
+/// Properties of a stream matched against the application name.
+const NAME_PROPERTIES: [&str; 3] = [
+    "application.name",
+    "application.process.binary",
+    "node.name",
+];
+
+/// The audio an application plays, recorded by a `pw-record` child process.
+pub struct AppSource {
+    pcm: StdinSource,
+    recorder: Child,
+}
+
+impl AppSource {
+    /// Records the first playback stream whose application name, binary or node name contains
+    /// `name`, ignoring case.
+    pub fn open(name: &str) -> Result<Self> {
+        let (serial, stream) = find_stream(name)?;
+        let mut recorder = Command::new("pw-record")
+            .arg(format!("--target={serial}"))
+            .arg(format!("--rate={SAMPLE_RATE}"))
+            .args(["--channels=1", "--format=f32", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::App(format!("failed to run pw-record: {e}")))?;
+        let Some(pipe) = recorder.stdout.take() else {
+            return Err(Error::App("pw-record has no output".to_string()));
+        };
+
+        info!("Capturing {stream} (PipeWire object {serial})");
+        Ok(Self {
+            pcm: StdinSource::from_pipe(&stream, pipe, PcmFormat::F32Le, SAMPLE_RATE, 1),
+            recorder,
+        })
+    }
+}
+
+impl Drop for AppSource {
+    fn drop(&mut self) {
+        let _ = self.recorder.kill();
+        let _ = self.recorder.wait();
+    }
+}
+
+impl AudioSource for AppSource {
+    fn name(&self) -> String {
+        self.pcm.name()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.pcm.sample_rate()
+    }
+
+    fn read(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        self.pcm.read(buf)
+    }
+}
+
+/// The object serial and the name of the first playback stream matching `name`.
+fn find_stream(name: &str) -> Result<(u64, String)> {
+    let objects = dump().map_err(|e| Error::App(e.to_string()))?;
+    let wanted = name.to_lowercase();
+    objects
+        .iter()
+        .filter_map(|object| object.pointer("/info/props"))
+        .filter(|props| props["media.class"] == PLAYBACK_CLASS)
+        .find_map(|props| {
+            let matches = NAME_PROPERTIES.iter().any(|property| {
+                props[property]
+                    .as_str()
+                    .is_some_and(|value| value.to_lowercase().contains(&wanted))
+            });
+            let serial = props["object.serial"].as_u64()?;
+            let stream = props["application.name"]
+                .as_str()
+                .or(props["node.name"].as_str())
+                .unwrap_or(name);
+            matches.then(|| (serial, stream.to_string()))
+        })
+        .ok_or_else(|| Error::App(format!("no application matching `{name}` is playing")))
+}
+
+/// Names of the other applications recording audio right now, this process and its children
+/// aside.
+pub fn recording_apps() -> io::Result<Vec<String>> {
+    let own = std::process::id();
+    let mut apps: Vec<String> = dump()?
+        .iter()
+        .filter(|object| {
+            object
+                .pointer("/info/state")
+                .is_some_and(|state| state == "running")
+        })
+        .filter_map(|object| object.pointer("/info/props"))
+        .filter(|props| props["media.class"] == CAPTURE_CLASS)
+        .filter(|props| {
+            let pid = props["application.process.id"]
+                .as_u64()
+                .or_else(|| props["application.process.id"].as_str()?.parse().ok());
+            pid.is_none_or(|pid| pid as u32 != own && parent(pid as u32) != Some(own))
+        })
+        .filter_map(|props| {
+            props["application.name"]
+                .as_str()
+                .or(props["node.name"].as_str())
+                .map(str::to_string)
+        })
+        .collect();
+    apps.sort();
+    apps.dedup();
+    Ok(apps)
+}
+
+/// Every PipeWire object, as listed by `pw-dump`.
+fn dump() -> io::Result<Vec<Value>> {
+    let output = Command::new("pw-dump")
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run pw-dump: {e}")))?;
+    serde_json::from_slice(&output.stdout).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse pw-dump: {e}"),
+        )
+    })
+}
+
+/// The parent of process `pid`.
+fn parent(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name in parentheses may contain spaces, the fields after it don't.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
@@ -0,0 +1,139 @@
+#[cfg(feature = "bench")]
+use std::alloc::{GlobalAlloc, Layout, System};
+#[cfg(feature = "bench")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+use backlight_mic::config::Config;
+use backlight_mic::generator::{Generator, Signal};
+use backlight_mic::source::AudioSource;
+use backlight_mic::spectrum::Spectrum;
+use backlight_mic::{BAND_COUNT, FFT_SIZE, RmsState, Timing, sink};
+
+/// Number of allocations so far, counted by [`Counting`].
+#[cfg(feature = "bench")]
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting allocations for `bench`.
+#[cfg(feature = "bench")]
+pub struct Counting;
+
+#[cfg(feature = "bench")]
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+/// Number of allocations so far.
+#[cfg(feature = "bench")]
+fn allocated_so_far() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Allocations are only counted with the `bench` feature.
+#[cfg(not(feature = "bench"))]
+fn allocated_so_far() -> usize {
+    0
+}
+
+/// Runs `seconds` of `signal` through the pipeline as fast as possible, as if a display was
+/// showing every chunk, and prints how long the chunks took, how much of it went into the
+/// spectrum analysis and, with the `bench` feature, how often they allocated.
+///
+/// The first chunk plans the FFT and fills the buffers, it is left out.
+pub fn run(config: &Config, signal: Signal, seconds: u32) {
+    // Nothing to light up, only the pipeline is measured.
+    sink::select(Box::new(sink::Memory::new()));
+    // One write per chunk, interpolating writes from another thread.
+    let timing = Timing {
+        update_rate: None,
+        ..config.timing()
+    };
+    let params = config.params();
+    let mut generator = Generator::new(signal, false);
+    let sample_rate = generator.sample_rate();
+    let samples_per_chunk =
+        (sample_rate as usize * timing.chunk_size.as_millis() as usize / 1000).max(1);
+    let chunks = (seconds as usize * sample_rate as usize / samples_per_chunk).max(2);
+    let mut samples = Vec::new();
+    while samples.len() < chunks * samples_per_chunk {
+        generator.read(&mut samples).unwrap();
+    }
+
+    let mut spectrum = Spectrum::new(FFT_SIZE, BAND_COUNT);
+    spectrum.configure(&params.spectrum, sample_rate);
+    let (levels_tx, levels_rx) = mpsc::channel();
+    let mut state = RmsState::new(timing, vec![levels_tx], Arc::new(Mutex::new(params)));
+
+    let mut times = Vec::with_capacity(chunks);
+    let mut fft = Duration::ZERO;
+    let mut allocations = 0;
+    for (i, chunk) in samples.chunks_exact(samples_per_chunk).enumerate() {
+        let before = allocated_so_far();
+        let started = Instant::now();
+        state.feed(chunk, sample_rate);
+        let elapsed = started.elapsed();
+        let allocated = allocated_so_far() - before;
+
+        let started = Instant::now();
+        spectrum.process(chunk);
+        let fft_elapsed = started.elapsed();
+        // Dropped outside of the measurements, like a display would.
+        levels_rx.try_iter().for_each(drop);
+
+        if i > 0 {
+            times.push(elapsed);
+            fft += fft_elapsed;
+            allocations += allocated;
+        }
+    }
+
+    let measured = times.len();
+    let total: Duration = times.iter().sum();
+    times.sort();
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let mean = total / measured as u32;
+    println!(
+        "{measured} chunks of {} ms in {:.1} ms, {:.0}x realtime",
+        timing.chunk_size.as_millis(),
+        ms(total),
+        timing.chunk_size.as_secs_f64() * measured as f64 / total.as_secs_f64()
+    );
+    println!(
+        "Per chunk: mean {:.3} ms, median {:.3} ms, 99th percentile {:.3} ms, max {:.3} ms",
+        ms(mean),
+        ms(times[measured / 2]),
+        ms(times[measured * 99 / 100]),
+        ms(times[measured - 1])
+    );
+    println!(
+        "Spectrum (FFT and bands): {:.3} ms per chunk, {:.0}% of the mean",
+        ms(fft / measured as u32),
+        fft.as_secs_f64() / total.as_secs_f64() * 100.0
+    );
+    if cfg!(feature = "bench") {
+        println!(
+            "Allocations: {:.1} per chunk",
+            allocations as f64 / measured as f64
+        );
+    } else {
+        println!("Allocations: not counted, build with `--features bench` to count them");
+    }
+}
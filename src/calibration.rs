@@ -0,0 +1,140 @@
+//! Guided measurement of the noise floor and peak of an input.
+
+use std::time::{Duration, Instant};
+
+/// How long the user is asked to stay quiet.
+pub const QUIET_DURATION: Duration = Duration::from_secs(5);
+/// How long the user is asked to play loud music.
+pub const LOUD_DURATION: Duration = Duration::from_secs(10);
+
+/// Percentile of the quiet levels taken as the noise floor, so steady background noise stays dark.
+const NOISE_FLOOR_PERCENTILE: f32 = 0.9;
+/// Percentile of the loud levels taken as the peak, so a few clipped chunks don't skew it.
+const PEAK_PERCENTILE: f32 = 0.95;
+/// Brightness the suggested boost gives the median loud level, in percent.
+const TYPICAL_BRIGHTNESS: f32 = 80.0;
+/// Lowest and highest suggested boost, as the terminal UI allows.
+const BOOST_RANGE: (f32, f32) = (0.1, 5.0);
+
+/// The current step of a calibration run.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Measuring the noise floor while the room is quiet.
+    Quiet,
+    /// Measuring the peak while loud music plays.
+    Loud,
+}
+
+/// The levels measured by a successful calibration.
+#[derive(Clone, Copy)]
+pub struct Calibrated {
+    /// RMS level at or below which the backlight stays off.
+    pub noise_floor: f32,
+    /// RMS level mapped to full brightness.
+    pub peak: f32,
+    /// Suggested [`Params::boost`](crate::Params::boost), lighting the typical loud level at
+    /// [`TYPICAL_BRIGHTNESS`] so there is still room above it.
+    pub boost: f32,
+}
+
+/// Records RMS levels through a quiet and a loud step and derives the mapping range from them.
+pub struct Calibration {
+    step: Step,
+    step_started: Instant,
+    quiet: Vec<f32>,
+    loud: Vec<f32>,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Calibration {
+    /// Starts a calibration, beginning with the quiet step.
+    pub fn new() -> Self {
+        Self {
+            step: Step::Quiet,
+            step_started: Instant::now(),
+            quiet: Vec::new(),
+            loud: Vec::new(),
+        }
+    }
+
+    /// The step currently being recorded.
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    /// Time left in the current step.
+    pub fn remaining(&self) -> Duration {
+        let duration = match self.step {
+            Step::Quiet => QUIET_DURATION,
+            Step::Loud => LOUD_DURATION,
+        };
+        duration.saturating_sub(self.step_started.elapsed())
+    }
+
+    /// Records one RMS level, returning the outcome once the loud step is over.
+    ///
+    /// The outcome is an error message if the loud step was not clearly louder than the quiet one.
+    pub fn push(&mut self, rms: f32) -> Option<Result<Calibrated, String>> {
+        if self.remaining().is_zero() {
+            match self.step {
+                Step::Quiet => {
+                    self.step = Step::Loud;
+                    self.step_started = Instant::now();
+                }
+                Step::Loud => return Some(self.finish()),
+            }
+        }
+
+        match self.step {
+            Step::Quiet => self.quiet.push(rms),
+            Step::Loud => self.loud.push(rms),
+        }
+        None
+    }
+
+    fn finish(&mut self) -> Result<Calibrated, String> {
+        let (Some(noise_floor), Some(peak)) = (
+            percentile(&mut self.quiet, NOISE_FLOOR_PERCENTILE),
+            percentile(&mut self.loud, PEAK_PERCENTILE),
+        ) else {
+            return Err("No audio was received during calibration".to_string());
+        };
+
+        if peak <= noise_floor * 1.5 {
+            return Err(format!(
+                "Loud level ({peak:.3}) is too close to the noise floor ({noise_floor:.3})"
+            ));
+        }
+
+        // The loud levels are sorted, their median is the typical level.
+        let typical = self.loud[self.loud.len() / 2];
+        let normalized = (typical - noise_floor) / (peak - noise_floor) * 100.0;
+        // (normalized)^boost = TYPICAL_BRIGHTNESS, a median at the noise floor suggests the most.
+        let boost = if normalized > 1.0 {
+            TYPICAL_BRIGHTNESS.ln() / normalized.ln()
+        } else {
+            BOOST_RANGE.1
+        };
+
+        Ok(Calibrated {
+            noise_floor,
+            peak,
+            boost: boost.clamp(BOOST_RANGE.0, BOOST_RANGE.1),
+        })
+    }
+}
+
+/// Returns the value below which `fraction` of the values lie.
+fn percentile(values: &mut [f32], fraction: f32) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f32::total_cmp);
+    let index = ((values.len() - 1) as f32 * fraction).round() as usize;
+    Some(values[index])
+}
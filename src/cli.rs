@@ -0,0 +1,407 @@
+use std::path::PathBuf;
+
+use backlight_mic::source::PcmFormat;
+use backlight_mic::{generator, swap};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+
+/// Multicast group of `--lead` and `--follow`.
+const DEFAULT_GROUP: &str = "239.255.42.99:7274";
+
+/// Dynamic keyboard backlight controller.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Where the audio comes from.
+    #[arg(long, value_enum, default_value_t = Input::Device)]
+    pub input: Input,
+
+    /// Render the brightness a `--lead` instance sends to this multicast group instead of
+    /// analyzing audio, on 239.255.42.99:7274 unless an address is given.
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = DEFAULT_GROUP)]
+    pub follow: Option<String>,
+
+    /// Send every chunk's brightness and beat to `--follow` instances on other machines, on
+    /// 239.255.42.99:7274 unless an address is given.
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = DEFAULT_GROUP, conflicts_with = "follow")]
+    pub lead: Option<String>,
+
+    /// Capture only the audio played by this application (part of its name or binary), through
+    /// PipeWire, instead of the input.
+    #[arg(long, value_name = "NAME")]
+    pub app: Option<String>,
+
+    /// Signal of `--input generator`.
+    #[arg(long, value_enum, default_value_t = Signal::Sweep)]
+    pub signal: Signal,
+
+    /// Sample encoding of `--input stdin`.
+    #[arg(long, value_enum, default_value_t = Format::S16le)]
+    pub format: Format,
+
+    /// Sample rate of `--input stdin`, in Hz.
+    #[arg(long, default_value_t = backlight_mic::SAMPLE_RATE)]
+    pub rate: u32,
+
+    /// Number of interleaved channels of `--input stdin`, mixed down to mono.
+    #[arg(long, default_value_t = 1)]
+    pub channels: u16,
+
+    /// Follow a sensor too, mixed with the audio as the config's `[mix]` says; repeatable.
+    #[arg(long, value_enum, value_name = "SENSOR")]
+    pub sensor: Vec<Sensor>,
+
+    /// Log more, `-vv` also traces every chunk; `RUST_LOG` sets per-module filters instead.
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Log less, `-qq` only logs errors.
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    /// Run headless, without the terminal UI (e.g. as a systemd service).
+    #[arg(long)]
+    pub no_ui: bool,
+
+    /// Process the audio with realtime priority (SCHED_FIFO, or through RTKit).
+    #[arg(long)]
+    pub realtime: bool,
+
+    /// Print every chunk's levels to stdout for other programs, implies `--no-ui`.
+    #[arg(long, value_name = "FORMAT")]
+    pub emit: Option<Emit>,
+
+    /// Write every chunk's RMS, normalized level and brightness to a CSV file.
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Run the whole pipeline but log the brightness instead of writing it to the backlight.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Take brightness overrides from other programs as UDP datagrams, on 127.0.0.1:7273 unless
+    /// an address is given.
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = "127.0.0.1:7273")]
+    pub udp: Option<String>,
+
+    /// Start with a built-in preset instead of the config's active profile and effect settings,
+    /// kept over changes to the config file until another profile is selected.
+    #[arg(long, value_enum)]
+    pub preset: Option<Preset>,
+
+    /// Only react to speech, so the keyboard glows while talking but not with game or music
+    /// sounds.
+    #[arg(long)]
+    pub voice: bool,
+
+    /// Throttle analysis and backlight writes to save power, overriding `low_power` in the config.
+    #[arg(long)]
+    pub low_power: bool,
+
+    /// Open the graphical window instead of the terminal UI.
+    #[cfg(feature = "gui")]
+    #[arg(long, conflicts_with = "no_ui")]
+    pub gui: bool,
+
+    /// Show a system tray icon with pause, profile and quit entries.
+    #[cfg(feature = "tray")]
+    #[arg(long)]
+    pub tray: bool,
+
+    /// Serve the HTTP control API, on 127.0.0.1:7272 unless an address is given.
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = "127.0.0.1:7272")]
+    pub http: Option<String>,
+
+    /// Send the levels, bands and beats as OSC messages to HOST:PORT, e.g. for Resolume.
+    #[cfg(feature = "osc")]
+    #[arg(long, value_name = "ADDRESS")]
+    pub osc_out: Option<String>,
+
+    /// Take parameter changes as OSC messages, on 127.0.0.1:9000 unless an address is given.
+    #[cfg(feature = "osc")]
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = "127.0.0.1:9000")]
+    pub osc_in: Option<String>,
+
+    /// Send the brightness as a MIDI CC and beats as notes on a virtual MIDI port.
+    #[cfg(feature = "midi")]
+    #[arg(long)]
+    pub midi_out: bool,
+
+    /// Draw the spectrum along the bottom screen edge (Wayland, wlr-layer-shell).
+    #[cfg(feature = "overlay")]
+    #[arg(long)]
+    pub overlay: bool,
+}
+
+/// What to run instead of capturing the microphone.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Drive the backlight and displays from a `--record` file instead of live audio.
+    Replay {
+        /// The CSV file written by `--record`.
+        file: PathBuf,
+    },
+    /// Drive the backlight from an audio file, playing it in sync.
+    #[cfg(feature = "file")]
+    Run {
+        /// The file, in any format symphonia decodes (FLAC, MP3, WAV, Ogg Vorbis...).
+        #[arg(long)]
+        file: PathBuf,
+        /// Only drive the backlight, without playing the file.
+        #[arg(long)]
+        mute: bool,
+    },
+    /// Process an audio file as fast as it decodes, printing every chunk's levels as CSV, e.g. to
+    /// compare the output of two versions.
+    #[cfg(feature = "file")]
+    Process {
+        /// The file, in any format symphonia decodes.
+        file: PathBuf,
+    },
+    /// Measure the noise floor and peak of the input without the UI, and save them with a
+    /// suggested boost to the active profile.
+    Calibrate,
+    /// Play clicks through the speakers and report how long they take to reach the backlight.
+    MeasureLatency,
+    /// Run `--signal` through the pipeline as fast as possible and report how long a chunk
+    /// takes, the spectrum analysis' share and the allocations, to catch slowdowns.
+    Bench {
+        /// Seconds of audio processed.
+        #[arg(long, default_value_t = 10)]
+        seconds: u32,
+    },
+    /// Print the state of the running instance, queried over its control socket.
+    Status {
+        /// Print it as JSON instead.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Tap the tempo of the running instance, four taps in a row lock its beats to it.
+    Tap,
+    /// Replace a script, the plugin or the mapping of the running instance, without
+    /// interrupting the audio.
+    Load {
+        /// What to replace.
+        #[arg(value_enum)]
+        kind: Effect,
+        /// The script's path, the plugin's name or the expression.
+        source: String,
+    },
+    /// Stop a script, the plugin or the mapping of the running instance.
+    Unload {
+        /// What to stop.
+        #[arg(value_enum)]
+        kind: Effect,
+    },
+    /// Save, apply and share presets: a profile bundled with its effect settings.
+    Preset {
+        #[command(subcommand)]
+        action: PresetAction,
+    },
+    /// Set the brightness once, in percent, without listening to any audio.
+    Set {
+        /// The brightness, 0-100.
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        percent: u8,
+    },
+    /// Print the current brightness, in percent.
+    Get,
+    /// Ramp the configured sink from 0 up to a brightness and back down over a few seconds,
+    /// reading every value back, to check the sink works before running live.
+    TestSink {
+        /// The top of the ramp, in percent.
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+        max: u8,
+    },
+    /// Install a udev rule letting a group (the user's by default) write the keyboard backlight.
+    SetupPermissions {
+        /// The group given write access.
+        #[arg(long)]
+        group: Option<String>,
+        /// Only print the rule, to review it before installing.
+        #[arg(long)]
+        print: bool,
+    },
+    /// Write the brightness percents read on stdin to the backlight, run through pkexec.
+    #[command(hide = true)]
+    Helper,
+}
+
+/// Subcommands of `preset`.
+#[derive(Subcommand)]
+pub enum PresetAction {
+    /// List the built-in and saved presets.
+    List,
+    /// Save the active profile and the effect settings as a preset.
+    Save {
+        /// Name of the preset, replacing any saved preset of that name.
+        name: String,
+        /// What the preset is meant for, shown by `preset list`.
+        #[arg(long, default_value = "")]
+        description: String,
+    },
+    /// Apply a preset to the config, as the active profile of the same name.
+    Load {
+        /// Name of the preset.
+        name: String,
+    },
+    /// Write a preset to a JSON file to share it.
+    Export {
+        /// Name of the preset.
+        name: String,
+        /// The file written.
+        file: PathBuf,
+    },
+    /// Save a preset shared as a JSON file, or saved as TOML.
+    Import {
+        /// The file read.
+        file: PathBuf,
+        /// Name of the preset, by default the name it was exported as or the file's name without
+        /// its extension.
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+/// Sources of `--input`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Input {
+    /// The input device from the config file, or the default one.
+    Device,
+    /// Raw PCM piped in, e.g. `pw-cat --record - | backlight_mic --input stdin`.
+    Stdin,
+    /// A synthetic test signal, see `--signal`.
+    Generator,
+    /// The ALSA PCM named by the config file's `device`, or `default`, without cpal.
+    #[cfg(feature = "alsa")]
+    Alsa,
+    /// The audio mixer levels of OBS Studio through obs-websocket, as the config file's `[obs]`
+    /// section says.
+    #[cfg(feature = "obs")]
+    Obs,
+}
+
+/// Test signals of `--signal`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Signal {
+    /// A sine sweeping from 20 Hz to 20 kHz every 10 seconds.
+    Sweep,
+    /// 100 ms tone bursts twice a second.
+    Bursts,
+    /// White noise.
+    WhiteNoise,
+    /// Pink noise.
+    PinkNoise,
+    /// Nothing.
+    Silence,
+}
+
+impl From<Signal> for generator::Signal {
+    fn from(signal: Signal) -> Self {
+        match signal {
+            Signal::Sweep => Self::Sweep,
+            Signal::Bursts => Self::Bursts,
+            Signal::WhiteNoise => Self::WhiteNoise,
+            Signal::PinkNoise => Self::PinkNoise,
+            Signal::Silence => Self::Silence,
+        }
+    }
+}
+
+/// Effects of `load` and `unload`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Effect {
+    /// The Lua mapping, `map.lua` until replaced.
+    Lua,
+    /// The Rhai effect, `effect.rhai` until replaced.
+    Rhai,
+    /// The WASM plugin, by name.
+    Plugin,
+    /// The profile's mapping expression.
+    Mapping,
+}
+
+impl From<Effect> for swap::Kind {
+    fn from(effect: Effect) -> Self {
+        match effect {
+            Effect::Lua => Self::Lua,
+            Effect::Rhai => Self::Rhai,
+            Effect::Plugin => Self::Plugin,
+            Effect::Mapping => Self::Mapping,
+        }
+    }
+}
+
+/// Built-in presets of `--preset`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Preset {
+    /// Hard flashes on every kick that fade fast.
+    Edm,
+    /// Calm swells following a voice, for calls, podcasts and videos.
+    Speech,
+    /// A slow glow breathing with the music.
+    Ambient,
+    /// Punchy flashes on explosions and gunfire.
+    Gaming,
+}
+
+impl Preset {
+    /// Name of the preset in [`BUILT_IN`](backlight_mic::preset::BUILT_IN).
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Edm => "edm",
+            Self::Speech => "speech",
+            Self::Ambient => "ambient",
+            Self::Gaming => "gaming",
+        }
+    }
+}
+
+/// Sample encodings of `--format`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Signed 16-bit little-endian.
+    S16le,
+    /// Signed 32-bit little-endian.
+    S32le,
+    /// 32-bit float little-endian.
+    F32le,
+}
+
+impl From<Format> for PcmFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::S16le => Self::S16Le,
+            Format::S32le => Self::S32Le,
+            Format::F32le => Self::F32Le,
+        }
+    }
+}
+
+/// Sensors of `--sensor`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Sensor {
+    /// The room's brightness, from an iio ambient light sensor.
+    Ambient,
+    /// The CPU load, to double as a load meter.
+    Cpu,
+    /// Disk activity, the utilization of the busiest disk.
+    Disk,
+    /// Download and upload activity of all network interfaces.
+    Network,
+    /// The average brightness of the screen content, over X11.
+    #[cfg(feature = "screen")]
+    Screen,
+    /// Typing speed, from the keyboards' input events.
+    Typing,
+}
+
+/// Formats of `--emit`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Emit {
+    /// One JSON object per line: `timestamp`, `rms`, `normalized`, `brightness` and `beat`.
+    Json,
+}
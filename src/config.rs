@@ -0,0 +1,806 @@
+//! Persistent settings and response profiles.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::Calibrated;
+use crate::expression::Expression;
+use crate::swap::Swapped;
+use crate::{
+    ATTACK, AVERAGE_WINDOW, BOOST, MAX_UPDATE_RATE, Params, RELEASE, THRESHOLD_FACTOR, Timing,
+};
+
+/// When [`Config::save`] last wrote the file.
+static SAVED: Mutex<Option<SystemTime>> = Mutex::new(None);
+
+/// Persistent settings, stored as TOML in the user's config directory.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Name (or part of it) of the input device to capture, the default device if unset.
+    pub device: Option<String>,
+    /// RMS level at or below which the backlight stays off (the noise floor).
+    pub min_rms: f32,
+    /// RMS level mapped to full brightness.
+    pub max_rms: f32,
+    /// Length of audio analyzed at once, in milliseconds.
+    pub chunk_ms: u64,
+    /// How often the backlight is written in Hz, interpolating between chunks, at most
+    /// [`MAX_UPDATE_RATE`]; once per chunk if unset.
+    pub update_rate: Option<f32>,
+    /// When to throttle to [`LOW_POWER_RATE`](crate::LOW_POWER_RATE) to save power.
+    pub low_power: LowPower,
+    /// Where the brightness is written.
+    pub sink: Sink,
+    /// Writes the backlight through `pkexec backlight_mic helper` instead of directly, so this
+    /// process needs no privileges.
+    pub helper: bool,
+    /// Pauses the backlight while another application records, e.g. during a call.
+    pub pause_during_calls: bool,
+    /// Minutes at the noise floor after which the capture stops until sound comes back; never if
+    /// unset.
+    pub sleep_after_min: Option<u64>,
+    /// Name of the active entry of `profiles`.
+    pub profile: String,
+    /// Every known profile, by name.
+    pub profiles: BTreeMap<String, Profile>,
+    /// The MQTT broker to publish to, with the `mqtt` feature; no MQTT if unset.
+    pub mqtt: Option<Mqtt>,
+    /// The MIDI controller turning the parameters, with the `midi` feature; no MIDI if unset.
+    pub midi: Option<Midi>,
+    /// The obs-websocket server `--input obs` follows, with the `obs` feature.
+    pub obs: Obs,
+    /// How the audio and the sensors are combined.
+    pub mix: Mix,
+    /// How the audio is split into frequency bands.
+    pub spectrum: Spectrum,
+    /// What of the audio drives the brightness.
+    pub driver: Driver,
+    /// Frequency weighting of the RMS level.
+    pub weighting: Weighting,
+    /// Bands lowered while others hit, e.g. the treble on every kick.
+    pub ducking: Vec<Duck>,
+    /// Flashing on transients over a glow following sustained sounds; disabled if unset.
+    pub layers: Option<Layers>,
+    /// Pausing by clapping twice; disabled if unset.
+    pub clap: Option<Clap>,
+    /// Setting the maximum brightness by whistling; disabled if unset.
+    pub whistle: Option<Whistle>,
+    /// Flashing on desktop notifications, with the `notifications` feature; disabled if unset.
+    pub notifications: Option<Flash>,
+    /// Named flashes other programs can trigger, e.g. through the HTTP API.
+    pub effects: BTreeMap<String, Flash>,
+    /// Name of the WASM plugin in `plugins/` shaping the brightness, with the `wasm` feature;
+    /// none if unset.
+    pub plugin: Option<String>,
+    /// The stages shaping the brightness, in order; the [`default_pipeline`] if empty.
+    pub pipeline: Vec<Stage>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            device: None,
+            min_rms: 0.0,
+            max_rms: 0.9,
+            chunk_ms: crate::CHUNK_SIZE.as_millis() as u64,
+            update_rate: None,
+            low_power: LowPower::Off,
+            sink: Sink::default(),
+            helper: false,
+            pause_during_calls: false,
+            sleep_after_min: None,
+            profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), Profile::default())]),
+            mqtt: None,
+            midi: None,
+            obs: Obs::default(),
+            mix: Mix::default(),
+            spectrum: Spectrum::default(),
+            driver: Driver::default(),
+            weighting: Weighting::default(),
+            ducking: Vec::new(),
+            layers: None,
+            clap: None,
+            whistle: None,
+            notifications: None,
+            effects: BTreeMap::new(),
+            plugin: None,
+            pipeline: Vec::new(),
+        }
+    }
+}
+
+/// Connection to an MQTT broker.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mqtt {
+    /// Host name or address of the broker.
+    pub host: String,
+    /// Port of the broker.
+    pub port: u16,
+    /// Prefix of the topics, e.g. `dynkbacklight/state`.
+    pub topic: String,
+    /// User name, for brokers requiring authentication.
+    pub username: Option<String>,
+    /// Password of `username`.
+    pub password: Option<String>,
+    /// Announces a light and sensors to Home Assistant through MQTT discovery.
+    pub discovery: bool,
+    /// Topic prefix Home Assistant watches for discovery.
+    pub discovery_prefix: String,
+}
+
+impl Default for Mqtt {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 1883,
+            topic: "dynkbacklight".to_string(),
+            username: None,
+            password: None,
+            discovery: true,
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
+/// A MIDI controller, and which of its controls (CC numbers) turn which parameter.
+///
+/// Each control covers the parameter's whole range, unmapped parameters are left alone.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Midi {
+    /// Name (or part of it) of the MIDI input port, the first port if unset.
+    pub port: Option<String>,
+    /// Control turning [`Params::boost`].
+    pub boost: Option<u8>,
+    /// Control turning [`Params::threshold_factor`].
+    pub threshold: Option<u8>,
+    /// Control turning [`Params::max_brightness`].
+    pub max_brightness: Option<u8>,
+}
+
+/// An OBS Studio instance whose audio mixer levels drive the backlight, through obs-websocket.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Obs {
+    /// Address of the obs-websocket server.
+    pub url: String,
+    /// Server password, when authentication is enabled in OBS.
+    pub password: Option<String>,
+    /// Names of the mixer's inputs followed, every input if empty.
+    pub inputs: Vec<String>,
+}
+
+impl Default for Obs {
+    fn default() -> Self {
+        Self {
+            url: "ws://127.0.0.1:4455".to_string(),
+            password: None,
+            inputs: Vec::new(),
+        }
+    }
+}
+
+/// How the audio-driven brightness and the [`sensor`](crate::sensor) levels are combined into
+/// one brightness.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mix {
+    /// How the weighted levels are combined.
+    pub mode: MixMode,
+    /// Weight of each source by name, `audio` or a sensor's; 1.0 for those not listed.
+    pub weights: BTreeMap<String, f32>,
+}
+
+impl Mix {
+    /// Weight of the source `name`.
+    pub fn weight(&self, name: &str) -> f32 {
+        self.weights.get(name).copied().unwrap_or(1.0)
+    }
+}
+
+/// Ways of combining the levels of [`Mix`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MixMode {
+    /// The loudest sensor sets a baseline, the audio lights up what it leaves dark.
+    #[default]
+    Layer,
+    /// The highest level.
+    Max,
+    /// The levels added up, capped at full brightness.
+    Sum,
+    /// The average of the levels, in proportion to their weights.
+    Blend,
+}
+
+/// How the FFT is split into the bands shown by the displays and sent to other programs.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Spectrum {
+    /// Frequency scale the bands are evenly spaced on.
+    pub scale: BandScale,
+    /// Window applied to each FFT frame.
+    pub window: Window,
+    /// Share of each FFT frame the next one overlaps, 0.0-0.9; 0.0 analyzes each chunk once.
+    pub overlap: f32,
+}
+
+/// Window functions of [`Spectrum`], trading frequency resolution against band bleed.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Window {
+    /// A good compromise, little bleed.
+    #[default]
+    Hann,
+    /// Slightly sharper peaks than Hann, more bleed far from them.
+    Hamming,
+    /// Wider peaks, the least bleed.
+    Blackman,
+    /// No window, the sharpest peaks and the most bleed.
+    Rectangular,
+}
+
+/// Frequency scales of [`Spectrum`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BandScale {
+    /// Logarithmic, every band spans the same number of octaves.
+    #[default]
+    Log,
+    /// The mel scale, perceptually even pitch steps.
+    Mel,
+    /// The Bark scale, the ear's critical bands.
+    Bark,
+}
+
+/// Measures of the audio that can drive the brightness.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Driver {
+    /// The RMS level, how loud the audio is.
+    #[default]
+    Rms,
+    /// The spectral flux, how much the spectrum just changed: new notes and hits light up,
+    /// sustained sounds stay calm.
+    Flux,
+}
+
+/// Frequency weightings of the RMS level, see [`weighting`](crate::weighting).
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weighting {
+    /// Every frequency counts the same.
+    #[default]
+    None,
+    /// K-weighting, as LUFS loudness meters use.
+    K,
+}
+
+/// Ways of deriving the trigger threshold from the recent levels.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThresholdMode {
+    /// The moving average times `threshold_factor`.
+    #[default]
+    Factor,
+    /// The moving average plus `threshold_factor` standard deviations of the recent levels, so
+    /// the sensitivity follows how much the audio varies.
+    Adaptive,
+}
+
+/// A soft-knee compressor on the normalized level, in decibels below full level, so quiet
+/// passages stay visible and loud ones don't all look the same.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Compressor {
+    /// Level above which it is compressed, in dB.
+    pub threshold_db: f32,
+    /// How many dB above the threshold make one dB more, 1.0 for none; a high ratio limits.
+    pub ratio: f32,
+    /// Width of the soft knee around the threshold, in dB; 0.0 for a hard knee.
+    pub knee_db: f32,
+    /// Gain added after compression, in dB; by default what keeps a full level full.
+    pub makeup_db: Option<f32>,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self {
+            threshold_db: -18.0,
+            ratio: 3.0,
+            knee_db: 6.0,
+            makeup_db: None,
+        }
+    }
+}
+
+/// Spectrum bands lowered for a while whenever other bands hit, so a kick reads clearly in a
+/// dense mix.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Duck {
+    /// First and last band whose hits duck, 0 being the lowest.
+    pub trigger: [usize; 2],
+    /// First and last band ducked.
+    pub target: [usize; 2],
+    /// Magnitude of a trigger band that counts as a hit, 0.0-1.0.
+    pub threshold: f32,
+    /// Share of their magnitude the target bands lose, 0.0-1.0.
+    pub depth: f32,
+    /// How long the target bands stay ducked after a hit, in milliseconds.
+    pub hold_ms: u64,
+}
+
+impl Default for Duck {
+    fn default() -> Self {
+        Self {
+            trigger: [0, 2],
+            target: [8, 15],
+            threshold: 0.5,
+            depth: 0.8,
+            hold_ms: 100,
+        }
+    }
+}
+
+/// The audio split into transients, which flash through the usual beat detection, and sustained
+/// sounds, which light a steady glow under the flashes.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layers {
+    /// Brightness of the glow at full sustained level, in percent of the full brightness.
+    pub glow: f32,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self { glow: 40.0 }
+    }
+}
+
+/// A stage of the pipeline shaping the brightness, between the beat detection and the sink.
+///
+/// Fields left unset take the active profile's value.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "lowercase")]
+pub enum Stage {
+    /// Maps the level to a brightness, with `mapping` if set and otherwise with the `boost` curve.
+    Mapper {
+        /// See [`Params::boost`].
+        boost: Option<f32>,
+        /// See [`Params::mapping`].
+        mapping: Option<Expression>,
+    },
+    /// Follows the brightness on beats, rising at most by `attack`, and fades by `release`
+    /// between them.
+    Smoother {
+        /// See [`Params::attack`].
+        attack: Option<f32>,
+        /// See [`Params::release`].
+        release: Option<f32>,
+    },
+    /// Raises the brightness to the glow of sustained sounds, with [`Layers`].
+    Glow,
+    /// Runs `map.lua`, with the `lua` feature.
+    Lua,
+    /// Runs `effect.rhai`, with the `rhai` feature.
+    Rhai,
+    /// Runs a WASM plugin, with the `wasm` feature.
+    Plugin {
+        /// Name of the plugin, the config's `plugin` if unset.
+        name: Option<String>,
+    },
+}
+
+/// The stages of the built-in pipeline, used when the config lists none.
+pub fn default_pipeline() -> Vec<Stage> {
+    vec![
+        Stage::Mapper {
+            boost: None,
+            mapping: None,
+        },
+        Stage::Smoother {
+            attack: None,
+            release: None,
+        },
+        Stage::Glow,
+        Stage::Lua,
+        Stage::Rhai,
+        Stage::Plugin { name: None },
+    ]
+}
+
+/// How a double clap, which toggles [`Params::paused`], is recognized.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Clap {
+    /// Longest time between the two claps, in milliseconds.
+    pub window_ms: u64,
+    /// Multiplier of the moving average a clap must rise above, higher ignores softer claps.
+    pub factor: f32,
+}
+
+impl Default for Clap {
+    fn default() -> Self {
+        Self {
+            window_ms: 600,
+            factor: 4.0,
+        }
+    }
+}
+
+/// The pitches of a whistle setting [`Params::max_brightness`], the lowest for none and the
+/// highest for full brightness.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Whistle {
+    /// Pitch mapped to 0%, in Hz.
+    pub low_hz: f32,
+    /// Pitch mapped to 100%, in Hz.
+    pub high_hz: f32,
+}
+
+impl Default for Whistle {
+    fn default() -> Self {
+        Self {
+            low_hz: 800.0,
+            high_hz: 2500.0,
+        }
+    }
+}
+
+/// A pattern of pulses played over the audio-driven brightness, which shows through whenever
+/// it is brighter.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Flash {
+    /// Brightness of the pulses, in percent.
+    pub brightness: f32,
+    /// Color of the pulses like `#ff8000`, replacing `brightness` with its own: the backlight has
+    /// a single color.
+    pub color: Option<String>,
+    /// Length of a pulse, in milliseconds.
+    pub on_ms: u64,
+    /// Time between two pulses, in milliseconds.
+    pub off_ms: u64,
+    /// Number of pulses.
+    pub count: u32,
+}
+
+impl Default for Flash {
+    fn default() -> Self {
+        Self {
+            brightness: 100.0,
+            color: None,
+            on_ms: 120,
+            off_ms: 120,
+            count: 2,
+        }
+    }
+}
+
+impl Flash {
+    /// Brightness of the pulses in percent, from `color` if it is set and valid.
+    pub fn level(&self) -> f32 {
+        self.color
+            .as_deref()
+            .and_then(color_brightness)
+            .unwrap_or(self.brightness)
+    }
+}
+
+/// Brightness in percent of a color like `#ff8000`, that of its brightest channel.
+pub fn color_brightness(color: &str) -> Option<f32> {
+    let rgb = color
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6)
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())?;
+    let channels = [rgb >> 16, rgb >> 8, rgb].map(|channel| channel & 0xff);
+    Some(channels.into_iter().max().unwrap_or_default() as f32 / 255.0 * 100.0)
+}
+
+/// Where the brightness is written, see [`sink`](crate::sink).
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sink {
+    /// Which kind of sink.
+    pub kind: SinkKind,
+    /// Read the brightness back after every sysfs write, warning when the kernel clamped or
+    /// ignored it; not with the `helper`.
+    pub verify: bool,
+    /// Command setting the brightness, for [`SinkKind::Command`], e.g.
+    /// `brightnessctl -d {device} set {value}%`.
+    pub set: String,
+    /// Command printing the brightness in percent, for [`SinkKind::Command`].
+    pub get: Option<String>,
+    /// What `{device}` stands for in the commands.
+    pub device: String,
+    /// Monitor to drive, for [`SinkKind::Ddc`], numbered like `ddcutil detect` does. The first
+    /// one if unset.
+    pub display: Option<u32>,
+    /// File written, for [`SinkKind::File`], or shared object loaded, for [`SinkKind::Plugin`].
+    pub path: Option<PathBuf>,
+    /// Value written at 0%, for [`SinkKind::File`].
+    pub min: f64,
+    /// Value written at 100%, for [`SinkKind::File`].
+    pub max: f64,
+    /// What is written to the file, `{value}` replaced by the value.
+    pub format: String,
+    /// Passed to the plugin when it opens its device, for [`SinkKind::Plugin`].
+    pub options: String,
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Self {
+            kind: SinkKind::default(),
+            verify: false,
+            set: String::new(),
+            get: None,
+            device: String::new(),
+            display: None,
+            path: None,
+            min: 0.0,
+            max: 100.0,
+            format: "{value}".to_string(),
+            options: String::new(),
+        }
+    }
+}
+
+/// Kinds of [`Sink`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    /// Sysfs, or in a sandbox the first D-Bus service that answers.
+    #[default]
+    Auto,
+    /// The backlight's sysfs file.
+    Sysfs,
+    /// systemd-logind, with the `dbus` feature.
+    Logind,
+    /// UPower's keyboard backlight, with the `dbus` feature.
+    Upower,
+    /// A Razer keyboard through the OpenRazer daemon, with the `dbus` feature.
+    Openrazer,
+    /// An external tool, e.g. `brightnessctl` or `light`.
+    Command,
+    /// An external monitor over DDC/CI, through `ddcutil`.
+    Ddc,
+    /// Any writable file, with the range mapped.
+    File,
+    /// A shared object implementing `dist/dynkb_sink.h`, with the `sink-plugins` feature.
+    Plugin,
+}
+
+/// When the low-power mode is active.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LowPower {
+    /// Never, always run at full rate.
+    #[default]
+    Off,
+    /// Whenever the machine runs on battery.
+    Battery,
+    /// Always.
+    On,
+}
+
+/// Name of the profile created when the config has none.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// A named set of response parameters, switchable at runtime.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// RMS level at or below which the backlight stays off with this profile, the config's
+    /// `min_rms` if unset.
+    pub min_rms: Option<f32>,
+    /// RMS level mapped to full brightness with this profile, the config's `max_rms` if unset.
+    pub max_rms: Option<f32>,
+    /// See [`Params::boost`].
+    pub boost: f32,
+    /// See [`Params::threshold_factor`].
+    pub threshold_factor: f32,
+    /// See [`Params::threshold_mode`].
+    pub threshold_mode: ThresholdMode,
+    /// See [`Params::attack`].
+    pub attack: f32,
+    /// See [`Params::release`].
+    pub release: f32,
+    /// See [`Params::average`], in milliseconds.
+    pub average_ms: u64,
+    /// See [`Params::compressor`].
+    pub compressor: Option<Compressor>,
+    /// See [`Params::mapping`].
+    pub mapping: Option<Expression>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            min_rms: None,
+            max_rms: None,
+            boost: crate::BOOST,
+            threshold_factor: crate::THRESHOLD_FACTOR,
+            threshold_mode: ThresholdMode::default(),
+            attack: crate::ATTACK,
+            release: crate::RELEASE,
+            average_ms: crate::AVERAGE_WINDOW.as_millis() as u64,
+            compressor: None,
+            mapping: None,
+        }
+    }
+}
+
+impl Profile {
+    /// Overwrites the live parameters this profile controls, except a mapping swapped over the
+    /// control socket, see [`Swapped`].
+    pub fn apply(&self, params: &mut Params) {
+        if let Some(min_rms) = self.min_rms {
+            params.min_rms = min_rms;
+        }
+        if let Some(max_rms) = self.max_rms {
+            params.max_rms = max_rms;
+        }
+        params.boost = self.boost;
+        params.threshold_factor = self.threshold_factor;
+        params.threshold_mode = self.threshold_mode;
+        params.attack = self.attack;
+        params.release = self.release;
+        params.average = Duration::from_millis(self.average_ms.max(1));
+        params.compressor = self.compressor.clone();
+        params.mapping = params
+            .swapped
+            .mapping
+            .clone()
+            .unwrap_or_else(|| self.mapping.clone());
+    }
+}
+
+impl Config {
+    /// Location of the config file, `$XDG_CONFIG_HOME/dynkbacklight/config.toml`.
+    pub fn path() -> PathBuf {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .unwrap_or_else(|| PathBuf::from("."));
+        base.join("dynkbacklight").join("config.toml")
+    }
+
+    /// The active profile, or the defaults if it names a profile that doesn't exist.
+    pub fn active_profile(&self) -> Profile {
+        self.profiles
+            .get(&self.profile)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Stores a calibration in the active profile, creating it if it doesn't exist.
+    pub fn calibrate(&mut self, calibrated: &Calibrated) {
+        let profile = self.profiles.entry(self.profile.clone()).or_default();
+        profile.min_rms = Some(calibrated.noise_floor);
+        profile.max_rms = Some(calibrated.peak);
+        profile.boost = calibrated.boost;
+    }
+
+    /// The configured analysis and update timing, ignoring values that can't work.
+    pub fn timing(&self) -> Timing {
+        Timing {
+            chunk_size: Duration::from_millis(self.chunk_ms.max(1)),
+            update_rate: self
+                .update_rate
+                .filter(|rate| rate.is_finite() && *rate > 0.0)
+                .map(|rate| rate.min(MAX_UPDATE_RATE)),
+        }
+    }
+
+    /// The live parameters these settings start with, see [`apply`](Self::apply).
+    pub fn params(&self) -> Params {
+        let mut params = Params {
+            min_rms: self.min_rms,
+            max_rms: self.max_rms,
+            boost: BOOST,
+            threshold_factor: THRESHOLD_FACTOR,
+            threshold_mode: ThresholdMode::default(),
+            average: AVERAGE_WINDOW,
+            attack: ATTACK,
+            release: RELEASE,
+            max_brightness: 100.0,
+            paused: false,
+            off: false,
+            low_power: false,
+            voice_only: false,
+            tempo: None,
+            sleep_after: None,
+            mix: Mix::default(),
+            spectrum: Spectrum::default(),
+            driver: Driver::default(),
+            weighting: Weighting::default(),
+            compressor: None,
+            mapping: None,
+            ducking: Vec::new(),
+            layers: None,
+            clap: None,
+            whistle: None,
+            forced: None,
+            flash: None,
+            plugin: None,
+            pipeline: Vec::new(),
+            swapped: Swapped::default(),
+        };
+        self.apply(&mut params);
+        params
+    }
+
+    /// Overwrites the live parameters with the calibrated range, the sleep delay, the mix, the
+    /// gestures and the active profile. A plugin or mapping swapped over the control socket is
+    /// kept, see [`Swapped`].
+    pub fn apply(&self, params: &mut Params) {
+        params.min_rms = self.min_rms;
+        params.max_rms = self.max_rms;
+        params.sleep_after = self.sleep_after();
+        params.mix = self.mix.clone();
+        params.spectrum = self.spectrum.clone();
+        params.driver = self.driver;
+        params.weighting = self.weighting;
+        params.ducking = self.ducking.clone();
+        params.layers = self.layers.clone();
+        params.clap = self.clap.clone();
+        params.whistle = self.whistle.clone();
+        params.plugin = params
+            .swapped
+            .plugin
+            .clone()
+            .unwrap_or_else(|| self.plugin.clone());
+        params.pipeline = if self.pipeline.is_empty() {
+            default_pipeline()
+        } else {
+            self.pipeline.clone()
+        };
+        self.active_profile().apply(params);
+    }
+
+    /// How long the level may stay at the noise floor before the capture sleeps.
+    pub fn sleep_after(&self) -> Option<Duration> {
+        self.sleep_after_min
+            .map(|minutes| Duration::from_secs(minutes * 60))
+    }
+
+    /// Reads the config file, falling back to the defaults when it does not exist yet.
+    pub fn load() -> io::Result<Self> {
+        match fs::read_to_string(Self::path()) {
+            Ok(text) => {
+                toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the config file, creating its directory if needed.
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(&path, text)?;
+        *SAVED.lock().unwrap() = fs::metadata(&path)?.modified().ok();
+        Ok(())
+    }
+
+    /// When this process last saved the config file, see [`save`](Self::save).
+    pub fn saved() -> Option<SystemTime> {
+        *SAVED.lock().unwrap()
+    }
+}
@@ -0,0 +1,544 @@
+//! The control plane: everything that isn't realtime audio, run as tasks on one tokio runtime.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::UnixListener;
+use tokio::task::LocalSet;
+
+use crate::config::{Config, LowPower, color_brightness};
+use crate::error::{self, Error};
+use crate::{
+    Flashing, LOW_POWER_RATE, Levels, Params, SharedParams, app, mute, notify, preset, sink,
+    source, swap,
+};
+
+/// How often the config file is checked for changes.
+const CONFIG_POLL: Duration = Duration::from_secs(1);
+/// How often the power supply is checked in [`LowPower::Battery`] mode.
+const POWER_POLL: Duration = Duration::from_secs(10);
+/// Where the kernel lists the power supplies.
+const POWER_SUPPLIES: &str = "/sys/class/power_supply";
+/// How often other applications are checked for recording, in [`spawn`]'s `pause_during_calls`.
+const CALLS_POLL: Duration = Duration::from_secs(3);
+/// How often the latest levels are picked up for the status.
+const LEVELS_POLL: Duration = Duration::from_millis(200);
+/// Number of taps setting the tempo.
+pub const TAPS: usize = 4;
+/// Longest time between two taps of the same tempo, a longer pause starts over.
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The running instance as reported by [`Handle::status`] and [`query_status`].
+#[derive(Serialize, Deserialize)]
+pub struct Status {
+    /// Name of the input device being captured.
+    pub device: Option<String>,
+    /// Why the input can't be opened, while it is being retried.
+    #[serde(default)]
+    pub unavailable: Option<String>,
+    /// Why writes to the backlight fail, while they are retried.
+    #[serde(default)]
+    pub degraded: Option<String>,
+    /// Whether writing to the backlight is paused.
+    pub paused: bool,
+    /// Whether the backlight is forced off.
+    #[serde(default)]
+    pub off: bool,
+    /// Whether the microphone is muted, and taken as silence.
+    #[serde(default)]
+    pub muted: bool,
+    /// Whether analysis and writes are throttled.
+    pub low_power: bool,
+    /// Name of the active profile.
+    pub profile: String,
+    /// Tempo set by tapping, in beats per minute.
+    #[serde(default)]
+    pub tempo: Option<f32>,
+    /// RMS level of the latest chunk.
+    pub rms: f32,
+    /// Current brightness in percent.
+    pub brightness: f32,
+    /// Seconds since the instance started.
+    pub uptime: u64,
+    /// The most recent error, see [`notify::last_error`].
+    pub last_error: Option<String>,
+}
+
+/// What the control plane knows beyond the live parameters.
+struct State {
+    started: Instant,
+    profile: String,
+    levels: Levels,
+    /// The taps of the tempo being tapped, at most [`TAPS`].
+    taps: Vec<Instant>,
+    /// The built-in preset of `--preset`, applied over the config until another profile is
+    /// selected.
+    preset: Option<String>,
+}
+
+/// Controls the running instance, shared by the control socket and the other remote frontends.
+#[derive(Clone)]
+pub struct Handle {
+    params: SharedParams,
+    state: Arc<Mutex<State>>,
+}
+
+impl Handle {
+    /// The live parameters.
+    pub fn params(&self) -> &SharedParams {
+        &self.params
+    }
+
+    /// The current state of the instance.
+    pub fn status(&self) -> Status {
+        let params = self.params.lock().unwrap();
+        let state = self.state.lock().unwrap();
+        Status {
+            device: source::current_device(),
+            unavailable: source::unavailable(),
+            degraded: sink::degraded(),
+            paused: params.paused,
+            off: params.off,
+            muted: mute::muted(),
+            low_power: params.low_power,
+            profile: state.profile.clone(),
+            tempo: params.tempo,
+            rms: state.levels.rms,
+            brightness: state.levels.brightness,
+            uptime: state.started.elapsed().as_secs(),
+            last_error: notify::last_error(),
+        }
+    }
+
+    /// Names of the profiles in the config file.
+    pub fn profiles(&self) -> io::Result<Vec<String>> {
+        Ok(Config::load()?.profiles.into_keys().collect())
+    }
+
+    /// Applies the profile `name` and saves it as the active one.
+    pub fn select_profile(&self, name: &str) -> io::Result<()> {
+        let mut config = Config::load()?;
+        let profile = config.profiles.get(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no profile named `{name}`"),
+            )
+        })?;
+        let mut params = self.params.lock().unwrap();
+        profile.apply(&mut params);
+        info!("Switched to profile {name}");
+        log_kept_swaps(&params);
+        drop(params);
+
+        config.profile = name.to_string();
+        let mut state = self.state.lock().unwrap();
+        if let Some(preset) = state.preset.take() {
+            info!("Dropped the {preset} preset");
+        }
+        state.profile = config.profile.clone();
+        drop(state);
+        config.save()
+    }
+
+    /// Records a tap, and sets the tempo from the last [`TAPS`] taps once there are enough.
+    ///
+    /// Returns the new tempo in beats per minute, or the number of taps so far.
+    pub fn tap(&self) -> Result<f32, usize> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let taps = &mut state.taps;
+        if taps
+            .last()
+            .is_some_and(|last| now.duration_since(*last) > TAP_TIMEOUT)
+        {
+            taps.clear();
+        }
+        taps.push(now);
+        if taps.len() > TAPS {
+            taps.remove(0);
+        }
+        if taps.len() < TAPS {
+            return Err(taps.len());
+        }
+
+        let interval = now.duration_since(taps[0]).as_secs_f32() / (TAPS - 1) as f32;
+        let tempo = 60.0 / interval;
+        self.params.lock().unwrap().tempo = Some(tempo);
+        info!("Tapped a tempo of {tempo:.0} BPM");
+        Ok(tempo)
+    }
+
+    /// Plays the effect `name` from the config file over the backlight.
+    pub fn trigger(&self, name: &str) -> io::Result<()> {
+        let config = Config::load()?;
+        let flash = config.effects.get(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no effect named `{name}`"))
+        })?;
+        if let Some(color) = flash.color.as_deref()
+            && color_brightness(color).is_none()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the color `{color}` of `{name}` is not like #ff8000"),
+            ));
+        }
+        debug!("Triggered effect {name}");
+        self.params.lock().unwrap().flash = Some(Flashing {
+            flash: flash.clone(),
+            started: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Replaces the effect of `kind` with `source` without interrupting the audio, see
+    /// [`swap::load`].
+    pub fn load(&self, kind: swap::Kind, source: &str) -> error::Result<()> {
+        swap::load(kind, source, &self.params)
+    }
+
+    /// Stops the effect of `kind`, see [`swap::unload`].
+    pub fn unload(&self, kind: swap::Kind) -> error::Result<()> {
+        swap::unload(kind, &self.params)
+    }
+}
+
+/// Starts the control plane on its own thread, away from the audio processing, and returns its
+/// handle and where to send the levels for [`Handle::status`].
+///
+/// `profile` is the initial profile, later ones are picked up from the config file, with the
+/// built-in `preset` started with applied over it until another profile is selected. With
+/// `pause_during_calls`, the backlight is paused while another application records.
+pub fn spawn(
+    params: SharedParams,
+    low_power: LowPower,
+    profile: String,
+    preset: Option<String>,
+    pause_during_calls: bool,
+) -> (Handle, Sender<Levels>) {
+    let (levels_tx, levels_rx) = mpsc::channel();
+    let handle = Handle {
+        params,
+        state: Arc::new(Mutex::new(State {
+            started: Instant::now(),
+            profile,
+            levels: Levels::default(),
+            taps: Vec::new(),
+            preset,
+        })),
+    };
+    let control = handle.clone();
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                error!("Failed to start the control plane: {e}");
+                return;
+            }
+        };
+
+        let tasks = LocalSet::new();
+        if low_power == LowPower::Battery {
+            tasks.spawn_local(watch_power(Arc::clone(&control.params)));
+        }
+        if pause_during_calls {
+            tasks.spawn_local(watch_calls(Arc::clone(&control.params)));
+        }
+        tasks.spawn_local(track_levels(levels_rx, Arc::clone(&control.state)));
+        tasks.spawn_local(serve(control.clone()));
+        tasks.spawn_local(watch_config(control));
+        runtime.block_on(tasks);
+    });
+    (handle, levels_tx)
+}
+
+/// Where the control socket listens, `$XDG_RUNTIME_DIR/dynkbacklight.sock`.
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("dynkbacklight.sock"),
+        None => {
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir().join(format!("dynkbacklight-{uid}.sock"))
+        }
+    }
+}
+
+/// Asks the running instance for its status over the control socket.
+pub fn query_status() -> io::Result<Status> {
+    let response = request("status")?;
+    serde_json::from_str(&response)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, response))
+}
+
+/// Taps the tempo of the running instance over the control socket, returning its answer.
+pub fn tap() -> io::Result<String> {
+    request("tap")
+}
+
+/// Replaces the effect of `kind` in the running instance with `source` over the control socket:
+/// a script's absolute path, a plugin's name or an expression.
+pub fn load(kind: swap::Kind, source: &str) -> error::Result<()> {
+    answered(request(&format!("load {} {source}", kind.name())))
+}
+
+/// Stops the effect of `kind` in the running instance over the control socket.
+pub fn unload(kind: swap::Kind) -> error::Result<()> {
+    answered(request(&format!("unload {}", kind.name())))
+}
+
+/// The error the running instance answered with, if any.
+fn answered(response: io::Result<String>) -> error::Result<()> {
+    let response = response.map_err(Error::Control)?;
+    match response.strip_prefix("error: ") {
+        Some(error) => Err(Error::Refused(error.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Sends one request line to the running instance and returns its response line.
+fn request(request: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    writeln!(stream, "{request}")?;
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    Ok(response.trim_end().to_string())
+}
+
+/// Keeps the latest levels for the status, dropping the others.
+async fn track_levels(levels: Receiver<Levels>, state: Arc<Mutex<State>>) {
+    let mut interval = tokio::time::interval(LEVELS_POLL);
+    loop {
+        interval.tick().await;
+        if let Some(latest) = levels.try_iter().last() {
+            state.lock().unwrap().levels = latest;
+        }
+    }
+}
+
+/// Answers requests on the control socket, one line each.
+async fn serve(control: Handle) {
+    let path = socket_path();
+    let listener = match bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Control socket {} unavailable: {e}", path.display());
+            return;
+        }
+    };
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let control = control.clone();
+                tokio::task::spawn_local(async move {
+                    if let Err(e) = answer(stream, &control).await {
+                        debug!("Control connection failed: {e}");
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept a control connection: {e}"),
+        }
+    }
+}
+
+/// Listens on `path`, taking over a socket left behind by an instance that crashed.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another instance is running",
+        ));
+    }
+    let _ = std::fs::remove_file(path);
+    UnixListener::bind(path)
+}
+
+async fn answer(stream: tokio::net::UnixStream, control: &Handle) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        let (request, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let response = match request {
+            "status" => serde_json::to_string(&control.status()).unwrap_or_default(),
+            "tap" => match control.tap() {
+                Ok(tempo) => format!("tempo {tempo:.1}"),
+                Err(taps) => format!("tap {taps}/{TAPS}"),
+            },
+            "load" => {
+                let (kind, source) = argument.split_once(' ').unwrap_or((argument, ""));
+                swapped(kind, |kind| control.load(kind, source.trim()))
+            }
+            "unload" => swapped(argument, |kind| control.unload(kind)),
+            other => format!("error: unknown request `{other}`"),
+        };
+        write.write_all(response.as_bytes()).await?;
+        write.write_all(b"\n").await?;
+    }
+    Ok(())
+}
+
+/// The response to a `load` or `unload` of the effect `kind`.
+fn swapped(kind: &str, swap: impl FnOnce(swap::Kind) -> error::Result<()>) -> String {
+    let Some(kind) = swap::Kind::parse(kind.trim()) else {
+        return format!("error: unknown effect `{kind}`, not lua, rhai, plugin or mapping");
+    };
+    match swap(kind) {
+        Ok(()) => "ok".to_string(),
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+/// Applies the config file to the live parameters whenever it changes.
+async fn watch_config(control: Handle) {
+    let path = Config::path();
+    let mut modified = modified_time(&path).await;
+    let mut interval = tokio::time::interval(CONFIG_POLL);
+
+    loop {
+        interval.tick().await;
+        let current = modified_time(&path).await;
+        if current == modified {
+            continue;
+        }
+        modified = current;
+
+        // What this process saved is already live, applying it again would undo the tuning
+        // done since, only the profile it names is picked up.
+        if current.is_some() && current == Config::saved() {
+            match Config::load() {
+                Ok(config) => {
+                    let mut state = control.state.lock().unwrap();
+                    if state.preset.is_none() {
+                        state.profile = config.profile;
+                    }
+                }
+                Err(e) => warn!("Failed to reload the config: {e}"),
+            }
+            continue;
+        }
+
+        match Config::load() {
+            Ok(mut config) => {
+                let mut params = control.params.lock().unwrap();
+                let mut state = control.state.lock().unwrap();
+                apply_reloaded(&mut config, state.preset.as_deref(), &mut params);
+                state.profile = config.profile;
+                info!("Reloaded {}", path.display());
+                if let Some(preset) = &state.preset {
+                    info!("Kept the {preset} preset over it");
+                }
+                log_kept_swaps(&params);
+            }
+            Err(e) => warn!("Failed to reload the config: {e}"),
+        }
+    }
+}
+
+/// Applies the reloaded `config` to the live parameters, with the built-in `preset` the
+/// instance started with over it, so that `--preset` survives changes to the config file.
+pub fn apply_reloaded(config: &mut Config, preset: Option<&str>, params: &mut Params) {
+    preset::apply_built_in(preset, config);
+    config.apply(params);
+}
+
+/// Tells that the effects swapped over the control socket were kept over the config's.
+fn log_kept_swaps(params: &Params) {
+    for kind in params.swapped.kinds() {
+        info!(
+            "Keeping the {} swapped over the control socket instead of the config's",
+            kind.name()
+        );
+    }
+}
+
+/// Pauses the backlight while another application records, e.g. during a call, so it doesn't
+/// flash with every word.
+///
+/// Pausing by hand while paused for a call is left alone, and so is resuming during the call.
+async fn watch_calls(params: SharedParams) {
+    let mut interval = tokio::time::interval(CALLS_POLL);
+    let mut paused_for_call = false;
+    let mut reported = false;
+    loop {
+        interval.tick().await;
+        let apps = match tokio::task::spawn_blocking(app::recording_apps).await {
+            Ok(Ok(apps)) => apps,
+            Ok(Err(e)) => {
+                if !reported {
+                    warn!("Failed to check for calls: {e}");
+                    reported = true;
+                }
+                continue;
+            }
+            Err(_) => continue,
+        };
+
+        let mut params = params.lock().unwrap();
+        if !apps.is_empty() && !paused_for_call {
+            paused_for_call = true;
+            if !params.paused {
+                params.paused = true;
+                info!("{} is recording, pausing", apps.join(", "));
+            }
+        } else if apps.is_empty() && paused_for_call {
+            paused_for_call = false;
+            if params.paused {
+                params.paused = false;
+                info!("No other application is recording, resuming");
+            }
+        }
+    }
+}
+
+/// Switches low-power mode on while the machine runs on battery.
+async fn watch_power(params: SharedParams) {
+    let mut interval = tokio::time::interval(POWER_POLL);
+    loop {
+        interval.tick().await;
+        let on_battery = on_battery().await;
+        let mut params = params.lock().unwrap();
+        if params.low_power != on_battery {
+            params.low_power = on_battery;
+            if on_battery {
+                info!("On battery, throttling to {LOW_POWER_RATE} Hz");
+            } else {
+                info!("On AC power, back to the full rate");
+            }
+        }
+    }
+}
+
+/// Whether the machine has a mains supply and none of them is online.
+async fn on_battery() -> bool {
+    let Ok(mut supplies) = tokio::fs::read_dir(POWER_SUPPLIES).await else {
+        return false;
+    };
+    let mut has_mains = false;
+    while let Ok(Some(supply)) = supplies.next_entry().await {
+        let path = supply.path();
+        let kind = tokio::fs::read_to_string(path.join("type")).await;
+        if kind.is_ok_and(|kind| kind.trim() == "Mains") {
+            has_mains = true;
+            let online = tokio::fs::read_to_string(path.join("online")).await;
+            if online.is_ok_and(|online| online.trim() == "1") {
+                return false;
+            }
+        }
+    }
+    has_mains
+}
+
+/// When the file at `path` was last modified, `None` if it does not exist.
+async fn modified_time(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
@@ -0,0 +1,58 @@
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use backlight_mic::Levels;
+
+/// One line of `--emit json`.
+#[derive(Serialize)]
+struct Line {
+    /// Seconds since the Unix epoch.
+    timestamp: f64,
+    rms: f32,
+    normalized: f32,
+    brightness: f32,
+    beat: bool,
+}
+
+/// Prints every chunk's levels to stdout on its own thread, returning where to send them.
+///
+/// Printing stops once stdout is closed, e.g. when the reading program exits.
+pub fn spawn() -> Sender<Levels> {
+    let (levels_tx, levels_rx) = mpsc::channel();
+    thread::spawn(move || {
+        if let Err(e) = run(levels_rx) {
+            warn!("Stopped emitting levels: {e}");
+        }
+    });
+    levels_tx
+}
+
+fn run(levels: Receiver<Levels>) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    for levels in levels {
+        let line = Line {
+            timestamp: timestamp(),
+            rms: levels.rms,
+            normalized: levels.normalized,
+            brightness: levels.brightness,
+            beat: levels.beat,
+        };
+        serde_json::to_writer(&mut stdout, &line)?;
+        // One flush per line so readers see every chunk as it happens.
+        writeln!(stdout)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// Seconds since the Unix epoch, to timestamp levels as they arrive.
+pub fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
@@ -0,0 +1,123 @@
+//! Errors of the pipeline.
+
+use std::io;
+use std::time::Duration;
+
+/// Everything that can go wrong while setting up or running the pipeline.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The config file exists but could not be read or parsed.
+    #[error("failed to load the config: {0}")]
+    Config(#[source] io::Error),
+    /// The host has no input device at all, e.g. the microphone is unplugged.
+    #[error("no input device available")]
+    NoDevice,
+    /// The device's name could not be read, it was probably just unplugged.
+    #[error("failed to read the input device name: {0}")]
+    DeviceName(#[from] cpal::DeviceNameError),
+    /// The device's supported configs could not be queried.
+    #[error("failed to query the input device configs: {0}")]
+    Configs(#[from] cpal::SupportedStreamConfigsError),
+    /// The device exists but can't be opened, another application holds it exclusively.
+    #[error("{0} is busy, another application is probably using it exclusively")]
+    Busy(String),
+    /// None of the device's configs can capture at [`SAMPLE_RATE`](crate::SAMPLE_RATE).
+    #[error("the input device cannot capture at {} Hz", crate::SAMPLE_RATE)]
+    NoConfig,
+    /// The capture stream could not be created.
+    #[error("failed to build the input stream: {0}")]
+    BuildStream(#[from] cpal::BuildStreamError),
+    /// The capture stream could not be started.
+    #[error("failed to start the input stream: {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
+    /// The capture stream died, usually because the device went away.
+    #[error("the input stream failed: {0}")]
+    Stream(#[from] cpal::StreamError),
+    /// Raw PCM could not be read from stdin or another pipe.
+    #[error("failed to read piped audio: {0}")]
+    Stdin(#[source] io::Error),
+    /// The application given to `--app` could not be captured.
+    #[error("failed to capture the application: {0}")]
+    App(String),
+    /// The capture callback stopped running, e.g. a wedged driver or a suspended device.
+    #[error("no audio received for {}s, the input stopped responding", .0.as_secs())]
+    Stalled(Duration),
+    /// Writing the keyboard backlight failed, often a permissions issue.
+    #[error("failed to write the keyboard backlight: {0}")]
+    Backlight(#[source] io::Error),
+    /// The `--record` file could not be created.
+    #[error("failed to create the recording: {0}")]
+    Record(#[source] io::Error),
+    /// The timeline of `process` could not be written.
+    #[error("failed to write the timeline: {0}")]
+    Timeline(#[source] io::Error),
+    /// `calibrate` measured no usable range.
+    #[error("calibration failed: {0}")]
+    Calibration(String),
+    /// A mapping expression in the config doesn't parse.
+    #[error("invalid expression: {0}")]
+    Expression(String),
+    /// An effect could not be swapped into the running pipeline.
+    #[error("failed to swap the effect: {0}")]
+    Swap(String),
+    /// The config file could not be written.
+    #[error("failed to save the config: {0}")]
+    SaveConfig(#[source] io::Error),
+    /// The host has no output device to play the latency clicks on.
+    #[error("no output device available to play the clicks")]
+    NoOutput,
+    /// The latency clicks or an audio file could not be played.
+    #[error("failed to play audio: {0}")]
+    Playback(String),
+    /// None of the latency clicks were picked up by the input.
+    #[error(
+        "heard none of {} clicks, check that the speakers are on and audible to the input",
+        crate::latency::CLICKS
+    )]
+    NoClick,
+    /// A preset could not be found, read or written.
+    #[error("preset failed: {0}")]
+    Preset(#[source] io::Error),
+    /// The running instance could not be queried over its control socket.
+    #[error("failed to reach the running instance: {0}")]
+    Control(#[source] io::Error),
+    /// The running instance answered a request over its control socket with an error.
+    #[error("the running instance refused: {0}")]
+    Refused(String),
+    /// The file given to `replay` could not be read or is not a recording.
+    #[error("failed to read the recording: {0}")]
+    Replay(#[source] io::Error),
+    /// The udev rule of `setup-permissions` could not be installed.
+    #[error("failed to set up the backlight permissions: {0}")]
+    Permissions(#[source] io::Error),
+    /// Neither SCHED_FIFO nor RTKit granted realtime priority.
+    #[error("failed to get realtime priority: {0}")]
+    Realtime(#[source] io::Error),
+    /// The ALSA capture PCM could not be opened or read.
+    #[cfg(feature = "alsa")]
+    #[error("ALSA capture failed: {0}")]
+    Alsa(#[from] ::alsa::Error),
+    /// The file given to `run --file` could not be opened or decoded.
+    #[cfg(feature = "file")]
+    #[error("failed to decode the audio file: {0}")]
+    Decode(#[from] symphonia::core::errors::Error),
+    /// `map.lua` could not be loaded.
+    #[cfg(feature = "lua")]
+    #[error("failed to load the Lua mapping: {0}")]
+    Lua(#[from] mlua::Error),
+    /// `effect.rhai` could not be loaded.
+    #[cfg(feature = "rhai")]
+    #[error("failed to load the Rhai effect: {0}")]
+    Rhai(#[from] Box<rhai::EvalAltResult>),
+    /// A WASM plugin could not be loaded or failed.
+    #[cfg(feature = "wasm")]
+    #[error("WASM plugin failed: {0:#}")]
+    Plugin(#[from] wasmtime::Error),
+    /// The graphical window could not be run.
+    #[cfg(feature = "gui")]
+    #[error("failed to run the GUI: {0}")]
+    Gui(#[from] eframe::Error),
+}
+
+/// Result of the fallible pipeline operations.
+pub type Result<T> = std::result::Result<T, Error>;
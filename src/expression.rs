@@ -0,0 +1,545 @@
+//! Brightness mappings written inline in the config, like
+//! `clamp((rms_db + 50) / 50, 0, 1)^1.6 * max`.
+//!
+//! An expression has numbers, the [`Variables`], `+ - * /`, `^` (power, right-associative),
+//! comparisons `< <= > >= == !=` (1 when true, 0 otherwise), parentheses and the functions `min`,
+//! `max`, `clamp`, `abs`, `sqrt`, `exp`, `ln`, `log10`, `floor`, `ceil`, `round` and
+//! `if(condition, then, else)`. Besides the fields of [`Variables`], `rms_db` is the level in
+//! dBFS (-100 for silence) and `max` is full brightness, 100, which the maximum brightness scales
+//! when written. It is parsed once, when the config is loaded.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// The values an expression can read, given to [`Expression::eval`].
+pub struct Variables {
+    /// `rms`, the level driving the brightness.
+    pub rms: f32,
+    /// `normalized`, the level within the calibrated range (0.0-1.0), after the compressor.
+    pub normalized: f32,
+    /// `min_rms`, the noise floor.
+    pub min_rms: f32,
+    /// `max_rms`, the level mapped to full brightness.
+    pub max_rms: f32,
+    /// `boost`, the exponent of the built-in mapping.
+    pub boost: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Variable {
+    Rms,
+    RmsDb,
+    Normalized,
+    MinRms,
+    MaxRms,
+    Boost,
+    Max,
+}
+
+impl Variable {
+    fn named(name: &str) -> Option<Self> {
+        Some(match name {
+            "rms" => Self::Rms,
+            "rms_db" => Self::RmsDb,
+            "normalized" => Self::Normalized,
+            "min_rms" => Self::MinRms,
+            "max_rms" => Self::MaxRms,
+            "boost" => Self::Boost,
+            "max" => Self::Max,
+            _ => return None,
+        })
+    }
+
+    fn value(self, variables: &Variables) -> f32 {
+        match self {
+            Self::Rms => variables.rms,
+            Self::RmsDb => (20.0 * variables.rms.log10()).max(-100.0),
+            Self::Normalized => variables.normalized,
+            Self::MinRms => variables.min_rms,
+            Self::MaxRms => variables.max_rms,
+            Self::Boost => variables.boost,
+            Self::Max => 100.0,
+        }
+    }
+}
+
+/// A function of an expression.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Min,
+    Max,
+    Clamp,
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Floor,
+    Ceil,
+    Round,
+    If,
+}
+
+impl Function {
+    fn named(name: &str) -> Option<Self> {
+        Some(match name {
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "clamp" => Self::Clamp,
+            "abs" => Self::Abs,
+            "sqrt" => Self::Sqrt,
+            "exp" => Self::Exp,
+            "ln" => Self::Ln,
+            "log10" => Self::Log10,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            "round" => Self::Round,
+            "if" => Self::If,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Min | Self::Max => 2,
+            Self::Clamp | Self::If => 3,
+            _ => 1,
+        }
+    }
+
+    fn apply(self, args: &[f32]) -> f32 {
+        match (self, args) {
+            (Self::Min, [a, b]) => a.min(*b),
+            (Self::Max, [a, b]) => a.max(*b),
+            // Unlike `f32::clamp`, a reversed range doesn't panic.
+            (Self::Clamp, [x, low, high]) => x.max(*low).min(*high),
+            (Self::Abs, [x]) => x.abs(),
+            (Self::Sqrt, [x]) => x.sqrt(),
+            (Self::Exp, [x]) => x.exp(),
+            (Self::Ln, [x]) => x.ln(),
+            (Self::Log10, [x]) => x.log10(),
+            (Self::Floor, [x]) => x.floor(),
+            (Self::Ceil, [x]) => x.ceil(),
+            (Self::Round, [x]) => x.round(),
+            (Self::If, [condition, then, otherwise]) => {
+                if *condition != 0.0 {
+                    *then
+                } else {
+                    *otherwise
+                }
+            }
+            _ => unreachable!("the arity is checked when parsing"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Operator {
+    fn apply(self, a: f32, b: f32) -> f32 {
+        let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+        match self {
+            Self::Add => a + b,
+            Self::Subtract => a - b,
+            Self::Multiply => a * b,
+            Self::Divide => a / b,
+            Self::Power => a.powf(b),
+            Self::Less => truth(a < b),
+            Self::LessOrEqual => truth(a <= b),
+            Self::Greater => truth(a > b),
+            Self::GreaterOrEqual => truth(a >= b),
+            Self::Equal => truth(a == b),
+            Self::NotEqual => truth(a != b),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(f32),
+    Variable(Variable),
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Node {
+    fn eval(&self, variables: &Variables) -> f32 {
+        match self {
+            Self::Number(value) => *value,
+            Self::Variable(variable) => variable.value(variables),
+            Self::Negate(node) => -node.eval(variables),
+            Self::Binary(operator, a, b) => operator.apply(a.eval(variables), b.eval(variables)),
+            Self::Call(function, args) => {
+                let args: Vec<f32> = args.iter().map(|arg| arg.eval(variables)).collect();
+                function.apply(&args)
+            }
+        }
+    }
+}
+
+/// A parsed mapping expression, written as its source in the config.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    /// Parses `source`, failing with the column of the first mistake.
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            end: source.len() + 1,
+        };
+        let root = parser.comparison()?;
+        if let Some((column, token)) = parser.tokens.get(parser.position) {
+            return Err(invalid(*column, &format!("unexpected {token}")));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    /// The value of the expression, NaN when it is undefined, e.g. `sqrt(-1)`.
+    pub fn eval(&self, variables: &Variables) -> f32 {
+        self.root.eval(variables)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = Error;
+
+    fn try_from(source: String) -> Result<Self> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Expression> for String {
+    fn from(expression: Expression) -> Self {
+        expression.source
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Number(value) => write!(f, "number {value}"),
+            Self::Name(name) => write!(f, "`{name}`"),
+            Self::Symbol(symbol) => write!(f, "`{symbol}`"),
+        }
+    }
+}
+
+/// Symbols, the two-character ones first so `<=` isn't read as `<`.
+const SYMBOLS: [&str; 14] = [
+    "<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "^", "(", ")", ",",
+];
+
+/// Splits `source` into tokens, each with its column.
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        rest = &rest[start..];
+        let column = source.len() - rest.len() + 1;
+        let c = rest.chars().next().unwrap_or_default();
+        let (token, len) = if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let value = rest[..len]
+                .parse()
+                .map_err(|_| invalid(column, &format!("`{}` is not a number", &rest[..len])))?;
+            (Token::Number(value), len)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (Token::Name(rest[..len].to_string()), len)
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            (Token::Symbol(symbol), symbol.len())
+        } else {
+            return Err(invalid(column, &format!("unexpected `{c}`")));
+        };
+        tokens.push((column, token));
+        rest = &rest[len..];
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser, one method per precedence level, loosest first.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    /// Column just after the source.
+    end: usize,
+}
+
+impl Parser {
+    fn comparison(&mut self) -> Result<Node> {
+        let a = self.sum()?;
+        let operator = match self.peek_symbol() {
+            Some("<") => Operator::Less,
+            Some("<=") => Operator::LessOrEqual,
+            Some(">") => Operator::Greater,
+            Some(">=") => Operator::GreaterOrEqual,
+            Some("==") => Operator::Equal,
+            Some("!=") => Operator::NotEqual,
+            _ => return Ok(a),
+        };
+        self.position += 1;
+        let b = self.sum()?;
+        Ok(Node::Binary(operator, Box::new(a), Box::new(b)))
+    }
+
+    fn sum(&mut self) -> Result<Node> {
+        let mut a = self.product()?;
+        loop {
+            let operator = match self.peek_symbol() {
+                Some("+") => Operator::Add,
+                Some("-") => Operator::Subtract,
+                _ => return Ok(a),
+            };
+            self.position += 1;
+            a = Node::Binary(operator, Box::new(a), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Node> {
+        let mut a = self.unary()?;
+        loop {
+            let operator = match self.peek_symbol() {
+                Some("*") => Operator::Multiply,
+                Some("/") => Operator::Divide,
+                _ => return Ok(a),
+            };
+            self.position += 1;
+            a = Node::Binary(operator, Box::new(a), Box::new(self.unary()?));
+        }
+    }
+
+    /// A negation binds looser than a power, `-x^2` is `-(x^2)`.
+    fn unary(&mut self) -> Result<Node> {
+        if self.peek_symbol() == Some("-") {
+            self.position += 1;
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Node> {
+        let base = self.atom()?;
+        if self.peek_symbol() != Some("^") {
+            return Ok(base);
+        }
+        self.position += 1;
+        // Right-associative, and the exponent may be negative.
+        let exponent = self.unary()?;
+        Ok(Node::Binary(
+            Operator::Power,
+            Box::new(base),
+            Box::new(exponent),
+        ))
+    }
+
+    fn atom(&mut self) -> Result<Node> {
+        let Some((column, token)) = self.tokens.get(self.position).cloned() else {
+            return Err(invalid(self.end, "unexpected end"));
+        };
+        self.position += 1;
+        match token {
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::Symbol("(") => {
+                let node = self.comparison()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            // `max` is both a variable and a function, the parenthesis tells them apart.
+            Token::Name(name) if self.peek_symbol() == Some("(") => {
+                let function = Function::named(&name)
+                    .ok_or_else(|| invalid(column, &format!("unknown function `{name}`")))?;
+                self.position += 1;
+                let mut args = vec![self.comparison()?];
+                while self.peek_symbol() == Some(",") {
+                    self.position += 1;
+                    args.push(self.comparison()?);
+                }
+                self.expect(")")?;
+                if args.len() != function.arity() {
+                    return Err(invalid(
+                        column,
+                        &format!(
+                            "`{name}` takes {} arguments, not {}",
+                            function.arity(),
+                            args.len()
+                        ),
+                    ));
+                }
+                Ok(Node::Call(function, args))
+            }
+            Token::Name(name) => Variable::named(&name)
+                .map(Node::Variable)
+                .ok_or_else(|| invalid(column, &format!("unknown variable `{name}`"))),
+            token => Err(invalid(column, &format!("unexpected {token}"))),
+        }
+    }
+
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some((_, Token::Symbol(symbol))) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        match self.tokens.get(self.position) {
+            Some((_, Token::Symbol(found))) if *found == symbol => {
+                self.position += 1;
+                Ok(())
+            }
+            Some((column, token)) => Err(invalid(
+                *column,
+                &format!("expected `{symbol}`, found {token}"),
+            )),
+            None => Err(invalid(self.end, &format!("expected `{symbol}`"))),
+        }
+    }
+}
+
+fn invalid(column: usize, message: &str) -> Error {
+    Error::Expression(format!("{message} at column {column}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARIABLES: Variables = Variables {
+        rms: 0.1,
+        normalized: 0.5,
+        min_rms: 0.01,
+        max_rms: 0.3,
+        boost: 2.0,
+    };
+
+    fn eval(source: &str) -> f32 {
+        Expression::parse(source).unwrap().eval(&VARIABLES)
+    }
+
+    /// The message `source` fails to parse with.
+    fn error(source: &str) -> String {
+        match Expression::parse(source) {
+            Err(Error::Expression(message)) => message,
+            Err(e) => panic!("`{source}` failed with {e}"),
+            Ok(_) => panic!("`{source}` parsed"),
+        }
+    }
+
+    #[test]
+    fn negation_binds_looser_than_power() {
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("(-2)^2"), 4.0);
+        assert_eq!(eval("2^-1"), 0.5);
+    }
+
+    #[test]
+    fn subtraction_is_left_associative() {
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("normalized - boost - max_rms"), 0.5 - 2.0 - 0.3);
+        assert_eq!(eval("8 / 4 / 2"), 1.0);
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(eval("2^3^2"), 512.0);
+        assert_eq!(eval("(2^3)^2"), 64.0);
+    }
+
+    #[test]
+    fn products_bind_tighter_than_sums_and_comparisons() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("1 + 2 * 3 == 7"), 1.0);
+        assert_eq!(eval("if(rms < min_rms, 0, max)"), 100.0);
+    }
+
+    #[test]
+    fn request_example() {
+        let rms_db = 20.0 * VARIABLES.rms.log10();
+        let expected = ((rms_db + 50.0) / 50.0).clamp(0.0, 1.0).powf(1.6) * 100.0;
+        let value = eval("clamp((rms_db + 50)/50, 0, 1)^1.6 * max");
+        assert!((value - expected).abs() < 1e-4, "{value} != {expected}");
+    }
+
+    #[test]
+    fn wrong_arity() {
+        assert_eq!(
+            error("clamp(rms, 0)"),
+            "`clamp` takes 3 arguments, not 2 at column 1"
+        );
+        assert_eq!(
+            error("2 * sqrt(rms, 1)"),
+            "`sqrt` takes 1 arguments, not 2 at column 5"
+        );
+    }
+
+    #[test]
+    fn unknown_identifiers() {
+        assert_eq!(error("volume * 2"), "unknown variable `volume` at column 1");
+        assert_eq!(
+            error("1 + pow(rms, 2)"),
+            "unknown function `pow` at column 5"
+        );
+    }
+
+    #[test]
+    fn trailing_input() {
+        assert_eq!(error("rms 2"), "unexpected number 2 at column 5");
+        assert_eq!(error("rms + 1)"), "unexpected `)` at column 8");
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        assert_eq!(error("(rms + 1"), "expected `)` at column 9");
+        assert_eq!(error("min(rms, 1"), "expected `)` at column 11");
+        assert_eq!(error("rms * ()"), "unexpected `)` at column 8");
+    }
+
+    #[test]
+    fn empty_and_dangling_operators() {
+        assert_eq!(error(""), "unexpected end at column 1");
+        assert_eq!(error("rms +"), "unexpected end at column 6");
+    }
+}
@@ -0,0 +1,286 @@
+//! Driving the pipeline from an audio file, optionally playing it in sync.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rtrb::{Consumer, Producer, RingBuffer};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions};
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::error::{Error, Result};
+use crate::source::AudioSource;
+
+/// Audio queued for playback, ahead of the speakers by at most this much.
+const PLAYBACK_BUFFER: Duration = Duration::from_millis(50);
+/// How long `read` waits for the playback to make room.
+const PLAYBACK_POLL: Duration = Duration::from_millis(5);
+
+/// How fast a [`FileSource`] is read.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Pace {
+    /// Played through the default output, in sync with the speakers.
+    Play,
+    /// Following the wall clock, silently.
+    Clock,
+    /// As fast as it decodes, e.g. to process a file offline.
+    Unpaced,
+}
+
+/// Decodes an audio file at its own [`Pace`].
+pub struct FileSource {
+    name: String,
+    sample_rate: u32,
+    channels: usize,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track: u32,
+    pace: Pace,
+    /// Samples waiting for the speakers, once the playback started.
+    player: Option<Player>,
+    /// When the first samples were delivered, and how many frames since, to pace a muted file.
+    clock: Option<(Instant, u64)>,
+}
+
+impl FileSource {
+    /// Opens `path`, to be read at `pace`.
+    pub fn open(path: &Path, pace: Pace) -> Result<Self> {
+        let file = File::open(path).map_err(|e| Error::Decode(DecodeError::IoError(e)))?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+            hint.with_extension(extension);
+        }
+        let format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )?
+            .format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(Error::Decode(DecodeError::Unsupported("no audio track")))?;
+        let Some(sample_rate) = track.codec_params.sample_rate else {
+            return Err(Error::Decode(DecodeError::Unsupported(
+                "unknown sample rate",
+            )));
+        };
+        let channels = track
+            .codec_params
+            .channels
+            .map_or(1, |channels| channels.count());
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+        let track = track.id;
+
+        info!("Decoding {} at {sample_rate} Hz", path.display());
+        Ok(Self {
+            name: path.display().to_string(),
+            sample_rate,
+            channels,
+            format,
+            decoder,
+            track,
+            pace,
+            player: None,
+            clock: None,
+        })
+    }
+
+    /// Decodes the next packet of the track as interleaved samples, `None` at the end.
+    fn next_packet(&mut self) -> Result<Option<Vec<f32>>> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(DecodeError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track {
+                continue;
+            }
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let mut samples =
+                        SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                    samples.copy_interleaved_ref(decoded);
+                    return Ok(Some(samples.samples().to_vec()));
+                }
+                // A corrupt packet is skipped, like players do.
+                Err(DecodeError::DecodeError(e)) => debug!("Skipping a corrupt packet: {e}"),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Waits until the interleaved `samples` are due, queuing them for the speakers if playing.
+    fn pace(&mut self, samples: &[f32]) -> Result<()> {
+        if self.pace == Pace::Unpaced {
+            return Ok(());
+        }
+        if self.pace == Pace::Play && self.player.is_none() {
+            self.player = Some(Player::start(self.sample_rate, self.channels)?);
+        }
+        if let Some(player) = &mut self.player {
+            player.queue(samples);
+            return Ok(());
+        }
+
+        let (started, frames) = self.clock.get_or_insert((Instant::now(), 0));
+        let due = Duration::from_secs_f64(*frames as f64 / self.sample_rate as f64);
+        thread::sleep(due.saturating_sub(started.elapsed()));
+        *frames += (samples.len() / self.channels) as u64;
+        Ok(())
+    }
+}
+
+impl AudioSource for FileSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        let Some(samples) = self.next_packet()? else {
+            if let Some(player) = &self.player {
+                player.drain();
+            }
+            return Ok(false);
+        };
+        self.pace(&samples)?;
+        for frame in samples.chunks_exact(self.channels) {
+            buf.push(frame.iter().sum::<f32>() / self.channels as f32);
+        }
+        Ok(true)
+    }
+}
+
+/// Plays interleaved samples through the default output from a small ring buffer.
+///
+/// The stream lives on its own thread, it can't be moved between threads like the source.
+struct Player {
+    samples: Producer<f32>,
+    /// Ends the stream's thread once dropped.
+    _alive: Arc<()>,
+}
+
+impl Player {
+    fn start(sample_rate: u32, channels: usize) -> Result<Self> {
+        let capacity = (sample_rate as f32 * PLAYBACK_BUFFER.as_secs_f32()) as usize * channels;
+        let (producer, consumer) = RingBuffer::<f32>::new(capacity);
+        let alive = Arc::new(());
+        let stream_alive = Arc::clone(&alive);
+        let (started_tx, started_rx) = mpsc::channel();
+
+        thread::spawn(move || match open_output(sample_rate, channels, consumer) {
+            Ok(_stream) => {
+                let _ = started_tx.send(Ok(()));
+                while Arc::strong_count(&stream_alive) > 1 {
+                    thread::sleep(PLAYBACK_POLL);
+                }
+            }
+            Err(e) => {
+                let _ = started_tx.send(Err(e));
+            }
+        });
+
+        started_rx
+            .recv()
+            .map_err(|_| Error::Playback("the playback thread died".to_string()))??;
+        Ok(Self {
+            samples: producer,
+            _alive: alive,
+        })
+    }
+
+    /// Queues `samples`, waiting for the speakers to make room.
+    fn queue(&mut self, mut samples: &[f32]) {
+        while !samples.is_empty() {
+            let (_, rest) = self.samples.push_partial_slice(samples);
+            samples = rest;
+            if !samples.is_empty() {
+                thread::sleep(PLAYBACK_POLL);
+            }
+        }
+    }
+
+    /// Waits until everything queued was played.
+    fn drain(&self) {
+        while self.samples.slots() < self.samples.buffer().capacity() {
+            thread::sleep(PLAYBACK_POLL);
+        }
+    }
+}
+
+/// Starts playing `samples`, `channels` interleaved at `sample_rate`, on the default output.
+fn open_output(
+    sample_rate: u32,
+    channels: usize,
+    mut samples: Consumer<f32>,
+) -> Result<cpal::Stream> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or(Error::NoOutput)?;
+    let config = device
+        .supported_output_configs()
+        .map_err(playback)?
+        .find(|range| {
+            range.sample_format() == cpal::SampleFormat::F32
+                && (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&sample_rate)
+        })
+        .ok_or_else(|| Error::Playback(format!("the output cannot play f32 at {sample_rate} Hz")))?
+        .with_sample_rate(cpal::SampleRate(sample_rate))
+        .config();
+
+    let outputs = config.channels as usize;
+    let mut frame = vec![0.0; channels];
+    let stream = device
+        .build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                for out in data.chunks_mut(outputs) {
+                    // Silence while the file is being decoded.
+                    match samples.read_chunk(channels) {
+                        Ok(chunk) => {
+                            for (slot, sample) in frame.iter_mut().zip(chunk) {
+                                *slot = sample;
+                            }
+                        }
+                        Err(_) => frame.fill(0.0),
+                    }
+                    // Extra output channels repeat the file's last one.
+                    for (i, sample) in out.iter_mut().enumerate() {
+                        *sample = frame[i.min(channels - 1)];
+                    }
+                }
+            },
+            |err| error!("File playback failed: {err}"),
+            None,
+        )
+        .map_err(playback)?;
+    stream.play().map_err(playback)?;
+    Ok(stream)
+}
+
+fn playback(e: impl std::fmt::Display) -> Error {
+    Error::Playback(e.to_string())
+}
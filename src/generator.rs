@@ -0,0 +1,148 @@
+//! Synthetic test signals, for demos and for exercising the pipeline without a microphone.
+
+use std::f32::consts::TAU;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::SAMPLE_RATE;
+use crate::error::Result;
+use crate::source::AudioSource;
+
+/// Samples produced per `read`, 10 ms.
+const BLOCK: usize = SAMPLE_RATE as usize / 100;
+/// How long a sweep takes to go from `SWEEP_RANGE.0` to `SWEEP_RANGE.1`.
+const SWEEP_DURATION: Duration = Duration::from_secs(10);
+/// Lowest and highest frequency of a sweep, in Hz.
+const SWEEP_RANGE: (f32, f32) = (20.0, 20_000.0);
+/// Length of a burst, and the time from one to the next.
+const BURST: (Duration, Duration) = (Duration::from_millis(100), Duration::from_millis(500));
+/// Frequency of the bursts' tone, in Hz.
+const BURST_FREQUENCY: f32 = 1000.0;
+/// Seed of the noise, the same noise every run.
+const SEED: u32 = 0x2545_f491;
+
+/// What a [`Generator`] produces.
+#[derive(Clone, Copy)]
+pub enum Signal {
+    /// A sine sweeping logarithmically through the audible range, again and again.
+    Sweep,
+    /// Short tone bursts twice a second, like a steady beat.
+    Bursts,
+    /// White noise, equal energy per frequency.
+    WhiteNoise,
+    /// Pink noise, equal energy per octave like most music.
+    PinkNoise,
+    /// Nothing at all.
+    Silence,
+}
+
+/// Produces a [`Signal`] forever, the same samples every run.
+pub struct Generator {
+    signal: Signal,
+    /// Whether `read` waits for its samples to be due, like a live input.
+    realtime: bool,
+    /// Number of samples produced so far.
+    position: u64,
+    /// Phase of the sweep, in cycles.
+    phase: f32,
+    /// State of the noise's xorshift generator.
+    noise: u32,
+    /// State of the pink noise filter.
+    pink: [f32; 7],
+    started: Option<Instant>,
+}
+
+impl Generator {
+    /// Produces `signal` at [`SAMPLE_RATE`], paced like a live input if `realtime` or as fast as
+    /// it is read otherwise, e.g. in tests.
+    pub fn new(signal: Signal, realtime: bool) -> Self {
+        Self {
+            signal,
+            realtime,
+            position: 0,
+            phase: 0.0,
+            noise: SEED,
+            pink: [0.0; 7],
+            started: None,
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        // Time within the current sweep or burst, exact however long it runs.
+        let since = |period: Duration| {
+            let samples = (period.as_secs_f64() * SAMPLE_RATE as f64) as u64;
+            (self.position % samples) as f32 / SAMPLE_RATE as f32
+        };
+        let sample = match self.signal {
+            Signal::Sweep => {
+                let (low, high) = SWEEP_RANGE;
+                let progress = since(SWEEP_DURATION) / SWEEP_DURATION.as_secs_f32();
+                let frequency = low * (high / low).powf(progress);
+                self.phase = (self.phase + frequency / SAMPLE_RATE as f32).fract();
+                0.5 * (TAU * self.phase).sin()
+            }
+            Signal::Bursts => {
+                let (length, period) = BURST;
+                let t = since(period);
+                if t < length.as_secs_f32() {
+                    0.8 * (TAU * BURST_FREQUENCY * t).sin()
+                } else {
+                    0.0
+                }
+            }
+            Signal::WhiteNoise => 0.3 * self.white(),
+            Signal::PinkNoise => {
+                // Paul Kellet's filter, accurate to ±0.05 dB above 9 Hz.
+                let white = self.white();
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.016898;
+                let pink = b.iter().sum::<f32>() + white * 0.5362;
+                b[6] = white * 0.115926;
+                0.05 * pink
+            }
+            Signal::Silence => 0.0,
+        };
+        self.position += 1;
+        sample
+    }
+
+    /// Uniform noise in the -1.0-1.0 range.
+    fn white(&mut self) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+impl AudioSource for Generator {
+    fn name(&self) -> String {
+        let signal = match self.signal {
+            Signal::Sweep => "sweep",
+            Signal::Bursts => "bursts",
+            Signal::WhiteNoise => "white noise",
+            Signal::PinkNoise => "pink noise",
+            Signal::Silence => "silence",
+        };
+        format!("generator ({signal})")
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn read(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        if self.realtime {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            let due = Duration::from_secs_f64(self.position as f64 / SAMPLE_RATE as f64);
+            thread::sleep(due.saturating_sub(started.elapsed()));
+        }
+        buf.extend((0..BLOCK).map(|_| self.next_sample()));
+        Ok(true)
+    }
+}
@@ -0,0 +1,98 @@
+//! Hands-free control picked out of the audio, e.g. clapping twice to pause.
+
+use crate::config::{Clap, Whistle};
+
+/// Fraction of its peak a clap must fall back to within a chunk, sustained sounds don't.
+const CLAP_DECAY: f32 = 0.5;
+/// Lowest normalized level of a clap, so a noise floor spiking above its average isn't one.
+const CLAP_MIN_LEVEL: f32 = 0.2;
+
+/// Share of a chunk's energy around its strongest frequency for it to be whistled, see
+/// [`Spectrum::peak`](crate::spectrum::Spectrum::peak).
+const WHISTLE_PURITY: f32 = 0.6;
+/// How long a whistle must be held before it takes control, so a stray tone doesn't.
+const WHISTLE_HOLD: f32 = 0.4;
+
+/// Recognizes two claps in a row, sharp transients a moment apart.
+#[derive(Default)]
+pub struct DoubleClap {
+    /// Time processed so far, in seconds.
+    clock: f32,
+    /// Level of the transient in the previous chunk, not yet known to be a clap.
+    onset: Option<f32>,
+    /// When the first clap of a pair was heard.
+    first: Option<f32>,
+}
+
+impl DoubleClap {
+    /// Records a chunk of `chunk_secs` whose level is `rms` and `normalized` once in the
+    /// calibrated range, `average` being the recent average level. Returns whether it completed a
+    /// double clap.
+    pub fn update(
+        &mut self,
+        rms: f32,
+        normalized: f32,
+        average: f32,
+        chunk_secs: f32,
+        clap: &Clap,
+    ) -> bool {
+        self.clock += chunk_secs;
+        if let Some(peak) = self.onset {
+            // Still loud a chunk later, speech or music rather than a clap.
+            if rms > peak * CLAP_DECAY {
+                self.onset = Some(peak.max(rms));
+                return false;
+            }
+            self.onset = None;
+            return self.clap(clap);
+        }
+
+        if normalized >= CLAP_MIN_LEVEL && rms > average * clap.factor {
+            self.onset = Some(rms);
+        }
+        false
+    }
+
+    /// Counts one clap, returning whether it was the second of a pair.
+    fn clap(&mut self, clap: &Clap) -> bool {
+        let window = clap.window_ms as f32 / 1000.0;
+        match self.first.take() {
+            Some(first) if self.clock - first <= window => true,
+            _ => {
+                self.first = Some(self.clock);
+                false
+            }
+        }
+    }
+}
+
+/// Recognizes a sustained whistle and follows its pitch.
+#[derive(Default)]
+pub struct WhistleControl {
+    /// How long the current whistle has been held, in seconds.
+    held: f32,
+}
+
+impl WhistleControl {
+    /// Records a chunk of `chunk_secs` whose strongest frequency is `frequency` in Hz, with a
+    /// `purity` share of the energy around it. Returns where the pitch lies within the range of
+    /// `whistle`, 0.0-1.0 on a musical scale, once it was held long enough.
+    pub fn update(
+        &mut self,
+        frequency: f32,
+        purity: f32,
+        chunk_secs: f32,
+        whistle: &Whistle,
+    ) -> Option<f32> {
+        let (low, high) = (whistle.low_hz, whistle.high_hz);
+        if purity < WHISTLE_PURITY || !(low..=high).contains(&frequency) {
+            self.held = 0.0;
+            return None;
+        }
+        self.held += chunk_secs;
+        if self.held < WHISTLE_HOLD {
+            return None;
+        }
+        Some(((frequency / low).ln() / (high / low).ln()).clamp(0.0, 1.0))
+    }
+}
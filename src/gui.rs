@@ -0,0 +1,199 @@
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use eframe::egui::{self, Color32};
+use egui_plot::{Bar, BarChart, Line, Plot, PlotPoints, VLine};
+
+use backlight_mic::source::{stall_count, unavailable};
+use backlight_mic::{Levels, Params, SharedParams, brightness_for};
+
+/// How often the window is redrawn while audio keeps coming in.
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// Parameters displayed alongside the live levels.
+pub struct Settings {
+    pub device: String,
+    /// The live mapping parameters, edited by the sliders and the curve editor.
+    pub params: SharedParams,
+}
+
+/// Opens the window and shows the levels until it is closed.
+pub fn run(levels: Receiver<Levels>, settings: Settings) -> eframe::Result {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title("DynKBacklight")
+            .with_inner_size([900.0, 600.0]),
+        ..Default::default()
+    };
+
+    eframe::run_native(
+        "DynKBacklight",
+        options,
+        Box::new(|_| {
+            Ok(Box::new(App {
+                levels,
+                settings,
+                current: Levels::default(),
+            }))
+        }),
+    )
+}
+
+struct App {
+    levels: Receiver<Levels>,
+    settings: Settings,
+    current: Levels,
+}
+
+impl eframe::App for App {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        if let Some(latest) = self.levels.try_iter().last() {
+            self.current = latest;
+        }
+
+        let mut params = self.settings.params.lock().unwrap();
+
+        egui::Panel::left("controls")
+            .resizable(false)
+            .show(ui, |ui| {
+                ui.heading("Settings");
+                ui.label(format!("Device: {}", self.settings.device));
+                if let Some(reason) = unavailable() {
+                    ui.colored_label(egui::Color32::YELLOW, format!("Unavailable: {reason}"));
+                }
+                ui.separator();
+                controls(ui, &mut params);
+            });
+
+        egui::CentralPanel::default().show(ui, |ui| {
+            let height = (ui.available_height() / 3.0 - 24.0).max(60.0);
+
+            ui.label(format!(
+                "rms {:.3} / threshold {:.3}, brightness {:.0}%, {} stalls, {:.1} chunks/s, \
+                 {:.1} writes/s",
+                self.current.rms,
+                self.current.threshold,
+                self.current.brightness,
+                stall_count(),
+                self.current.chunk_rate,
+                self.current.write_rate
+            ));
+            let health = self.current.health;
+            ui.label(format!(
+                "jitter {:.1} ms, buffer {:.0}%, {} samples dropped, write {:.1} ms",
+                health.stream.jitter.as_secs_f32() * 1000.0,
+                health.stream.occupancy * 100.0,
+                health.stream.dropped,
+                health.write_latency.as_secs_f32() * 1000.0
+            ));
+            ui.add(egui::ProgressBar::new(self.current.brightness / 100.0));
+
+            ui.label("Waveform");
+            waveform(ui, height, &self.current.waveform);
+            ui.label("Spectrum");
+            spectrum(ui, height, &self.current.bands);
+            ui.label("Mapping curve (drag to move the RMS range)");
+            curve_editor(ui, height, &mut params, self.current.rms);
+        });
+
+        ui.ctx().request_repaint_after(FRAME_TIME);
+    }
+}
+
+/// Sliders for every live parameter.
+fn controls(ui: &mut egui::Ui, params: &mut Params) {
+    let max_rms = params.max_rms;
+    ui.add(egui::Slider::new(&mut params.min_rms, 0.0..=max_rms).text("Noise floor"));
+    let min_rms = params.min_rms;
+    ui.add(egui::Slider::new(&mut params.max_rms, min_rms..=1.0).text("Peak"));
+    ui.add(egui::Slider::new(&mut params.boost, 0.1..=5.0).text("Boost"));
+    ui.add(egui::Slider::new(&mut params.threshold_factor, 0.5..=5.0).text("Threshold"));
+    ui.add(
+        egui::Slider::new(&mut params.attack, 1.0..=10_000.0)
+            .logarithmic(true)
+            .text("Attack %/s"),
+    );
+    ui.add(
+        egui::Slider::new(&mut params.release, 1.0..=10_000.0)
+            .logarithmic(true)
+            .text("Release %/s"),
+    );
+    ui.checkbox(&mut params.paused, "Pause");
+    ui.checkbox(&mut params.low_power, "Low power");
+}
+
+fn waveform(ui: &mut egui::Ui, height: f32, samples: &[f32]) {
+    let points: PlotPoints = samples
+        .iter()
+        .enumerate()
+        .map(|(i, &sample)| [i as f64, sample as f64])
+        .collect();
+
+    Plot::new("waveform")
+        .height(height)
+        .include_y(-1.0)
+        .include_y(1.0)
+        .show_axes([false, true])
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show(ui, |plot_ui| plot_ui.line(Line::new("samples", points)));
+}
+
+fn spectrum(ui: &mut egui::Ui, height: f32, bands: &[f32]) {
+    let bars = bands
+        .iter()
+        .enumerate()
+        .map(|(i, &band)| Bar::new(i as f64, band as f64))
+        .collect();
+
+    Plot::new("spectrum")
+        .height(height)
+        .include_y(0.0)
+        .include_y(1.0)
+        .show_axes([false, true])
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show(ui, |plot_ui| {
+            plot_ui.bar_chart(BarChart::new("bands", bars).color(Color32::LIGHT_BLUE))
+        });
+}
+
+/// Plots brightness against RMS; dragging moves whichever end of the RMS range is closer.
+fn curve_editor(ui: &mut egui::Ui, height: f32, params: &mut Params, rms: f32) {
+    let max_x = (params.max_rms * 1.2).max(0.001) as f64;
+    let points: PlotPoints = (0..=200)
+        .map(|i| {
+            let x = max_x * i as f64 / 200.0;
+            [x, brightness_for(x as f32, params) as f64]
+        })
+        .collect();
+
+    Plot::new("curve")
+        .height(height)
+        .include_x(0.0)
+        .include_x(max_x)
+        .include_y(0.0)
+        .include_y(100.0)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new("brightness", points).color(Color32::YELLOW));
+            plot_ui.vline(VLine::new("noise floor", params.min_rms).color(Color32::GRAY));
+            plot_ui.vline(VLine::new("peak", params.max_rms).color(Color32::GRAY));
+            plot_ui.vline(VLine::new("rms", rms).color(Color32::RED));
+
+            if plot_ui.response().dragged()
+                && let Some(pointer) = plot_ui.pointer_coordinate()
+            {
+                let x = pointer.x.max(0.0) as f32;
+                if (x - params.min_rms).abs() < (x - params.max_rms).abs() {
+                    params.min_rms = x.min(params.max_rms - 0.001);
+                } else {
+                    params.max_rms = x.max(params.min_rms + 0.001);
+                }
+            }
+        });
+}
@@ -0,0 +1,86 @@
+//! Writing the backlight through a privileged helper, so the main process never runs as root.
+//!
+//! The helper is this same binary started as `pkexec backlight_mic helper`; it reads one
+//! brightness percent per line on stdin and writes it to the backlight, nothing else.
+
+use std::io::{self, BufRead, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{Error, Result};
+use crate::sink::Sysfs;
+
+/// Whether the backlight is written through the helper, see [`set_enabled`].
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// The running helper and its stdin, started on the first write.
+static HELPER: Mutex<Option<(Child, ChildStdin)>> = Mutex::new(None);
+
+/// Writes the backlight through the helper (`true`), or directly (`false`).
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the backlight is written through the helper.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Sends `level` to the helper, starting it if needed and once more if it died.
+pub fn write(level: u8) -> io::Result<()> {
+    let mut helper = HELPER.lock().unwrap();
+    for attempt in 0..2 {
+        if helper.is_none() {
+            *helper = Some(start()?);
+        }
+        let Some((_, stdin)) = helper.as_mut() else {
+            continue;
+        };
+        match writeln!(stdin, "{level}") {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt == 0 && e.kind() == io::ErrorKind::BrokenPipe => {
+                warn!("The backlight helper exited, restarting it");
+                if let Some((mut child, _)) = helper.take() {
+                    let _ = child.wait();
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::other("the backlight helper keeps exiting"))
+}
+
+/// Starts `pkexec <this binary> helper`.
+fn start() -> io::Result<(Child, ChildStdin)> {
+    let exe = std::env::current_exe()?;
+    info!("Starting the backlight helper through pkexec");
+    let mut child = Command::new("pkexec")
+        .arg(exe)
+        .arg("helper")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| io::Error::other("the backlight helper has no stdin"))?;
+    Ok((child, stdin))
+}
+
+/// Runs the helper side: writes every brightness percent read on stdin until it is closed.
+///
+/// Anything but a number is refused, the unprivileged side can't make it write elsewhere.
+pub fn serve() -> Result<()> {
+    for line in io::stdin().lock().lines() {
+        let line = line.map_err(Error::Backlight)?;
+        match line.trim().parse::<u8>() {
+            Ok(level) => {
+                if let Err(e) = Sysfs::new().write(level.min(100) as f32) {
+                    eprintln!("{e}");
+                }
+            }
+            Err(_) => eprintln!("Ignoring `{}`, expected a brightness percent", line.trim()),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use backlight_mic::Levels;
+use backlight_mic::control::Handle;
+
+/// The web UI served on `/`.
+const INDEX: &str = include_str!("http/index.html");
+
+/// Frames queued per WebSocket client, more are dropped until it catches up.
+const CLIENT_QUEUE: usize = 8;
+
+/// The senders of the connected WebSocket clients' frames.
+type Clients = Arc<Mutex<Vec<SyncSender<String>>>>;
+
+/// One chunk's levels, as streamed on `/api/levels`.
+#[derive(Serialize)]
+struct Frame<'a> {
+    rms: f32,
+    threshold: f32,
+    normalized: f32,
+    brightness: f32,
+    beat: bool,
+    bands: &'a [f32],
+}
+
+/// The live parameters, as read and written by `/api/params`.
+///
+/// In updates every field is optional, the missing ones are left alone.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ParamsBody {
+    min_rms: Option<f32>,
+    max_rms: Option<f32>,
+    boost: Option<f32>,
+    threshold_factor: Option<f32>,
+    attack: Option<f32>,
+    release: Option<f32>,
+    paused: Option<bool>,
+    off: Option<bool>,
+    low_power: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ProfileBody {
+    name: String,
+}
+
+/// Serves the HTTP API on `address` from its own thread and returns where to send the levels
+/// streamed to WebSocket clients.
+///
+/// Failing to listen is logged, it never brings down the rest of the program.
+pub fn spawn(address: String, control: Handle) -> Sender<Levels> {
+    let (levels_tx, levels_rx) = mpsc::channel();
+    let server = match Server::http(&address) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to serve HTTP on {address}: {e}");
+            return levels_tx;
+        }
+    };
+    info!("Serving the HTTP API on http://{address}");
+
+    let clients = Clients::default();
+    let broadcast_clients = Arc::clone(&clients);
+    thread::spawn(move || broadcast(levels_rx, broadcast_clients));
+
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            if request.url() == "/api/levels" {
+                stream_levels(request, &clients);
+                continue;
+            }
+            if request.url() == "/" {
+                let html = Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+                let _ = request.respond(Response::from_string(INDEX).with_header(html));
+                continue;
+            }
+
+            let (status, body) = route(&mut request, &control);
+            let json = Header::from_bytes("Content-Type", "application/json").unwrap();
+            let response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(json);
+            if let Err(e) = request.respond(response) {
+                debug!("Failed to answer an HTTP request: {e}");
+            }
+        }
+    });
+    levels_tx
+}
+
+/// Sends every chunk's levels to the connected clients.
+fn broadcast(levels: Receiver<Levels>, clients: Clients) {
+    for levels in levels {
+        let mut clients = clients.lock().unwrap();
+        if clients.is_empty() {
+            continue;
+        }
+        let frame = serde_json::to_string(&Frame {
+            rms: levels.rms,
+            threshold: levels.threshold,
+            normalized: levels.normalized,
+            brightness: levels.brightness,
+            beat: levels.beat,
+            bands: &levels.bands,
+        })
+        .unwrap_or_default();
+        // Slow clients skip frames, the ones that went away are dropped.
+        clients.retain(|client| match client.try_send(frame.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+/// Upgrades `request` to a WebSocket and streams the levels to it from its own thread.
+fn stream_levels(request: Request, clients: &Clients) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| header.value.as_bytes().to_vec());
+    let Some(key) = key else {
+        let body = error(400, "expected a WebSocket upgrade").1;
+        let _ = request.respond(Response::from_string(body).with_status_code(400));
+        return;
+    };
+
+    let accept = tungstenite::handshake::derive_accept_key(&key);
+    let response = Response::empty(101)
+        .with_header(Header::from_bytes("Sec-WebSocket-Accept", accept).unwrap());
+    let stream = request.upgrade("websocket", response);
+
+    let (frames_tx, frames_rx) = mpsc::sync_channel(CLIENT_QUEUE);
+    clients.lock().unwrap().push(frames_tx);
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        for frame in frames_rx {
+            if let Err(e) = socket.send(Message::text(frame)) {
+                debug!("WebSocket client went away: {e}");
+                return;
+            }
+        }
+    });
+}
+
+/// Handles one request, returning its status code and JSON body.
+fn route(request: &mut Request, control: &Handle) -> (u16, String) {
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return error(400, e);
+    }
+
+    match (request.method(), request.url()) {
+        (Method::Get, "/api/status") => json(&control.status()),
+        (Method::Get, "/api/params") => {
+            let params = control.params().lock().unwrap();
+            json(&ParamsBody {
+                min_rms: Some(params.min_rms),
+                max_rms: Some(params.max_rms),
+                boost: Some(params.boost),
+                threshold_factor: Some(params.threshold_factor),
+                attack: Some(params.attack),
+                release: Some(params.release),
+                paused: Some(params.paused),
+                off: Some(params.off),
+                low_power: Some(params.low_power),
+            })
+        }
+        (Method::Post, "/api/params") => match serde_json::from_str::<ParamsBody>(&body) {
+            Ok(update) => {
+                apply(update, control);
+                (204, String::new())
+            }
+            Err(e) => error(400, e),
+        },
+        (Method::Get, "/api/profiles") => match control.profiles() {
+            Ok(profiles) => json(&profiles),
+            Err(e) => error(500, e),
+        },
+        (Method::Post, "/api/profile") => match serde_json::from_str::<ProfileBody>(&body) {
+            Ok(profile) => match control.select_profile(&profile.name) {
+                Ok(()) => (204, String::new()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => error(404, e),
+                Err(e) => error(500, e),
+            },
+            Err(e) => error(400, e),
+        },
+        (Method::Post, url) if url.starts_with("/api/trigger/") => {
+            match control.trigger(&url["/api/trigger/".len()..]) {
+                Ok(()) => (204, String::new()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => error(404, e),
+                Err(e) => error(500, e),
+            }
+        }
+        _ => error(404, "no such endpoint"),
+    }
+}
+
+/// Applies the fields of `update`, within the ranges the terminal UI allows.
+fn apply(update: ParamsBody, control: &Handle) {
+    let mut params = control.params().lock().unwrap();
+    if let Some(min_rms) = update.min_rms {
+        params.min_rms = min_rms.max(0.0);
+    }
+    if let Some(max_rms) = update.max_rms {
+        params.max_rms = max_rms.max(params.min_rms + 0.001);
+    }
+    if let Some(boost) = update.boost {
+        params.boost = boost.clamp(0.1, 5.0);
+    }
+    if let Some(threshold_factor) = update.threshold_factor {
+        params.threshold_factor = threshold_factor.clamp(0.5, 5.0);
+    }
+    if let Some(attack) = update.attack {
+        params.attack = attack.clamp(1.0, 10_000.0);
+    }
+    if let Some(release) = update.release {
+        params.release = release.clamp(1.0, 10_000.0);
+    }
+    if let Some(paused) = update.paused {
+        params.paused = paused;
+    }
+    if let Some(off) = update.off {
+        params.off = off;
+    }
+    if let Some(low_power) = update.low_power {
+        params.low_power = low_power;
+    }
+}
+
+fn json(value: &impl Serialize) -> (u16, String) {
+    (200, serde_json::to_string(value).unwrap_or_default())
+}
+
+fn error(status: u16, message: impl std::fmt::Display) -> (u16, String) {
+    (
+        status,
+        serde_json::json!({ "error": message.to_string() }).to_string(),
+    )
+}
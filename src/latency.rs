@@ -0,0 +1,189 @@
+//! Measuring the end-to-end latency by playing clicks and detecting them on the input.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::error::{Error, Result};
+use crate::source::AudioSource;
+use crate::{Timing, calc_rms};
+
+/// Number of clicks played per measurement.
+pub const CLICKS: usize = 5;
+/// Length of a click, long enough to be heard by any microphone.
+const CLICK_LENGTH: Duration = Duration::from_millis(5);
+/// Frequency of the tone making up a click, in Hz.
+const CLICK_FREQUENCY: f32 = 1000.0;
+/// How long the room is listened to before the first click, to set the detection threshold.
+const NOISE_DURATION: Duration = Duration::from_millis(500);
+/// How long a click is waited for before counting it as missed.
+const CLICK_TIMEOUT: Duration = Duration::from_secs(1);
+/// Pause after each click, so its echo doesn't trigger the next detection.
+const SETTLE: Duration = Duration::from_millis(300);
+/// Lowest detection threshold, for a silent room.
+const MIN_THRESHOLD: f32 = 0.01;
+
+/// Plays [`CLICKS`] clicks through the default output and times how long each takes to show up
+/// on `input` as a chunk of [`Timing::chunk_size`] above the room's noise.
+///
+/// This includes what the pipeline adds: the output and capture buffers, and waiting for a chunk
+/// to fill. Missed clicks are left out, [`Error::NoClick`] is returned if all of them were.
+pub fn measure(input: &mut dyn AudioSource, timing: Timing) -> Result<Vec<Duration>> {
+    let click = Click::start()?;
+    let samples_per_chunk =
+        (input.sample_rate() as usize * timing.chunk_size.as_millis() as usize / 1000).max(1);
+    let mut chunks = Chunks {
+        input,
+        samples_per_chunk,
+        buffer: Vec::new(),
+    };
+
+    let mut noise: f32 = 0.0;
+    let start = Instant::now();
+    while start.elapsed() < NOISE_DURATION
+        && let Some(rms) = chunks.next_rms()?
+    {
+        noise = noise.max(rms);
+    }
+    let threshold = (noise * 4.0).max(MIN_THRESHOLD);
+    debug!(noise, threshold, "Listening for clicks");
+
+    let mut latencies = Vec::new();
+    // Stops early if the input ends.
+    'clicks: for _ in 0..CLICKS {
+        let fired = Instant::now();
+        click.fire();
+        while fired.elapsed() < CLICK_TIMEOUT {
+            let Some(rms) = chunks.next_rms()? else {
+                break 'clicks;
+            };
+            if rms > threshold {
+                if let Some(played) = click.played() {
+                    latencies.push(Instant::now().saturating_duration_since(played));
+                }
+                break;
+            }
+        }
+        // Let the echo die down, skipping whatever was captured meanwhile.
+        let settle = Instant::now();
+        while settle.elapsed() < SETTLE {
+            if chunks.next_rms()?.is_none() {
+                break 'clicks;
+            }
+        }
+    }
+
+    if latencies.is_empty() {
+        return Err(Error::NoClick);
+    }
+    Ok(latencies)
+}
+
+/// Cuts the input into chunks like the pipeline does.
+struct Chunks<'a> {
+    input: &'a mut dyn AudioSource,
+    samples_per_chunk: usize,
+    buffer: Vec<f32>,
+}
+
+impl Chunks<'_> {
+    /// Blocks until the next chunk is complete and returns its RMS level, `None` once the input
+    /// ended.
+    fn next_rms(&mut self) -> Result<Option<f32>> {
+        while self.buffer.len() < self.samples_per_chunk {
+            if !self.input.read(&mut self.buffer)? {
+                return Ok(None);
+            }
+        }
+        let rms = calc_rms(&self.buffer[..self.samples_per_chunk]);
+        self.buffer.drain(..self.samples_per_chunk);
+        Ok(Some(rms))
+    }
+}
+
+/// An output stream playing silence, and a click when fired.
+struct Click {
+    requested: Arc<AtomicBool>,
+    /// When the last click is expected to come out of the speakers.
+    played: Arc<Mutex<Option<Instant>>>,
+    _stream: cpal::Stream,
+}
+
+impl Click {
+    fn start() -> Result<Self> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or(Error::NoOutput)?;
+        let config = device
+            .supported_output_configs()
+            .map_err(playback)?
+            .find(|range| range.sample_format() == cpal::SampleFormat::F32)
+            .ok_or_else(|| Error::Playback("the output cannot play f32 samples".to_string()))?
+            .with_max_sample_rate()
+            .config();
+
+        let requested = Arc::new(AtomicBool::new(false));
+        let played = Arc::new(Mutex::new(None));
+        let (callback_requested, callback_played) = (Arc::clone(&requested), Arc::clone(&played));
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0 as f32;
+        let click_frames = (sample_rate * CLICK_LENGTH.as_secs_f32()) as usize;
+        // Frames of the current click left to play.
+        let mut remaining = 0;
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                    if callback_requested.swap(false, Ordering::Relaxed) {
+                        remaining = click_frames;
+                        let timestamp = info.timestamp();
+                        let delay = timestamp
+                            .playback
+                            .duration_since(&timestamp.callback)
+                            .unwrap_or_default();
+                        // Never blocks the callback, a missed timestamp only misses one click.
+                        if let Ok(mut played) = callback_played.try_lock() {
+                            *played = Some(Instant::now() + delay);
+                        }
+                    }
+                    for frame in data.chunks_mut(channels) {
+                        let value = if remaining > 0 {
+                            remaining -= 1;
+                            let t = (click_frames - remaining) as f32 / sample_rate;
+                            0.8 * (std::f32::consts::TAU * CLICK_FREQUENCY * t).sin()
+                        } else {
+                            0.0
+                        };
+                        frame.fill(value);
+                    }
+                },
+                |err| error!("Click playback failed: {err}"),
+                None,
+            )
+            .map_err(playback)?;
+        stream.play().map_err(playback)?;
+
+        Ok(Self {
+            requested,
+            played,
+            _stream: stream,
+        })
+    }
+
+    /// Plays a click in the next output callback.
+    fn fire(&self) {
+        *self.played.lock().unwrap() = None;
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// When the last fired click was played, if it was.
+    fn played(&self) -> Option<Instant> {
+        *self.played.lock().unwrap()
+    }
+}
+
+fn playback(e: impl std::fmt::Display) -> Error {
+    Error::Playback(e.to_string())
+}
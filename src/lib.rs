@@ -0,0 +1,1107 @@
+//! Drives a keyboard backlight from the microphone level.
+//!
+//! Samples come from an [`AudioSource`](source::AudioSource), either a cpal device driven by
+//! [`run_audio`] or any other source fed through [`run_source`], and are cut into
+//! chunks of [`Timing::chunk_size`]. Each chunk goes through [`process_audio_chunk`]: its RMS level is
+//! compared to a moving average, mapped to a brightness with [`brightness_for`] and written with
+//! [`set_brightness`]. Recorded levels can be fed instead with [`replay`]. The live [`Params`] can
+//! be tuned while running, and every chunk is published as [`Levels`] for displays.
+
+#![warn(missing_docs)]
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[macro_use]
+extern crate tracing;
+
+pub mod log;
+
+#[cfg(feature = "alsa")]
+pub mod alsa;
+pub mod app;
+pub mod calibration;
+pub mod config;
+pub mod control;
+pub mod error;
+pub mod expression;
+#[cfg(feature = "file")]
+pub mod file;
+pub mod generator;
+pub mod gesture;
+pub mod helper;
+pub mod latency;
+#[cfg(feature = "lua")]
+pub mod lua;
+pub mod mute;
+pub mod notify;
+pub mod output;
+pub mod preset;
+pub mod realtime;
+pub mod replay;
+pub mod restore;
+#[cfg(feature = "rhai")]
+pub mod rhai;
+pub mod sensor;
+mod simd;
+pub mod sink;
+pub mod source;
+pub mod spectrum;
+pub mod swap;
+pub mod vad;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(any(feature = "lua", feature = "rhai", feature = "wasm"))]
+mod watch;
+pub mod weighting;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use error::{Error, Result};
+use source::{AudioSource, CpalSource};
+
+/// The keyboard backlight's LED in sysfs.
+pub const BACKLIGHT: &str = "/sys/class/leds/chromeos::kbd_backlight";
+/// Capture rate requested from the input device, in Hz.
+pub const SAMPLE_RATE: u32 = 48_000;
+/// Default length of audio analyzed at once, shorter is more reactive.
+pub const CHUNK_SIZE: Duration = Duration::from_millis(50);
+/// Exponent applied to the normalized level, 1.0 = linear, >1 = sensitive at low end.
+pub const BOOST: f32 = 1.6;
+/// How much recent audio the moving average covers.
+pub const AVERAGE_WINDOW: Duration = Duration::from_millis(500);
+/// How far above the moving average the level must be to light up the keyboard.
+pub const THRESHOLD_FACTOR: f32 = 1.4;
+/// How fast the brightness may rise, in percent per second (2000 is instant at 50 ms chunks).
+pub const ATTACK: f32 = 2000.0;
+/// How fast the brightness fades between peaks, in percent per second.
+pub const RELEASE: f32 = 20.0;
+/// Analysis and write rate in low-power mode, in Hz.
+pub const LOW_POWER_RATE: f32 = 5.0;
+/// Highest rate the backlight is written at with an `update_rate`, in Hz.
+pub const MAX_UPDATE_RATE: f32 = 1000.0;
+/// How long to wait before reopening an input device that failed.
+pub const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// Longest wait between attempts at opening the input, which doubles from [`RETRY_DELAY`].
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How long the input may go without delivering audio before its stream is rebuilt.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+/// How often a sleeping input is reopened to listen for sound, see [`Params::sleep_after`].
+pub const SLEEP_POLL: Duration = Duration::from_secs(10);
+/// How long a sleeping input is listened to at each poll.
+pub const SLEEP_PROBE: Duration = Duration::from_millis(250);
+/// Number of FFT samples analyzed per chunk.
+pub const FFT_SIZE: usize = 2048;
+/// Number of bands shown in the UI spectrum.
+pub const BAND_COUNT: usize = 16;
+/// Number of samples of each chunk kept for waveform displays.
+pub const WAVEFORM_POINTS: usize = 256;
+/// Number of chunks the calibration drift check looks at, 30 s at 50 ms chunks.
+pub const DRIFT_WINDOW: usize = 600;
+
+/// Parameters of the brightness mapping, shared between the audio callback and the UI.
+pub struct Params {
+    /// RMS level at or below which the backlight stays off (the noise floor).
+    pub min_rms: f32,
+    /// RMS level mapped to full brightness.
+    pub max_rms: f32,
+    /// Exponent applied to the normalized level, see [`BOOST`].
+    pub boost: f32,
+    /// Multiplier of the moving average above which a chunk triggers, or standard deviations
+    /// above it with [`ThresholdMode::Adaptive`](config::ThresholdMode::Adaptive); lower is more
+    /// sensitive.
+    pub threshold_factor: f32,
+    /// How [`threshold_factor`](Self::threshold_factor) sets the trigger threshold.
+    pub threshold_mode: config::ThresholdMode,
+    /// How much recent audio the moving average covers, see [`AVERAGE_WINDOW`]; shorter reacts
+    /// faster to changes in loudness, longer smooths them out.
+    pub average: Duration,
+    /// How fast the brightness may rise, in percent per second.
+    pub attack: f32,
+    /// How fast the brightness fades between peaks, in percent per second.
+    pub release: f32,
+    /// Brightness written at full level, in percent; lower values scale the whole range down.
+    pub max_brightness: f32,
+    /// Keeps analyzing but stops writing to the backlight.
+    pub paused: bool,
+    /// Keeps the backlight off whatever the level, e.g. forced during a meeting.
+    pub off: bool,
+    /// Analyzes and writes at [`LOW_POWER_RATE`] to save power.
+    pub low_power: bool,
+    /// Only reacts to speech, other sounds leave the backlight fading.
+    pub voice_only: bool,
+    /// Tempo in beats per minute tapped over the control socket; while set, the beats fall on it
+    /// instead of being detected, from the chunk it was tapped on.
+    pub tempo: Option<f32>,
+    /// How long the level may stay at the noise floor before the capture stops until sound comes
+    /// back; never if unset.
+    pub sleep_after: Option<Duration>,
+    /// How the audio and the sensors are combined.
+    pub mix: config::Mix,
+    /// How the audio is split into frequency bands.
+    pub spectrum: config::Spectrum,
+    /// What of the audio drives the brightness.
+    pub driver: config::Driver,
+    /// Frequency weighting of the RMS level.
+    pub weighting: config::Weighting,
+    /// Evens out the normalized level before it is boosted, when set.
+    pub compressor: Option<config::Compressor>,
+    /// Maps the level to the brightness instead of [`boost`](Self::boost), when set.
+    pub mapping: Option<expression::Expression>,
+    /// Bands lowered while others hit.
+    pub ducking: Vec<config::Duck>,
+    /// Transients flash and sustained sounds glow, when set.
+    pub layers: Option<config::Layers>,
+    /// Clapping twice toggles [`paused`](Self::paused), when set.
+    pub clap: Option<config::Clap>,
+    /// Whistling sets [`max_brightness`](Self::max_brightness) from the pitch, when set.
+    pub whistle: Option<config::Whistle>,
+    /// Brightness set by another program, replacing the audio-driven one for a while.
+    pub forced: Option<Forced>,
+    /// Flash pattern playing over the audio-driven brightness, e.g. for a notification.
+    pub flash: Option<Flashing>,
+    /// Name of the WASM plugin shaping the brightness, with the `wasm` feature; none if unset.
+    pub plugin: Option<String>,
+    /// The stages shaping the brightness, in order.
+    pub pipeline: Vec<config::Stage>,
+    /// Effects swapped over the control socket, which take precedence over the config's.
+    pub swapped: swap::Swapped,
+}
+
+/// A brightness set from outside the pipeline, e.g. a game flashing the keyboard.
+#[derive(Clone, Copy)]
+pub struct Forced {
+    /// Brightness in percent.
+    pub brightness: f32,
+    /// When the audio takes over again.
+    pub until: Instant,
+}
+
+/// A [`Flash`](config::Flash) playing over the audio-driven brightness.
+#[derive(Clone)]
+pub struct Flashing {
+    /// The pattern.
+    pub flash: config::Flash,
+    /// When it started.
+    pub started: Instant,
+}
+
+impl Flashing {
+    /// Brightness in percent at `now`, `None` between pulses and once the pattern ended.
+    pub fn brightness(&self, now: Instant) -> Option<f32> {
+        let elapsed = now.duration_since(self.started).as_millis() as u64;
+        let period = (self.flash.on_ms + self.flash.off_ms).max(1);
+        let lit = elapsed / period < self.flash.count as u64 && elapsed % period < self.flash.on_ms;
+        lit.then(|| self.flash.level())
+    }
+}
+
+/// [`Params`] as shared between threads.
+pub type SharedParams = Arc<Mutex<Params>>;
+
+/// How often audio is analyzed and the backlight written.
+#[derive(Clone, Copy)]
+pub struct Timing {
+    /// Length of audio analyzed at once.
+    pub chunk_size: Duration,
+    /// Rate at which the backlight is written in Hz, interpolating between chunks; once per chunk
+    /// if unset.
+    pub update_rate: Option<f32>,
+}
+
+impl Timing {
+    /// The timing actually used, throttled to [`LOW_POWER_RATE`] in low-power mode.
+    pub fn effective(self, low_power: bool) -> Self {
+        if low_power {
+            Self {
+                chunk_size: Duration::from_secs_f32(1.0 / LOW_POWER_RATE),
+                update_rate: None,
+            }
+        } else {
+            self
+        }
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            chunk_size: CHUNK_SIZE,
+            update_rate: None,
+        }
+    }
+}
+
+/// A snapshot of the audio pipeline, sent once per processed chunk.
+#[derive(Clone, Default)]
+pub struct Levels {
+    /// RMS level of the chunk.
+    pub rms: f32,
+    /// Level above which the chunk would have triggered.
+    pub threshold: f32,
+    /// RMS level within the calibrated range, 0.0-1.0.
+    pub normalized: f32,
+    /// Whether the chunk rose above the threshold and lit up the keyboard.
+    pub beat: bool,
+    /// Brightness in percent.
+    pub brightness: f32,
+    /// Band magnitudes in the 0.0-1.0 range, lowest frequency first.
+    pub bands: Vec<f32>,
+    /// The chunk's samples, decimated to at most `WAVEFORM_POINTS` values.
+    pub waveform: Vec<f32>,
+    /// Measured number of chunks analyzed per second.
+    pub chunk_rate: f32,
+    /// Measured number of backlight writes per second.
+    pub write_rate: f32,
+    /// Health of the capture and the backlight writes, updated once a second.
+    pub health: Health,
+}
+
+/// Pipeline statistics to quantify lag, measured over about a second.
+#[derive(Clone, Copy, Default)]
+pub struct Health {
+    /// How regularly the capture delivers audio.
+    pub stream: source::StreamHealth,
+    /// Longest backlight write.
+    pub write_latency: Duration,
+}
+
+/// Captures from the input device named `device` (or the default one) forever, switching
+/// devices for each name received on `switch`.
+///
+/// When the device goes away or cannot be opened, it is retried every [`RETRY_DELAY`].
+pub fn run_audio(
+    device: Option<String>,
+    timing: Timing,
+    levels: Vec<Sender<Levels>>,
+    params: SharedParams,
+    switch: Receiver<String>,
+) -> ! {
+    let host = cpal::default_host();
+    mute::watch();
+    let mut wanted = device;
+    let mut source = open_retrying(&host, wanted.as_deref());
+    let mut state = RmsState::new(timing, levels, params);
+    let mut samples = Vec::new();
+
+    loop {
+        samples.clear();
+        let failure = match source.read(&mut samples) {
+            Ok(true) => {
+                state.feed(&samples, source.sample_rate());
+                let sleep_after = state.params.lock().unwrap().sleep_after;
+                if sleep_after.is_some_and(|after| state.silence >= after) {
+                    drop(source);
+                    source = sleep_until_sound(&host, wanted.as_deref(), &state.params);
+                    state.silence = Duration::ZERO;
+                }
+                None
+            }
+            Ok(false) => Some(format!("{} stopped delivering audio", source.name())),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(failure) = failure {
+            notify::alert("stream", "Audio input failed", &failure);
+            drop(source);
+            source = open_retrying(&host, wanted.as_deref());
+        }
+
+        if let Some(name) = switch.try_iter().last() {
+            #[cfg(feature = "null")]
+            if let Some(signal) = source::null_signal(&name) {
+                drop(source);
+                wanted = Some(name);
+                source = CpalSource::open_null(signal);
+                continue;
+            }
+            match find_input_device(&host, &name) {
+                Some(device) => {
+                    // Release the current device before opening the next one.
+                    drop(source);
+                    wanted = Some(name);
+                    source = match CpalSource::open(&device) {
+                        Ok(source) => source,
+                        Err(e) => {
+                            notify::alert("stream", "Audio input failed", &e.to_string());
+                            open_retrying(&host, wanted.as_deref())
+                        }
+                    };
+                }
+                None => warn!("Input device not found: {name}"),
+            }
+        }
+    }
+}
+
+/// Opens the first input device whose name contains `name`, or the default one.
+pub fn open_input(host: &cpal::Host, name: Option<&str>) -> Result<CpalSource> {
+    #[cfg(feature = "null")]
+    if let Some(signal) = name.and_then(source::null_signal) {
+        return Ok(CpalSource::open_null(signal));
+    }
+    let device = name
+        .and_then(|name| find_input_device(host, name))
+        .or_else(|| host.default_input_device())
+        .ok_or(Error::NoDevice)?;
+    CpalSource::open(&device)
+}
+
+/// Like [`open_input`], retrying until it succeeds, first after [`RETRY_DELAY`] then twice as
+/// long every time up to [`MAX_RETRY_DELAY`], e.g. while another application holds the device.
+fn open_retrying(host: &cpal::Host, name: Option<&str>) -> CpalSource {
+    let mut delay = RETRY_DELAY;
+    loop {
+        match open_input(host, name) {
+            Ok(source) => return source,
+            Err(e) => {
+                // There is no portal capture yet, only the sound server's socket.
+                if delay == RETRY_DELAY && sink::sandboxed() {
+                    warn!(
+                        "Running in a sandbox, capture needs the PulseAudio or PipeWire socket: \
+                         grant it with --socket=pulseaudio"
+                    );
+                }
+                let body = format!("{e}, retrying in {}s", delay.as_secs());
+                notify::alert("stream", "Audio input unavailable", &body);
+                source::set_unavailable(Some(e.to_string()));
+                sleep(delay);
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+/// Keeps the input closed, listening for [`SLEEP_PROBE`] every [`SLEEP_POLL`], and returns it
+/// reopened once its level rises above the noise floor.
+fn sleep_until_sound(host: &cpal::Host, name: Option<&str>, params: &SharedParams) -> CpalSource {
+    info!("Silent for a while, stopping the capture until sound comes back");
+    loop {
+        sleep(SLEEP_POLL);
+        let mut source = open_retrying(host, name);
+        let wanted = (source.sample_rate() as f32 * SLEEP_PROBE.as_secs_f32()) as usize;
+        let mut samples = Vec::new();
+        while samples.len() < wanted && matches!(source.read(&mut samples), Ok(true)) {}
+        if !samples.is_empty() && calc_rms(&samples) > params.lock().unwrap().min_rms {
+            info!("Sound is back, capturing again");
+            return source;
+        }
+    }
+}
+
+/// Feeds `source` through the pipeline until it ends.
+pub fn run_source(source: &mut dyn AudioSource, state: &mut RmsState) -> Result<()> {
+    let mut samples = Vec::new();
+    while source.read(&mut samples)? {
+        state.feed(&samples, source.sample_rate());
+        samples.clear();
+    }
+    Ok(())
+}
+
+/// Lists the names of all input devices of the default host.
+pub fn input_device_names() -> Vec<String> {
+    let names = match cpal::default_host().input_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            error!("Failed to get input devices: {e}");
+            Vec::new()
+        }
+    };
+    #[cfg(feature = "null")]
+    let names = [names, vec![source::NULL_DEVICE.to_string()]].concat();
+    names
+}
+
+/// Finds the first input device whose name contains `name`.
+pub fn find_input_device(host: &cpal::Host, name: &str) -> Option<cpal::Device> {
+    host.input_devices()
+        .ok()?
+        .find(|d| d.name().map(|n| n.contains(name)).unwrap_or(false))
+}
+
+/// What the pipeline knows about a chunk, handed to custom mappings and plugins.
+pub struct Chunk<'a> {
+    /// The level driving the brightness.
+    pub rms: f32,
+    /// Band magnitudes in the 0.0-1.0 range, lowest first; empty without samples.
+    pub bands: &'a [f32],
+    /// The level within the calibrated range, 0.0-1.0.
+    pub normalized: f32,
+    /// Level above which the chunk triggers.
+    pub threshold: f32,
+    /// Whether the chunk triggered.
+    pub beat: bool,
+    /// The pipeline's brightness, in percent.
+    pub brightness: f32,
+    /// Length of the chunk, in seconds.
+    pub chunk_secs: f32,
+}
+
+/// Keeps the state throughout the app's lifetime.
+pub struct RmsState {
+    moving_avg: MovingAverage,
+    params: SharedParams,
+    /// Brightness of the last chunk, in percent.
+    current_brightness: f32,
+    /// The brightness each [`Smoother`](config::Stage::Smoother) stage holds, by stage.
+    smoothed: Vec<f32>,
+    spectrum: spectrum::Spectrum,
+    drift: DriftCheck,
+    double_clap: gesture::DoubleClap,
+    voice: vad::VoiceDetector,
+    whistle: gesture::WhistleControl,
+    tempo: TempoLock,
+    k_weighting: weighting::KWeighting,
+    /// The K-weighted samples of the current chunk.
+    weighted: Vec<f32>,
+    /// Sample rate of the audio being fed.
+    sample_rate: u32,
+    /// How long the level has been at the noise floor.
+    silence: Duration,
+    timing: Timing,
+    output: output::Output,
+    rates: RateMeter,
+    /// Samples received but not yet processed, less than a chunk.
+    buffer: Vec<f32>,
+    /// Where the per-chunk levels are published, one sender per running display.
+    levels: Vec<Sender<Levels>>,
+    /// The custom mapping of `map.lua`, if there is one.
+    #[cfg(feature = "lua")]
+    lua: Option<lua::Mapping>,
+    /// The effect of `effect.rhai`, if there is one.
+    #[cfg(feature = "rhai")]
+    rhai: Option<rhai::Effect>,
+    /// The WASM plugin each [`Plugin`](config::Stage::Plugin) stage runs, by stage.
+    #[cfg(feature = "wasm")]
+    plugins: Vec<wasm::Slot>,
+}
+
+impl RmsState {
+    /// Starts from a dark keyboard, publishing every chunk to `levels`.
+    pub fn new(timing: Timing, levels: Vec<Sender<Levels>>, params: SharedParams) -> Self {
+        Self {
+            moving_avg: MovingAverage::new(10),
+            params,
+            current_brightness: 0.0f32,
+            smoothed: Vec::new(),
+            spectrum: spectrum::Spectrum::new(FFT_SIZE, BAND_COUNT),
+            drift: DriftCheck::default(),
+            double_clap: gesture::DoubleClap::default(),
+            voice: vad::VoiceDetector::default(),
+            whistle: gesture::WhistleControl::default(),
+            tempo: TempoLock::default(),
+            k_weighting: weighting::KWeighting::default(),
+            weighted: Vec::new(),
+            sample_rate: SAMPLE_RATE,
+            silence: Duration::ZERO,
+            timing,
+            output: output::Output::new(timing),
+            rates: RateMeter::default(),
+            buffer: Vec::new(),
+            levels,
+            #[cfg(feature = "lua")]
+            lua: lua::Mapping::load().unwrap_or_else(|e| {
+                error!("Failed to load the Lua mapping, ignoring it: {e}");
+                None
+            }),
+            #[cfg(feature = "rhai")]
+            rhai: rhai::Effect::load().unwrap_or_else(|e| {
+                error!("Failed to load the Rhai effect, ignoring it: {e}");
+                None
+            }),
+            #[cfg(feature = "wasm")]
+            plugins: Vec::new(),
+        }
+    }
+
+    /// Buffers `samples` and processes every complete chunk.
+    pub fn feed(&mut self, samples: &[f32], sample_rate: u32) {
+        self.buffer.extend_from_slice(samples);
+        self.sample_rate = sample_rate;
+
+        let low_power = self.params.lock().unwrap().low_power;
+        let chunk_size = self.timing.effective(low_power).chunk_size;
+        // Sample rate * duration in seconds = number of samples in duration.
+        let samples_per_chunk =
+            (sample_rate as usize * chunk_size.as_millis() as usize / 1000).max(1);
+        // Chunks are processed in place, the buffer is only taken out to lend it to the pipeline.
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let mut processed = 0;
+        while buffer.len() - processed >= samples_per_chunk {
+            let _chunk = trace_span!("chunk").entered();
+            process_audio_chunk(&buffer[processed..processed + samples_per_chunk], self);
+            processed += samples_per_chunk;
+        }
+        buffer.drain(..processed);
+        self.buffer = buffer;
+    }
+}
+
+/// Measures how often chunks are analyzed and the backlight is written, and the [`Health`].
+struct RateMeter {
+    since: Instant,
+    chunks: usize,
+    /// [`output::write_count`] at `since`.
+    writes: usize,
+    chunk_rate: f32,
+    write_rate: f32,
+    health: Health,
+}
+
+impl Default for RateMeter {
+    fn default() -> Self {
+        Self {
+            since: Instant::now(),
+            chunks: 0,
+            writes: output::write_count(),
+            chunk_rate: 0.0,
+            write_rate: 0.0,
+            health: Health::default(),
+        }
+    }
+}
+
+impl RateMeter {
+    /// Counts one chunk, updating the rates about once a second and returning whether it did.
+    fn tick(&mut self) -> bool {
+        self.chunks += 1;
+        let elapsed = self.since.elapsed().as_secs_f32();
+        if elapsed >= 1.0 {
+            let writes = output::write_count();
+            *self = Self {
+                since: Instant::now(),
+                chunks: 0,
+                writes,
+                chunk_rate: self.chunks as f32 / elapsed,
+                write_rate: (writes - self.writes) as f32 / elapsed,
+                health: Health {
+                    stream: source::take_health(),
+                    write_latency: output::take_write_latency(),
+                },
+            };
+            return true;
+        }
+        false
+    }
+}
+
+/// Watches whether the live levels still fit the calibrated RMS range.
+#[derive(Default)]
+struct DriftCheck {
+    chunks: usize,
+    /// Chunks at or above the calibrated peak in the current window.
+    saturated: usize,
+    /// Quietest level seen in the current window.
+    quietest: Option<f32>,
+}
+
+impl DriftCheck {
+    /// Records one level, returning a description of the drift at the end of a window that drifted.
+    fn update(&mut self, rms: f32, params: &Params) -> Option<String> {
+        self.chunks += 1;
+        if rms >= params.max_rms {
+            self.saturated += 1;
+        }
+        self.quietest = Some(self.quietest.map_or(rms, |quietest| quietest.min(rms)));
+
+        if self.chunks < DRIFT_WINDOW {
+            return None;
+        }
+
+        let saturated = self.saturated as f32 / self.chunks as f32;
+        let quietest = self.quietest.unwrap_or_default();
+        *self = Self::default();
+
+        if saturated > 0.5 {
+            Some(format!(
+                "the level was above the calibrated peak {:.0}% of the time",
+                saturated * 100.0
+            ))
+        } else if quietest > params.min_rms + (params.max_rms - params.min_rms) / 2.0 {
+            Some(format!(
+                "the quietest level ({quietest:.3}) is far above the calibrated noise floor ({:.3})",
+                params.min_rms
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Beats falling on a tapped tempo rather than detected, see [`Params::tempo`].
+#[derive(Default)]
+struct TempoLock {
+    /// The tempo locked to, in beats per minute.
+    tempo: Option<f32>,
+    /// How far into the current beat, in beats.
+    phase: f32,
+}
+
+impl TempoLock {
+    /// Whether a beat of `tempo` falls in the next `chunk_secs`, the first on the chunk the
+    /// tempo changed.
+    fn beat(&mut self, tempo: f32, chunk_secs: f32) -> bool {
+        if self.tempo != Some(tempo) {
+            self.tempo = Some(tempo);
+            self.phase = 0.0;
+            return true;
+        }
+        self.phase += chunk_secs * tempo / 60.0;
+        if self.phase < 1.0 {
+            return false;
+        }
+        self.phase = self.phase.fract();
+        true
+    }
+}
+
+/// A simple moving average calculator for real-time data.
+struct MovingAverage {
+    /// The number of items to average over.
+    size: usize,
+    /// The recent values used to compute the average.
+    window: VecDeque<f32>,
+}
+
+impl MovingAverage {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            window: VecDeque::new(),
+        }
+    }
+
+    /// Averages over the last `size` values from now on.
+    fn resize(&mut self, size: usize) {
+        self.size = size;
+        while self.window.len() > size {
+            self.window.pop_front();
+        }
+    }
+
+    fn update(&mut self, data: f32) -> &mut Self {
+        self.window.push_back(data);
+        if self.window.len() > self.size {
+            self.window.pop_front();
+        }
+
+        self
+    }
+
+    fn value(&self) -> f32 {
+        if self.window.is_empty() {
+            0.0
+        } else {
+            self.window.iter().sum::<f32>() / self.window.len() as f32
+        }
+    }
+
+    /// Standard deviation of the recent values around [`value`](Self::value).
+    fn std_dev(&self) -> f32 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+        let mean = self.value();
+        let variance = self
+            .window
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f32>()
+            / self.window.len() as f32;
+        variance.sqrt()
+    }
+
+    /// The level a chunk must exceed to be a beat, see [`config::ThresholdMode`].
+    fn threshold(&self, params: &Params) -> f32 {
+        match params.threshold_mode {
+            config::ThresholdMode::Factor => self.value() * params.threshold_factor,
+            config::ThresholdMode::Adaptive => {
+                self.value() + self.std_dev() * params.threshold_factor
+            }
+        }
+    }
+}
+
+/// Taes a chunk of audio data point (always the same length) and updates the keyboard backlights.
+pub fn process_audio_chunk(chunk: &[f32], state: &mut RmsState) {
+    if mute::muted() {
+        process(0.0, &[], state);
+        return;
+    }
+    let weighting = state.params.lock().unwrap().weighting;
+    let rms = match weighting {
+        config::Weighting::None => calc_rms(chunk),
+        config::Weighting::K => {
+            let mut weighted = std::mem::take(&mut state.weighted);
+            state
+                .k_weighting
+                .filter(chunk, state.sample_rate, &mut weighted);
+            let rms = calc_rms(&weighted);
+            state.weighted = weighted;
+            rms
+        }
+    };
+    process(rms, chunk, state);
+}
+
+/// Updates the keyboard backlight from the RMS level of a chunk whose samples aren't available,
+/// e.g. a recorded one; the published [`Levels`] then have no bands or waveform.
+pub fn process_level(rms: f32, state: &mut RmsState) {
+    process(rms, &[], state);
+}
+
+fn process(rms: f32, chunk: &[f32], state: &mut RmsState) {
+    let mut params = state.params.lock().unwrap();
+
+    // The spectrum is only analyzed when something needs it.
+    let analyzed = !chunk.is_empty()
+        && (!state.levels.is_empty()
+            || params.whistle.is_some()
+            || params.driver == config::Driver::Flux
+            || params.layers.is_some());
+    // The script gets the bands too.
+    #[cfg(feature = "lua")]
+    let analyzed = analyzed || (!chunk.is_empty() && state.lua.is_some());
+    #[cfg(feature = "rhai")]
+    let analyzed = analyzed || (!chunk.is_empty() && state.rhai.is_some());
+    let timing = state.timing.effective(params.low_power);
+    let chunk_secs = timing.chunk_size.as_secs_f32();
+    if analyzed {
+        state
+            .spectrum
+            .configure(&params.spectrum, state.sample_rate);
+        state.spectrum.process(chunk);
+        state.spectrum.duck(&params.ducking, chunk_secs);
+    }
+    // Transients drive the beats and sustained sounds a glow under them, when layered.
+    let (transient, sustain) = match &params.layers {
+        Some(_) if analyzed => {
+            let (transient, sustain) = state.spectrum.separate();
+            (Some(transient), sustain)
+        }
+        _ => (None, 0.0),
+    };
+    // Recorded chunks only have their RMS, they keep driving with it.
+    let rms = if params.driver == config::Driver::Flux && analyzed {
+        state.spectrum.flux()
+    } else {
+        transient.unwrap_or(rms)
+    };
+    // The window is set in time, the number of chunks follows the chunk size.
+    let average_chunks = (params.average.as_secs_f32() / chunk_secs).round().max(1.0);
+    state.moving_avg.resize(average_chunks as usize);
+    state.moving_avg.update(rms);
+
+    //let threshold = (state.moving_avg.value() * 1.5).max(1.0);
+    let threshold = state.moving_avg.threshold(&params);
+    let normalized = normalize(rms, &params);
+    // Without the samples, e.g. replaying, every chunk passes for speech.
+    let speech =
+        !params.voice_only || chunk.is_empty() || state.voice.update(chunk, normalized, chunk_secs);
+    let beat = match params
+        .tempo
+        .filter(|tempo| tempo.is_finite() && *tempo > 0.0)
+    {
+        Some(tempo) => state.tempo.beat(tempo, chunk_secs),
+        None => {
+            state.tempo = TempoLock::default();
+            rms > threshold && speech
+        }
+    };
+    if rms > params.min_rms {
+        state.silence = Duration::ZERO;
+    } else {
+        state.silence += timing.chunk_size;
+    }
+
+    if let Some(clap) = &params.clap
+        && state
+            .double_clap
+            .update(rms, normalized, state.moving_avg.value(), chunk_secs, clap)
+    {
+        params.paused = !params.paused;
+        info!(
+            "Double clap, {}",
+            if params.paused { "paused" } else { "resumed" }
+        );
+    }
+
+    if let Some(whistle) = &params.whistle
+        && analyzed
+    {
+        let (bin, purity) = state.spectrum.peak();
+        let frequency = bin as f32 * state.sample_rate as f32 / FFT_SIZE as f32;
+        if let Some(pitch) = state.whistle.update(frequency, purity, chunk_secs, whistle) {
+            params.max_brightness = pitch * 100.0;
+        }
+    }
+
+    let glow = params.layers.as_ref().map_or(0.0, |layers| {
+        brightness_for(sustain, &params) * layers.glow.clamp(0.0, 100.0) / 100.0
+    });
+    let stages = params.pipeline.len();
+    state.smoothed.resize(stages, 0.0);
+    #[cfg(feature = "wasm")]
+    state.plugins.resize_with(stages, Default::default);
+    #[cfg(any(feature = "lua", feature = "rhai", feature = "wasm"))]
+    for swap in swap::take() {
+        match swap {
+            #[cfg(feature = "lua")]
+            swap::Swap::Lua(mapping) => state.lua = mapping,
+            #[cfg(feature = "rhai")]
+            swap::Swap::Rhai(effect) => state.rhai = effect.map(|effect| *effect),
+            // Into the first stage following the config, any other loads its own copy.
+            #[cfg(feature = "wasm")]
+            swap::Swap::Plugin(plugin) => {
+                let first = params
+                    .pipeline
+                    .iter()
+                    .position(|stage| matches!(stage, config::Stage::Plugin { name: None }));
+                if let Some(i) = first {
+                    state.plugins[i].install(plugin);
+                }
+            }
+        }
+    }
+    #[cfg(any(feature = "lua", feature = "rhai", feature = "wasm"))]
+    let view = |brightness| Chunk {
+        rms,
+        bands: if analyzed {
+            state.spectrum.bands()
+        } else {
+            &[]
+        },
+        normalized,
+        threshold,
+        beat,
+        brightness,
+        chunk_secs,
+    };
+    let mut audio_brightness = 0.0;
+    for (i, stage) in params.pipeline.iter().enumerate() {
+        audio_brightness = match stage {
+            config::Stage::Mapper { boost, mapping } => {
+                // A boost of the stage's own is for the curve, not the profile's mapping.
+                let mapping = match (mapping, boost) {
+                    (Some(mapping), _) => Some(mapping),
+                    (None, Some(_)) => None,
+                    (None, None) => params.mapping.as_ref(),
+                };
+                map_level(rms, &params, boost.unwrap_or(params.boost), mapping)
+            }
+            config::Stage::Smoother { attack, release } => {
+                let smoothed = &mut state.smoothed[i];
+                *smoothed = if beat {
+                    let rise_limit = *smoothed + attack.unwrap_or(params.attack) * chunk_secs;
+                    audio_brightness.min(rise_limit)
+                } else {
+                    (*smoothed - release.unwrap_or(params.release) * chunk_secs).max(0.0)
+                };
+                *smoothed
+            }
+            config::Stage::Glow => audio_brightness.max(glow),
+            #[cfg(feature = "lua")]
+            config::Stage::Lua => state
+                .lua
+                .as_mut()
+                .and_then(|mapping| mapping.map(&view(audio_brightness)))
+                .unwrap_or(audio_brightness),
+            #[cfg(feature = "rhai")]
+            config::Stage::Rhai => state
+                .rhai
+                .as_mut()
+                .and_then(|effect| effect.map(&view(audio_brightness)))
+                .unwrap_or(audio_brightness),
+            #[cfg(feature = "wasm")]
+            config::Stage::Plugin { name } => state.plugins[i]
+                .frame(
+                    name.as_deref().or(params.plugin.as_deref()),
+                    &view(audio_brightness),
+                )
+                .unwrap_or(audio_brightness),
+            // The stages of features left out pass the brightness through.
+            #[cfg(not(all(feature = "lua", feature = "rhai", feature = "wasm")))]
+            _ => audio_brightness,
+        };
+    }
+    state.current_brightness = audio_brightness;
+    write_level(&state.output, audio_brightness, &params);
+    if state.rates.tick() {
+        debug!(
+            rms,
+            brightness = state.current_brightness,
+            chunk_rate = state.rates.chunk_rate,
+            write_rate = state.rates.write_rate,
+            jitter_us = state.rates.health.stream.jitter.as_micros() as u64,
+            occupancy = state.rates.health.stream.occupancy,
+            dropped = state.rates.health.stream.dropped,
+            write_latency_us = state.rates.health.write_latency.as_micros() as u64,
+            "Levels"
+        );
+    }
+
+    if let Some(drift) = state.drift.update(rms, &params) {
+        notify::alert("drift", "Calibration drifted, please recalibrate", &drift);
+    }
+    drop(params);
+
+    if !state.levels.is_empty() {
+        let bands = if chunk.is_empty() {
+            Vec::new()
+        } else {
+            state.spectrum.bands().to_vec()
+        };
+        let snapshot = Levels {
+            rms,
+            threshold,
+            normalized,
+            beat,
+            brightness: audio_brightness,
+            bands,
+            waveform: chunk
+                .iter()
+                .step_by(chunk.len().div_ceil(WAVEFORM_POINTS).max(1))
+                .copied()
+                .collect(),
+            chunk_rate: state.rates.chunk_rate,
+            write_rate: state.rates.write_rate,
+            health: state.rates.health,
+        };
+        publish(&mut state.levels, snapshot);
+    }
+}
+
+/// Updates the keyboard backlight from a `brightness` already analyzed elsewhere, e.g. by a
+/// leader instance on another machine; the published [`Levels`] then only have the brightness
+/// and the beat.
+pub fn process_brightness(brightness: f32, beat: bool, state: &mut RmsState) {
+    state.current_brightness = brightness.clamp(0.0, 100.0);
+    write_level(
+        &state.output,
+        state.current_brightness,
+        &state.params.lock().unwrap(),
+    );
+    state.rates.tick();
+
+    if !state.levels.is_empty() {
+        let snapshot = Levels {
+            beat,
+            brightness: state.current_brightness,
+            chunk_rate: state.rates.chunk_rate,
+            write_rate: state.rates.write_rate,
+            health: state.rates.health,
+            ..Levels::default()
+        };
+        publish(&mut state.levels, snapshot);
+    }
+}
+
+/// Writes the audio-driven `brightness` in percent, mixed with the sensors, or what overrides it.
+fn write_level(output: &output::Output, brightness: f32, params: &Params) {
+    let now = Instant::now();
+    let forced = params.forced.filter(|forced| forced.until > now);
+    let flash = params
+        .flash
+        .as_ref()
+        .and_then(|flash| flash.brightness(now));
+    let level = if params.off {
+        0.0
+    } else if let Some(forced) = forced {
+        forced.brightness
+    } else {
+        let level = sensor::mix(brightness / 100.0, &params.mix) * params.max_brightness;
+        level.max(flash.unwrap_or_default())
+    };
+    output.set(level, params.paused, params.low_power);
+}
+
+/// Sends `snapshot` to every display, dropping those that went away.
+fn publish(levels: &mut Vec<Sender<Levels>>, snapshot: Levels) {
+    levels.retain(|levels| levels.send(snapshot.clone()).is_ok());
+}
+
+/// Maps an RMS level to a brightness percentage.
+pub fn brightness_for(rms: f32, params: &Params) -> f32 {
+    map_level(rms, params, params.boost, params.mapping.as_ref())
+}
+
+/// Maps an RMS level to a brightness percentage with `mapping`, or the `boost` curve without.
+fn map_level(
+    rms: f32,
+    params: &Params,
+    boost: f32,
+    mapping: Option<&expression::Expression>,
+) -> f32 {
+    let mut normalized = normalize(rms, params);
+    if let Some(compressor) = &params.compressor {
+        normalized = compress(normalized, compressor);
+    }
+    if let Some(mapping) = mapping {
+        let brightness = mapping.eval(&expression::Variables {
+            rms,
+            normalized,
+            min_rms: params.min_rms,
+            max_rms: params.max_rms,
+            boost,
+        });
+        // An undefined result, e.g. the log of silence, leaves the backlight dark.
+        return if brightness.is_nan() {
+            0.0
+        } else {
+            brightness.clamp(0.0, 100.0)
+        };
+    }
+    let normalized_rms = normalized * 100.0;
+
+    let boosted = normalized_rms.powf(boost);
+    boosted.clamp(0.0, 100.0)
+}
+
+/// Position of an RMS level within the calibrated range, clamped to 0.0-1.0.
+pub fn normalize(rms: f32, params: &Params) -> f32 {
+    if params.max_rms > params.min_rms {
+        ((rms - params.min_rms) / (params.max_rms - params.min_rms)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Applies `compressor` to a normalized level, a soft-knee curve in decibels.
+fn compress(level: f32, compressor: &config::Compressor) -> f32 {
+    if level <= 0.0 {
+        return 0.0;
+    }
+    let (threshold, ratio) = (compressor.threshold_db, compressor.ratio.max(1.0));
+    let knee = compressor.knee_db.max(0.0);
+    // The gain the curve gives the level `db` loses.
+    let reduction = |db: f32| {
+        let over = db - threshold;
+        if 2.0 * over <= -knee {
+            0.0
+        } else if 2.0 * over.abs() <= knee {
+            (1.0 / ratio - 1.0) * (over + knee / 2.0).powi(2) / (2.0 * knee)
+        } else {
+            over * (1.0 / ratio - 1.0)
+        }
+    };
+    // By default a full level stays full, the compression lifts everything below.
+    let makeup = compressor.makeup_db.unwrap_or_else(|| -reduction(0.0));
+    let db = 20.0 * level.log10();
+    10f32
+        .powf((db + reduction(db) + makeup) / 20.0)
+        .clamp(0.0, 1.0)
+}
+
+/// Sets the brightness of the keyboard backlight, through the selected [`sink`].
+pub fn set_brightness(level: f32) -> Result<()> {
+    sink::set(level).map_err(Error::Backlight)
+}
+
+/// Root mean square of the samples.
+pub fn calc_rms(data: &[f32]) -> f32 {
+    let mean_squares = simd::sum_squares(data) / data.len() as f32;
+    mean_squares.sqrt()
+}
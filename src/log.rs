@@ -0,0 +1,159 @@
+//! Logging setup: `tracing` events go to stderr, or to an in-app buffer while the UI captures them.
+
+use std::io::IsTerminal;
+#[cfg(feature = "ui")]
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+pub use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{EnvFilter, fmt as format};
+#[cfg(feature = "ui")]
+use tracing_subscriber::{filter::filter_fn, layer::Context};
+
+/// Number of entries kept for the UI, older ones are dropped.
+#[cfg(feature = "ui")]
+const CAPACITY: usize = 1000;
+
+/// Whether messages go to the in-app buffer instead of the terminal.
+#[cfg(feature = "ui")]
+static CAPTURE: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "ui")]
+static ENTRIES: Mutex<VecDeque<Entry>> = Mutex::new(VecDeque::new());
+#[cfg(feature = "ui")]
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// A captured message, as shown in the UI log pane.
+#[cfg(feature = "ui")]
+#[derive(Clone)]
+pub struct Entry {
+    /// Time since logging was set up.
+    pub elapsed: Duration,
+    /// Severity of the message.
+    pub level: Level,
+    /// The formatted message, followed by the event's other fields.
+    pub message: String,
+}
+
+/// Installs the global subscriber, logging to journald when run as a systemd service.
+///
+/// `verbosity` shifts this crate's level from `INFO`, down to `TRACE` at 2 and up to `ERROR` at
+/// -2; `RUST_LOG` replaces it entirely, e.g. `RUST_LOG=backlight_mic::source=debug`. Chunk spans
+/// and their timings are logged at `TRACE`.
+pub fn init(verbosity: i8) {
+    let level = match verbosity {
+        ..=-2 => "error",
+        -1 => "warn",
+        0 => "info",
+        1 => "debug",
+        2.. => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,backlight_mic={level}")));
+
+    // Under systemd the journal takes the structured fields, stderr would only duplicate it.
+    let journald = journald();
+    let terminal = journald.is_none().then(|| {
+        format::layer()
+            .with_writer(std::io::stderr)
+            .with_ansi(std::io::stderr().is_terminal())
+            .without_time()
+            .with_target(verbosity > 0)
+            .with_span_events(FmtSpan::CLOSE)
+    });
+
+    #[cfg(feature = "ui")]
+    {
+        LazyLock::force(&START);
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(journald)
+            .with(terminal.with_filter(filter_fn(|_| !CAPTURE.load(Ordering::Relaxed))))
+            .with(Capture.with_filter(filter_fn(|_| CAPTURE.load(Ordering::Relaxed))))
+            .init();
+    }
+    #[cfg(not(feature = "ui"))]
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(journald)
+        .with(terminal)
+        .init();
+}
+
+/// Connects to journald when systemd connected stderr to the journal.
+fn journald() -> Option<tracing_journald::Layer> {
+    std::env::var_os("JOURNAL_STREAM")?;
+    match tracing_journald::layer() {
+        Ok(layer) => Some(
+            layer
+                .with_field_prefix(None)
+                .with_syslog_identifier("dynkbacklight".to_string()),
+        ),
+        // Nothing is logged yet, say it on stderr which ends up in the journal anyway.
+        Err(e) => {
+            eprintln!("Failed to connect to journald, logging to stderr: {e}");
+            None
+        }
+    }
+}
+
+/// Routes messages to the in-app buffer (`true`) or back to the terminal (`false`).
+#[cfg(feature = "ui")]
+pub fn set_capture(capture: bool) {
+    CAPTURE.store(capture, Ordering::Relaxed);
+}
+
+/// Returns a copy of the captured entries, oldest first.
+#[cfg(feature = "ui")]
+pub fn entries() -> Vec<Entry> {
+    ENTRIES.lock().unwrap().iter().cloned().collect()
+}
+
+/// Number of captured entries.
+#[cfg(feature = "ui")]
+pub fn count() -> usize {
+    ENTRIES.lock().unwrap().len()
+}
+
+/// Stores events in `ENTRIES`.
+#[cfg(feature = "ui")]
+struct Capture;
+
+#[cfg(feature = "ui")]
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Capture {
+    fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+        let mut message = String::new();
+        event.record(&mut Message(&mut message));
+
+        let mut entries = ENTRIES.lock().unwrap();
+        if entries.len() == CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            elapsed: START.elapsed(),
+            level: *event.metadata().level(),
+            message,
+        });
+    }
+}
+
+/// Formats an event's fields on one line, the message first.
+#[cfg(feature = "ui")]
+struct Message<'a>(&'a mut String);
+
+#[cfg(feature = "ui")]
+impl tracing::field::Visit for Message<'_> {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+        let _ = if field.name() == "message" {
+            write!(self.0, "{value:?}")
+        } else {
+            write!(self.0, " {}={value:?}", field.name())
+        };
+    }
+}
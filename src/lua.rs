@@ -0,0 +1,149 @@
+//! Custom brightness mappings written in Lua.
+//!
+//! `map.lua`, next to the config file, defines `map(rms, bands, state)`. It is called for every
+//! chunk with the level the pipeline drives with, the band magnitudes (an array, lowest frequency
+//! first, empty without samples) and a table kept from one call to the next. The pipeline fills
+//! `state` with `normalized`, `threshold`, `beat`, `brightness` (its own mapping, after the
+//! envelope) and `chunk_secs` before each call; everything else in it is the script's own.
+//!
+//! `map` returns the brightness in percent, a color like `"#ff8000"` (its brightest channel, the
+//! backlight has a single color), or `nil` to keep the pipeline's. A call may run at most
+//! [`MAX_INSTRUCTIONS`] instructions. The file is reloaded when it changes.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use mlua::{Function, HookTriggers, Lua, Table, Value, VmState};
+
+use crate::Chunk;
+use crate::config::{Config, color_brightness};
+use crate::error::Result;
+use crate::notify;
+use crate::watch::Watched;
+
+/// Instructions a call of `map` may run, far more than a mapping needs.
+pub const MAX_INSTRUCTIONS: u32 = 1_000_000;
+/// Instructions between two checks of [`MAX_INSTRUCTIONS`].
+const INSTRUCTIONS_CHECK: u32 = 10_000;
+
+/// A loaded `map.lua`.
+pub struct Mapping {
+    file: Watched,
+    /// Checks of the instruction count since the running call started.
+    checks: Arc<AtomicU32>,
+    lua: Lua,
+    map: Function,
+    state: Table,
+}
+
+impl Mapping {
+    /// Location of the script, `map.lua` next to the config file.
+    pub fn path() -> PathBuf {
+        Config::path().with_file_name("map.lua")
+    }
+
+    /// Loads the script at [`path`](Self::path), `None` if there is none.
+    pub fn load() -> Result<Option<Self>> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::open(path).map(Some)
+    }
+
+    /// Loads the script at `path`, reloaded from there when it changes.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let lua = Lua::new();
+        let checks = Arc::new(AtomicU32::new(0));
+        let counted = Arc::clone(&checks);
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(INSTRUCTIONS_CHECK),
+            move |_, _| {
+                if counted.fetch_add(1, Ordering::Relaxed) + 1
+                    >= MAX_INSTRUCTIONS / INSTRUCTIONS_CHECK
+                {
+                    return Err(mlua::Error::runtime(format!(
+                        "ran more than {MAX_INSTRUCTIONS} instructions"
+                    )));
+                }
+                Ok(VmState::Continue)
+            },
+        )?;
+        let file = Watched::new(path);
+        let map = compile(&lua, file.path())?;
+        let state = lua.create_table()?;
+        info!("Mapping the brightness with {}", file.path().display());
+        Ok(Self {
+            file,
+            checks,
+            lua,
+            map,
+            state,
+        })
+    }
+
+    /// The brightness the script maps `chunk` to, in percent; `None` to keep the pipeline's, also
+    /// when the script fails.
+    pub fn map(&mut self, chunk: &Chunk) -> Option<f32> {
+        self.reload();
+        match self.call(chunk) {
+            Ok(brightness) => brightness.map(|brightness| brightness.clamp(0.0, 100.0)),
+            Err(e) => {
+                notify::alert("lua", "The Lua mapping failed", &e.to_string());
+                None
+            }
+        }
+    }
+
+    fn call(&self, chunk: &Chunk) -> mlua::Result<Option<f32>> {
+        let bands = self.lua.create_sequence_from(chunk.bands.iter().copied())?;
+        self.state.set("normalized", chunk.normalized)?;
+        self.state.set("threshold", chunk.threshold)?;
+        self.state.set("beat", chunk.beat)?;
+        self.state.set("brightness", chunk.brightness)?;
+        self.state.set("chunk_secs", chunk.chunk_secs)?;
+        self.checks.store(0, Ordering::Relaxed);
+        let value: Value = self.map.call((chunk.rms, bands, &self.state))?;
+        Ok(match value {
+            Value::Nil => None,
+            Value::Integer(brightness) => Some(brightness as f32),
+            Value::Number(brightness) => Some(brightness as f32),
+            Value::String(color) => Some(color_brightness(&color.to_str()?).ok_or_else(|| {
+                mlua::Error::runtime(format!("`{}` is not a color like #ff8000", color.display()))
+            })?),
+            other => {
+                return Err(mlua::Error::runtime(format!(
+                    "map returned a {}, not a number or a color",
+                    other.type_name()
+                )));
+            }
+        })
+    }
+
+    /// Compiles the script again if it changed, keeping the state and the previous version if
+    /// it doesn't compile.
+    fn reload(&mut self) {
+        if !self.file.changed() {
+            return;
+        }
+        self.checks.store(0, Ordering::Relaxed);
+        match compile(&self.lua, self.file.path()) {
+            Ok(map) => {
+                info!("Reloaded {}", self.file.path().display());
+                self.map = map;
+            }
+            Err(e) => notify::alert("lua", "The Lua mapping failed to load", &e.to_string()),
+        }
+    }
+}
+
+/// Runs the script at `path` and returns its `map` function.
+fn compile(lua: &Lua, path: &Path) -> Result<Function> {
+    let source = fs::read_to_string(path).map_err(mlua::Error::external)?;
+    lua.load(source)
+        .set_name(format!("@{}", path.display()))
+        .exec()?;
+    Ok(lua.globals().get("map")?)
+}
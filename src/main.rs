@@ -1,200 +1,709 @@
-use std::collections::VecDeque;
-use std::fs::OpenOptions;
-use std::io::{self, Write};
-use std::process::exit;
-use std::thread::{self, sleep};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+#[macro_use]
+extern crate tracing;
+
+mod bench;
+mod cli;
+mod emit;
+#[cfg(feature = "gui")]
+mod gui;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "midi")]
+mod midi;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "notifications")]
+mod notifications;
+#[cfg(feature = "obs")]
+mod obs;
+#[cfg(feature = "osc")]
+mod osc;
+#[cfg(feature = "overlay")]
+mod overlay;
+mod permissions;
+mod record;
+mod sync;
+#[cfg(feature = "file")]
+mod timeline;
+#[cfg(feature = "tray")]
+mod tray;
+mod udp;
+#[cfg(feature = "ui")]
 mod ui;
 
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-
-const SAMPLE_RATE: u32 = 48_000;
-// Reactive
-const CHUNK_SIZE: Duration = Duration::from_millis(50);
+#[cfg(feature = "alsa")]
+use backlight_mic::alsa::AlsaSource;
+use backlight_mic::app::AppSource;
+use backlight_mic::calibration::{self, Calibration};
+#[cfg(feature = "file")]
+use backlight_mic::file::{FileSource, Pace};
+use backlight_mic::generator::Generator;
+use backlight_mic::preset::{self, Preset};
+use backlight_mic::sensor::{self, Sensor};
+use backlight_mic::source::{AudioSource, StdinSource};
+use backlight_mic::{
+    Levels, RmsState, SharedParams, Timing, calc_rms, config, config::LowPower, control,
+    error::Error, error::Result, find_input_device, helper, input_device_names, latency, log,
+    open_input, output, realtime, replay::Recording, restore, run_audio, run_source, sink,
+};
+use clap::Parser;
+use cpal::traits::{DeviceTrait, HostTrait};
+
+/// How long `test-sink` ramps up and down.
+const TEST_RAMP: Duration = Duration::from_secs(4);
+/// Time between the writes of `test-sink`.
+const TEST_STEP: Duration = Duration::from_millis(50);
+
+/// How long a panic waits for the brightness to be restored before exiting anyway.
+const PANIC_RESTORE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether a panic is being handled, see [`install_panic_hook`].
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "bench")]
+#[global_allocator]
+static ALLOCATOR: bench::Counting = bench::Counting;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-fn main() {
-    thread::spawn(|| {
-        ui::run_ui();
-    });
+fn run() -> Result<()> {
+    let args = cli::Args::parse();
+    log::init(args.verbose as i8 - args.quiet as i8);
+    install_panic_hook();
+
+    let config = config::Config::load().map_err(Error::Config)?;
+    // What runs, with the preset; `config` stays the file's for everything that saves it.
+    let started_preset = args.preset.map(cli::Preset::name);
+    let mut live = config.clone();
+    preset::apply_built_in(started_preset, &mut live);
+    if let Some(name) = started_preset {
+        info!("Starting with the {name} preset");
+    }
 
     let host = cpal::default_host();
 
-    match host.input_devices() {
-        Ok(devices) => {
-            for (i, device) in devices.enumerate() {
-                match device.name() {
-                    Ok(name) => println!("{i}. {name}"),
-                    Err(e) => eprintln!("{i}. Error getting name: {e}"),
-                }
-            }
+    // Only for display, a missing device is waited for by the audio thread.
+    let (input, device_name) = match &args.command {
+        Some(cli::Command::MeasureLatency) => {
+            return measure_latency(&host, config.device.as_deref(), config.timing());
         }
-        Err(e) => {
-            eprintln!("Failed to get input devices: {e}");
+        Some(cli::Command::Calibrate) => return calibrate(&host, config),
+        Some(cli::Command::Status { json }) => return print_status(*json),
+        Some(cli::Command::Bench { seconds }) => {
+            bench::run(&live, args.signal.into(), *seconds);
+            return Ok(());
+        }
+        Some(cli::Command::Set { percent }) => {
+            helper::set_enabled(config.helper);
+            let mut sink = sink::open(&config.sink).map_err(Error::Backlight)?;
+            return sink.set(*percent as f32).map_err(Error::Backlight);
+        }
+        Some(cli::Command::Get) => {
+            let mut sink = sink::open(&config.sink).map_err(Error::Backlight)?;
+            println!("{:.0}", sink.get().map_err(Error::Backlight)?);
+            return Ok(());
+        }
+        Some(cli::Command::TestSink { max }) => {
+            helper::set_enabled(config.helper);
+            let mut sink = sink::open(&config.sink).map_err(Error::Backlight)?;
+            return test_sink(sink.as_mut(), *max as f32);
         }
+        Some(cli::Command::Helper) => return helper::serve(),
+        Some(cli::Command::SetupPermissions { group, print }) => {
+            return permissions::setup(group.clone(), *print).map_err(Error::Permissions);
+        }
+        Some(cli::Command::Tap) => {
+            println!("{}", control::tap().map_err(Error::Control)?);
+            return Ok(());
+        }
+        Some(cli::Command::Load { kind, source }) => {
+            // The running instance has its own working directory.
+            let source = match kind {
+                cli::Effect::Lua | cli::Effect::Rhai => std::fs::canonicalize(source)
+                    .map_err(Error::Control)?
+                    .display()
+                    .to_string(),
+                cli::Effect::Plugin | cli::Effect::Mapping => source.clone(),
+            };
+            return control::load((*kind).into(), &source);
+        }
+        Some(cli::Command::Unload { kind }) => {
+            return control::unload((*kind).into());
+        }
+        Some(cli::Command::Preset { action }) => return preset(action, config),
+        #[cfg(feature = "file")]
+        Some(cli::Command::Process { file }) => return process(&live, file),
+        Some(cli::Command::Replay { file }) => (
+            Input::Replay(Recording::load(file)?),
+            format!("replay of {}", file.display()),
+        ),
+        #[cfg(feature = "file")]
+        Some(cli::Command::Run { file, mute }) => (
+            Input::Source(Box::new(FileSource::open(
+                file,
+                if *mute { Pace::Clock } else { Pace::Play },
+            )?)),
+            file.display().to_string(),
+        ),
+        None if let Some(group) = &args.follow => {
+            (Input::Follow(group.clone()), format!("leader on {group}"))
+        }
+        None if let Some(app) = &args.app => {
+            let source = AppSource::open(app)?;
+            let name = source.name();
+            (Input::Source(Box::new(source)), name)
+        }
+        None if args.input == cli::Input::Stdin => (
+            Input::Source(Box::new(StdinSource::new(
+                args.format.into(),
+                args.rate,
+                args.channels,
+            ))),
+            "stdin".to_string(),
+        ),
+        #[cfg(feature = "alsa")]
+        None if args.input == cli::Input::Alsa => {
+            let source = AlsaSource::open(config.device.as_deref().unwrap_or("default"))?;
+            let name = source.name();
+            (Input::Source(Box::new(source)), name)
+        }
+        #[cfg(feature = "obs")]
+        None if args.input == cli::Input::Obs => (
+            Input::Obs(config.obs.clone()),
+            format!("OBS on {}", config.obs.url),
+        ),
+        None if args.input == cli::Input::Generator => {
+            let generator = Generator::new(args.signal.into(), true);
+            let name = generator.name();
+            (Input::Source(Box::new(generator)), name)
+        }
+        #[cfg(feature = "null")]
+        None if let Some(device) = config
+            .device
+            .as_ref()
+            .filter(|device| backlight_mic::source::null_signal(device).is_some()) =>
+        {
+            (Input::Live(Some(device.clone())), device.clone())
+        }
+        None => (
+            Input::Live(config.device.clone()),
+            config
+                .device
+                .as_deref()
+                .and_then(|name| find_input_device(&host, name))
+                .or_else(|| host.default_input_device())
+                .and_then(|device| device.name().ok())
+                .unwrap_or_else(|| "none".to_string()),
+        ),
+    };
+
+    for (i, name) in input_device_names().iter().enumerate() {
+        info!("{i}. {name}");
     }
 
-    let device = host
-        .input_devices()
-        .expect("Failed to get input devices")
-        .find(|d| d.name().map(|name| name.contains("NTUSB")).unwrap_or(false))
-        .expect("No input devices available");
+    output::set_dry_run(args.dry_run);
+    helper::set_enabled(config.helper);
+    if !args.dry_run {
+        match sink::open(&config.sink) {
+            Ok(opened) => sink::select(opened),
+            Err(e) => warn!("Brightness sink unavailable, writing to sysfs: {e}"),
+        }
+        restore::save();
+    }
 
-    println!("Using input device: {}", device.name().unwrap());
+    let mut params = live.params();
+    params.voice_only = args.voice;
+    let params: SharedParams = Arc::new(Mutex::new(params));
+    let mut timing = config.timing();
+    // Attack and release are applied per chunk, replay with the recorded chunk size.
+    if let Input::Replay(recording) = &input
+        && let Some(chunk_size) = recording.chunk_size()
+    {
+        timing.chunk_size = chunk_size;
+    }
 
-    let mut supported_config_range = device
-        .supported_input_configs()
-        .expect("Error while querying configs");
+    let low_power = if args.low_power {
+        LowPower::On
+    } else {
+        config.low_power
+    };
+    params.lock().unwrap().low_power = low_power == LowPower::On;
+    for kind in &args.sensor {
+        match open_sensor(*kind) {
+            Ok(sensor) => sensor::spawn(sensor),
+            Err(e) => warn!("Sensor unavailable: {e}"),
+        }
+    }
 
-    let supported_config = supported_config_range
-        .next()
-        .expect("No supported config available")
-        .with_sample_rate(cpal::SampleRate(SAMPLE_RATE));
+    let (control, status) = control::spawn(
+        Arc::clone(&params),
+        low_power,
+        live.profile.clone(),
+        started_preset.map(str::to_string),
+        config.pause_during_calls,
+    );
+
+    #[cfg(feature = "tray")]
+    if args.tray {
+        tray::spawn(Arc::clone(&params), config.clone());
+    }
 
-    println!("sampleformat: {}", supported_config.sample_format());
-    println!("samplerate:   {}", supported_config.sample_format());
+    // Device names selected in the UI, the stream is rebuilt for each of them.
+    let (switch_tx, switch_rx) = mpsc::channel::<String>();
 
-    let mut buffer = Vec::new();
-    // Sample rate * duration in seconds = number of samples in duration.
-    let samples_per_chunk: usize = (SAMPLE_RATE as usize * CHUNK_SIZE.as_millis() as usize) / 1000;
+    // Everything that displays the live levels.
+    let mut levels = vec![status];
 
-    let mut state = RmsState::default();
-    state.min_rms = 0.0;
-    state.max_rms = 0.9;
+    if let Some(address) = &args.udp {
+        udp::spawn(address, Arc::clone(&params));
+    }
+    #[cfg(feature = "midi")]
+    if let Some(midi) = config.midi.clone() {
+        midi::spawn(midi, Arc::clone(&params));
+    }
+    #[cfg(feature = "notifications")]
+    if let Some(flash) = config.notifications.clone() {
+        notifications::spawn(flash, Arc::clone(&params));
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = config.mqtt.clone() {
+        levels.push(mqtt::spawn(mqtt, control.clone()));
+    }
+    #[cfg(feature = "osc")]
+    if let Some(address) = args.osc_in.clone() {
+        osc::listen(address, control.clone());
+    }
+    #[cfg(feature = "http")]
+    if let Some(address) = args.http.clone() {
+        levels.push(http::spawn(address, control));
+    }
+    #[cfg(not(feature = "http"))]
+    let _ = control;
 
-    let stream = device
-        .build_input_stream(
-            &supported_config.config(),
-            move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                buffer.extend_from_slice(data);
+    #[cfg(feature = "osc")]
+    if let Some(address) = args.osc_out.clone() {
+        levels.push(osc::spawn(address));
+    }
 
-                while buffer.len() >= samples_per_chunk {
-                    let chunk: Vec<f32> = buffer.drain(..samples_per_chunk).collect();
-                    process_audio_chunk(&chunk, &mut state);
-                }
-            },
-            move |err| {
-                eprintln!("Stream error: {err}");
-            },
-            None,
-        )
-        .expect("Failed to build input stream");
+    #[cfg(feature = "midi")]
+    if args.midi_out {
+        levels.push(midi::spawn_output());
+    }
+
+    if let Some(group) = args.lead.clone() {
+        levels.push(sync::lead(group));
+    }
+
+    if let Some(cli::Emit::Json) = args.emit {
+        levels.push(emit::spawn());
+    }
 
-    stream.play().expect("Failed to play stream");
+    if let Some(path) = &args.record {
+        levels.push(record::spawn(path).map_err(Error::Record)?);
+    }
+
+    #[cfg(feature = "overlay")]
+    if args.overlay {
+        levels.push(overlay::spawn());
+    }
 
-    loop {
-        sleep(Duration::from_secs(3600));
+    #[cfg(feature = "gui")]
+    if args.gui {
+        // The window has to own the main thread, the audio moves to its own.
+        let (levels_tx, levels_rx) = mpsc::channel();
+        levels.push(levels_tx);
+        let settings = gui::Settings {
+            device: device_name,
+            params: Arc::clone(&params),
+        };
+        std::thread::spawn(move || {
+            promote(args.realtime);
+            input.run(timing, levels, params, switch_rx)
+        });
+        gui::run(levels_rx, settings)?;
+        restore::restore();
+        return Ok(());
     }
+
+    // The JSON lines would be mixed into the terminal UI, which also draws on stdout.
+    #[cfg(feature = "ui")]
+    if !args.no_ui && args.emit.is_none() {
+        levels.push(ui::spawn(ui::Settings {
+            device: device_name,
+            pick_device: config.device.is_none() && matches!(input, Input::Live(_)),
+            sample_rate: backlight_mic::SAMPLE_RATE,
+            timing,
+            params: Arc::clone(&params),
+            config: config.clone(),
+            switch_device: switch_tx,
+        }));
+    }
+    // Without the `ui` feature there is nothing to show, `--no-ui` is implied.
+    #[cfg(not(feature = "ui"))]
+    let _ = (args.no_ui, switch_tx, device_name);
+
+    promote(args.realtime);
+    input.run(timing, levels, params, switch_rx);
+    // The input ended, the UI thread is still holding the terminal.
+    #[cfg(feature = "ui")]
+    ui::release_terminal();
+    restore::restore();
+    Ok(())
 }
 
-/// Keeps the state throughout the app's lifetime.
-struct RmsState {
-    moving_avg: MovingAverage,
-    max_rms: f32,
-    min_rms: f32,
-    current_brightness: f32,
+/// Runs `file` through the pipeline as fast as it decodes, printing its timeline on stdout.
+#[cfg(feature = "file")]
+fn process(config: &config::Config, file: &std::path::Path) -> Result<()> {
+    // Nothing to light up, the timeline is the output.
+    sink::select(Box::new(sink::Memory::new()));
+    let mut source = FileSource::open(file, Pace::Unpaced)?;
+    // One write per chunk, interpolating would depend on the clock.
+    let timing = Timing {
+        update_rate: None,
+        ..config.timing()
+    };
+
+    let (levels_tx, levels_rx) = mpsc::channel();
+    let writer = std::thread::spawn(move || {
+        timeline::write(levels_rx, timing.chunk_size, std::io::stdout().lock())
+    });
+    let mut state = RmsState::new(
+        timing,
+        vec![levels_tx],
+        Arc::new(Mutex::new(config.params())),
+    );
+    run_source(&mut source, &mut state)?;
+    // Ends the timeline.
+    drop(state);
+    writer
+        .join()
+        .expect("the timeline writer panicked")
+        .map_err(Error::Timeline)
 }
 
-impl Default for RmsState {
-    fn default() -> Self {
-        Self {
-            moving_avg: MovingAverage::new(10),
-            max_rms: f32::MIN, // assume initially we want any value to be greater
-            min_rms: f32::MAX, // assume initially we want any value to be smaller
-            current_brightness: 0.0f32,
-        }
-    }
+/// Where the levels come from.
+enum Input {
+    /// The named input device, or the default one.
+    Live(Option<String>),
+    /// A recording, played once.
+    Replay(Recording),
+    /// Any other source, e.g. raw PCM piped in, until it ends.
+    Source(Box<dyn AudioSource + Send>),
+    /// The frames of a leader instance on this multicast group.
+    Follow(String),
+    /// The audio mixer levels of this OBS instance.
+    #[cfg(feature = "obs")]
+    Obs(config::Obs),
 }
 
-impl RmsState {
-    /// Takes in a RMS value and updates the min and max.
-    fn update_rms_min_max(&mut self, value: f32) {
-        if value < self.min_rms {
-            self.min_rms = value;
-        }
-        if value > self.max_rms {
-            self.max_rms = value;
+impl Input {
+    /// Processes the input until it ends, which a live device never does.
+    fn run(
+        self,
+        timing: Timing,
+        levels: Vec<Sender<Levels>>,
+        params: SharedParams,
+        switch: Receiver<String>,
+    ) {
+        match self {
+            Self::Live(device) => run_audio(device, timing, levels, params, switch),
+            Self::Replay(recording) => {
+                recording.play(&mut RmsState::new(timing, levels, params));
+                info!("Replay finished");
+            }
+            Self::Source(mut source) => {
+                match run_source(source.as_mut(), &mut RmsState::new(timing, levels, params)) {
+                    Ok(()) => info!("{} ended", source.name()),
+                    Err(e) => error!("{e}"),
+                }
+            }
+            Self::Follow(group) => {
+                if let Err(e) = sync::follow(&group, &mut RmsState::new(timing, levels, params)) {
+                    error!("Failed to follow the leader on {group}: {e}");
+                }
+            }
+            #[cfg(feature = "obs")]
+            Self::Obs(config) => obs::follow(&config, &mut RmsState::new(timing, levels, params)),
         }
     }
 }
 
-/// A simple moving average calculator for real-time data.
-struct MovingAverage {
-    /// The number of items to average over.
-    size: usize,
-    /// The recent values used to compute the average.
-    window: VecDeque<f32>,
+/// Opens the sensor of `kind`.
+fn open_sensor(kind: cli::Sensor) -> std::io::Result<Box<dyn Sensor>> {
+    Ok(match kind {
+        cli::Sensor::Ambient => Box::new(sensor::Ambient::find()?),
+        cli::Sensor::Cpu => Box::new(sensor::Cpu::new()),
+        cli::Sensor::Disk => Box::new(sensor::Disk::new()),
+        cli::Sensor::Network => Box::new(sensor::Network::new()),
+        #[cfg(feature = "screen")]
+        cli::Sensor::Screen => Box::new(sensor::Screen::connect()?),
+        cli::Sensor::Typing => Box::new(sensor::Typing::open()?),
+    })
 }
 
-impl MovingAverage {
-    fn new(size: usize) -> Self {
-        Self {
-            size,
-            window: VecDeque::new(),
-        }
+/// Prints the status of the running instance.
+fn print_status(json: bool) -> Result<()> {
+    let status = control::query_status().map_err(Error::Control)?;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&status).unwrap_or_default()
+        );
+        return Ok(());
     }
 
-    fn update(&mut self, data: f32) -> &mut Self {
-        self.window.push_back(data);
-        if self.window.len() > self.size {
-            self.window.pop_front();
-        }
+    let mode = match (status.paused, status.low_power) {
+        _ if status.off => "forced off",
+        _ if status.muted => "microphone muted",
+        (true, _) => "paused",
+        (false, true) => "running (low power)",
+        (false, false) => "running",
+    };
+    let uptime = status.uptime;
+    println!("Device:     {}", status.device.as_deref().unwrap_or("none"));
+    if let Some(reason) = &status.unavailable {
+        println!("Input:      unavailable, {reason}");
+    }
+    if let Some(reason) = &status.degraded {
+        println!("Backlight:  degraded, {reason}");
+    }
+    println!("Mode:       {mode}");
+    println!("Profile:    {}", status.profile);
+    if let Some(tempo) = status.tempo {
+        println!("Tempo:      {tempo:.0} BPM");
+    }
+    println!(
+        "Level:      rms {:.3}, brightness {:.0}%",
+        status.rms, status.brightness
+    );
+    println!(
+        "Uptime:     {}h {:02}m {:02}s",
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60
+    );
+    println!(
+        "Last error: {}",
+        status.last_error.as_deref().unwrap_or("none")
+    );
+    Ok(())
+}
 
-        self
+/// Prints the latency of clicks played on the speakers and picked up by the input.
+fn measure_latency(host: &cpal::Host, device: Option<&str>, timing: Timing) -> Result<()> {
+    let mut input = open_input(host, device)?;
+    println!(
+        "Playing {} clicks, make sure the speakers are on...",
+        latency::CLICKS
+    );
+    let mut latencies = latency::measure(&mut input, timing)?;
+    latencies.sort();
+
+    let ms = |latency: Duration| latency.as_secs_f32() * 1000.0;
+    println!(
+        "Heard {} of {} clicks: median {:.0} ms, min {:.0} ms, max {:.0} ms ({} ms chunks)",
+        latencies.len(),
+        latency::CLICKS,
+        ms(latencies[latencies.len() / 2]),
+        ms(latencies[0]),
+        ms(latencies[latencies.len() - 1]),
+        timing.chunk_size.as_millis()
+    );
+    Ok(())
+}
+
+/// Ramps `sink` from 0 to `max` percent and back over [`TEST_RAMP`], reading every write back,
+/// then restores the brightness it had.
+fn test_sink(sink: &mut dyn sink::Sink, max: f32) -> Result<()> {
+    println!(
+        "Ramping {} from 0% to {max:.0}% and back over {} s",
+        sink.name(),
+        TEST_RAMP.as_secs()
+    );
+    let original = sink.get();
+    let steps = (TEST_RAMP.as_millis() / TEST_STEP.as_millis()) as usize;
+    let mut failed = 0;
+    let mut first_error = None;
+    let mut observed: Option<(f32, f32)> = None;
+    for step in 0..=steps {
+        // Up for the first half, down for the second.
+        let position = 1.0 - (2.0 * step as f32 / steps as f32 - 1.0).abs();
+        let result = sink.set(position * max).and_then(|()| sink.get());
+        match result {
+            Ok(read) => {
+                let (low, high) = observed.get_or_insert((read, read));
+                (*low, *high) = (low.min(read), high.max(read));
+            }
+            Err(e) => {
+                failed += 1;
+                first_error.get_or_insert(e);
+            }
+        }
+        std::thread::sleep(TEST_STEP);
+    }
+    if let Ok(original) = original {
+        let _ = sink.set(original);
     }
 
-    fn value(&self) -> f32 {
-        if self.window.is_empty() {
-            0.0
-        } else {
-            self.window.iter().sum::<f32>() / self.window.len() as f32
+    println!("{} writes, {failed} failed", steps + 1);
+    if let Some((low, high)) = observed {
+        println!("Read back {low:.0}% to {high:.0}%");
+    }
+    match first_error {
+        Some(e) if failed > steps => Err(Error::Backlight(e)),
+        Some(e) => {
+            println!("First error: {e}");
+            Ok(())
         }
+        None => Ok(()),
     }
 }
 
-/// Taes a chunk of audio data point (always the same length) and updates the keyboard backlights.
-fn process_audio_chunk(chunk: &[f32], state: &mut RmsState) {
-    let rms = calc_rms(chunk);
-    //state.update_rms_min_max(rms);
-    state.moving_avg.update(rms);
-
-    let min_rms = state.min_rms;
-    let max_rms = state.max_rms;
-
-    //let threshold = (state.moving_avg.value() * 1.5).max(1.0);
-    let threshold = state.moving_avg.value() * 1.4;
-    let normalized_rms: f32 = if max_rms > min_rms {
-        ((rms - min_rms) / (max_rms - min_rms)) * 100.0
-    } else {
-        0.0
+/// Walks through the calibration steps on the terminal, then stores the outcome in the active
+/// profile.
+fn calibrate(host: &cpal::Host, mut config: config::Config) -> Result<()> {
+    let mut input = open_input(host, config.device.as_deref())?;
+    let samples_per_chunk =
+        (input.sample_rate() as usize * config.timing().chunk_size.as_millis() as usize / 1000)
+            .max(1);
+    println!(
+        "Stay quiet for {} seconds...",
+        calibration::QUIET_DURATION.as_secs()
+    );
+    let mut calibration = Calibration::new();
+    let mut samples = Vec::new();
+    let outcome = 'chunks: loop {
+        if !input.read(&mut samples)? {
+            break Err("the input ended".to_string());
+        }
+        let step = calibration.step();
+        for chunk in samples.chunks_exact(samples_per_chunk) {
+            if let Some(outcome) = calibration.push(calc_rms(chunk)) {
+                break 'chunks outcome;
+            }
+        }
+        samples.drain(..samples.len() / samples_per_chunk * samples_per_chunk);
+        if step != calibration.step() {
+            println!(
+                "Now play loud music for {} seconds...",
+                calibration::LOUD_DURATION.as_secs()
+            );
+        }
     };
+    let calibrated = outcome.map_err(Error::Calibration)?;
+
+    config.calibrate(&calibrated);
+    config.save().map_err(Error::SaveConfig)?;
+    println!(
+        "Noise floor {:.3}, peak {:.3}, boost {:.2}, saved to the {} profile in {}",
+        calibrated.noise_floor,
+        calibrated.peak,
+        calibrated.boost,
+        config.profile,
+        config::Config::path().display()
+    );
+    Ok(())
+}
 
-    let boost: f32 = 1.6;
-
-    let boosted = normalized_rms.powf(boost); // 1.0 = linear, >1 = sensitive at low end
-    let brightness = (boosted).clamp(0.0, 100.0);
-
-    if rms > threshold {
-        state.current_brightness = brightness;
-        println!("Changing backlight to: {brightness:.02}!");
-        set_brightness(brightness).unwrap();
-    } else {
-        state.current_brightness -= 1.0;
-        set_brightness(state.current_brightness).unwrap();
+fn preset(action: &cli::PresetAction, mut config: config::Config) -> Result<()> {
+    match action {
+        cli::PresetAction::List => {
+            for (name, preset) in preset::list().map_err(Error::Preset)? {
+                println!("{name:<16}{}", preset.description);
+            }
+        }
+        cli::PresetAction::Save { name, description } => {
+            let preset = Preset {
+                description: description.clone(),
+                ..Preset::from_config(&config)
+            };
+            preset.save(name).map_err(Error::Preset)?;
+            println!("Saved the {} profile as the preset {name}", config.profile);
+        }
+        cli::PresetAction::Load { name } => {
+            Preset::load(name)
+                .map_err(Error::Preset)?
+                .apply(name, &mut config);
+            config.save().map_err(Error::SaveConfig)?;
+            println!(
+                "Applied the preset {name} to {}",
+                config::Config::path().display()
+            );
+        }
+        cli::PresetAction::Export { name, file } => {
+            let preset = Preset::load(name).map_err(Error::Preset)?;
+            preset::export(&preset, name, file).map_err(Error::Preset)?;
+        }
+        cli::PresetAction::Import { file, name } => {
+            let (exported, preset) = preset::import(file).map_err(Error::Preset)?;
+            let name = match name.clone().or(exported) {
+                Some(name) => name,
+                None => file
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            };
+            preset.save(&name).map_err(Error::Preset)?;
+            println!("Imported {} as the preset {name}", file.display());
+        }
     }
+    Ok(())
 }
 
-/// Sets the brightness of the keyboard backlight.
-fn set_brightness(level: f32) -> io::Result<()> {
-    let level_whole: u8 = level as u8;
-    let path: &str = "/sys/class/leds/chromeos::kbd_backlight/brightness";
+/// Gives the audio processing thread realtime priority if `enabled`, only warning on failure.
+fn promote(enabled: bool) {
+    if !enabled {
+        return;
+    }
+    match realtime::promote() {
+        Ok(()) => info!("Processing audio with realtime priority"),
+        Err(e) => warn!("{e}"),
+    }
+}
 
-    let mut file = OpenOptions::new().write(true).open(path)?;
-    file.write_all(level_whole.to_string().as_bytes())?;
-    Ok(())
+/// Makes a panic on any thread, the UI's included, release the terminal, log the panic, restore the
+/// brightness and exit, rather than leave the keyboard stuck while the other threads carry on.
+fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // A panic while restoring only gets the default report.
+        if PANICKED.swap(true, Ordering::SeqCst) {
+            default(info);
+            return;
+        }
+        #[cfg(feature = "ui")]
+        ui::release_terminal();
+        error!("{info}");
+        // From another thread, the panicking one may hold the sink's lock.
+        let (done_tx, done_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            restore::restore();
+            let _ = done_tx.send(());
+        });
+        let _ = done_rx.recv_timeout(PANIC_RESTORE_TIMEOUT);
+        std::process::exit(101);
+    }));
 }
 
-fn calc_rms(data: &[f32]) -> f32 {
-    let mean_squares = data.iter().map(|x| x * x).sum::<f32>() / data.len() as f32;
-    mean_squares.sqrt()
+/// Exits the process, leaving the terminal usable if the UI had taken it over.
+#[cfg(feature = "tray")]
+pub fn quit() -> ! {
+    #[cfg(feature = "ui")]
+    ui::release_terminal();
+    restore::restore();
+    std::process::exit(0);
 }
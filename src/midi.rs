@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use midir::os::unix::VirtualOutput;
+use midir::{MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputConnection};
+
+use backlight_mic::config::Midi;
+use backlight_mic::{Levels, RETRY_DELAY, SharedParams};
+
+/// Status byte of a control change, without the channel.
+const CONTROL_CHANGE: u8 = 0xb0;
+/// Status byte of a note on, without the channel.
+const NOTE_ON: u8 = 0x90;
+/// Status byte of a note off, without the channel.
+const NOTE_OFF: u8 = 0x80;
+/// Controller the brightness envelope is sent on, undefined in the MIDI spec.
+const ENVELOPE_CONTROL: u8 = 20;
+/// Note played on beats, C1, the kick drum in General MIDI.
+const BEAT_NOTE: u8 = 36;
+/// Name of the virtual output port.
+const OUTPUT_PORT: &str = "dynkbacklight";
+
+/// Connects to the controller from a background thread and applies its control changes to
+/// `params`.
+///
+/// A missing controller is waited for, it never brings down the rest of the program.
+pub fn spawn(config: Midi, params: SharedParams) {
+    thread::spawn(move || {
+        let mut reported = false;
+        loop {
+            match connect(&config, &params) {
+                Ok(connection) => {
+                    // The controller is only listened to while the connection lives.
+                    let _connection = connection;
+                    loop {
+                        thread::park();
+                    }
+                }
+                Err(e) => {
+                    if !reported {
+                        warn!("MIDI controller unavailable, waiting for it: {e}");
+                        reported = true;
+                    }
+                    thread::sleep(RETRY_DELAY);
+                }
+            }
+        }
+    });
+}
+
+fn connect(config: &Midi, params: &SharedParams) -> Result<MidiInputConnection<()>, String> {
+    let input = MidiInput::new("dynkbacklight").map_err(|e| e.to_string())?;
+    let port = find_port(&input, config.port.as_deref()).ok_or("no matching MIDI input port")?;
+    let name = input.port_name(&port).unwrap_or_default();
+
+    let config = config.clone();
+    let params = Arc::clone(params);
+    let connection = input
+        .connect(
+            &port,
+            "dynkbacklight",
+            move |_, message, _| {
+                if let [status, control, value] = *message
+                    && status & 0xf0 == CONTROL_CHANGE
+                {
+                    apply(&config, &params, control, value);
+                }
+            },
+            (),
+        )
+        .map_err(|e| e.to_string())?;
+    info!("Listening to the MIDI controller {name}");
+    Ok(connection)
+}
+
+/// The first input port whose name contains `name`, or the first one.
+fn find_port(input: &MidiInput, name: Option<&str>) -> Option<MidiInputPort> {
+    let ports = input.ports();
+    match name {
+        Some(name) => ports.into_iter().find(|port| {
+            input
+                .port_name(port)
+                .is_ok_and(|port_name| port_name.contains(name))
+        }),
+        None => ports.into_iter().next(),
+    }
+}
+
+/// Maps the 0-127 `value` of `control` onto the range of the parameter it is assigned to, the
+/// same ranges the terminal UI allows.
+fn apply(config: &Midi, params: &SharedParams, control: u8, value: u8) {
+    let position = value as f32 / 127.0;
+    let mut params = params.lock().unwrap();
+    if config.boost == Some(control) {
+        params.boost = 0.1 + position * (5.0 - 0.1);
+    }
+    if config.threshold == Some(control) {
+        params.threshold_factor = 0.5 + position * (5.0 - 0.5);
+    }
+    if config.max_brightness == Some(control) {
+        params.max_brightness = position * 100.0;
+    }
+}
+
+/// Opens a virtual output port and sends every chunk to it from its own thread, returning where
+/// to send the levels.
+///
+/// The brightness goes out as [`ENVELOPE_CONTROL`] on channel 1 whenever it changes, and every
+/// beat as a [`BEAT_NOTE`] whose velocity follows the level. Failing to open the port is logged,
+/// it never brings down the rest of the program.
+pub fn spawn_output() -> Sender<Levels> {
+    let (levels_tx, levels_rx) = mpsc::channel();
+    let connection = MidiOutput::new("dynkbacklight")
+        .map_err(|e| e.to_string())
+        .and_then(|output| {
+            output
+                .create_virtual(OUTPUT_PORT)
+                .map_err(|e| e.to_string())
+        });
+    match connection {
+        Ok(connection) => {
+            info!("Sending MIDI on the virtual port {OUTPUT_PORT}");
+            thread::spawn(move || send(levels_rx, connection));
+        }
+        Err(e) => error!("Failed to open the MIDI output port: {e}"),
+    }
+    levels_tx
+}
+
+fn send(levels: Receiver<Levels>, mut connection: MidiOutputConnection) {
+    let mut envelope = None;
+    let mut beat = false;
+    for levels in levels {
+        let value = (levels.brightness / 100.0 * 127.0)
+            .round()
+            .clamp(0.0, 127.0) as u8;
+        if envelope != Some(value) {
+            envelope = Some(value);
+            let _ = connection.send(&[CONTROL_CHANGE, ENVELOPE_CONTROL, value]);
+        }
+
+        if levels.beat != beat {
+            beat = levels.beat;
+            let message = if beat {
+                // A zero velocity would be taken as a note off.
+                let velocity = (levels.normalized * 127.0).round().clamp(1.0, 127.0) as u8;
+                [NOTE_ON, BEAT_NOTE, velocity]
+            } else {
+                [NOTE_OFF, BEAT_NOTE, 0]
+            };
+            // Nobody connected to the port is not an error.
+            let _ = connection.send(&message);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use backlight_mic::config::Mqtt;
+use backlight_mic::control::Handle;
+use backlight_mic::{Levels, RETRY_DELAY};
+
+/// How often the state is published.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Published, retained, on `<topic>/state`.
+#[derive(Serialize)]
+struct State {
+    /// `OFF` while forced off.
+    state: &'static str,
+    brightness: u8,
+    /// RMS level within the calibrated range, 0.0-1.0.
+    level: f32,
+    rms: f32,
+    profile: String,
+    low_power: bool,
+}
+
+/// Accepted on `<topic>/set`, every field is optional.
+#[derive(Deserialize)]
+struct Command {
+    /// `OFF` forces the backlight off, `ON` lets it follow the level again.
+    state: Option<String>,
+    profile: Option<String>,
+    low_power: Option<bool>,
+}
+
+/// Connects to the broker from background threads and returns where to send the levels.
+///
+/// Connection failures are logged and retried, they never bring down the rest of the program.
+pub fn spawn(config: Mqtt, control: Handle) -> Sender<Levels> {
+    let (levels_tx, levels_rx) = mpsc::channel();
+
+    let availability = format!("{}/availability", config.topic);
+    // Instances sharing a broker under different topics must not kick each other off.
+    let mut options = MqttOptions::new(id(&config), &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        &availability,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+    }
+    let (client, mut connection) = Client::new(options, 16);
+
+    let command_topic = format!("{}/set", config.topic);
+    let state_topic = format!("{}/state", config.topic);
+    let subscriber = client.clone();
+    let commands = control.clone();
+    thread::spawn(move || {
+        for event in connection.iter() {
+            match event {
+                // Subscriptions don't survive a reconnection. Queued without waiting for room:
+                // this thread is the one sending the requests, waiting would deadlock it.
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!(
+                        "Connected to the MQTT broker {}:{}",
+                        config.host, config.port
+                    );
+                    let _ = subscriber.try_subscribe(&command_topic, QoS::AtLeastOnce);
+                    let _ = subscriber.try_publish(&availability, QoS::AtLeastOnce, true, "online");
+                    if config.discovery {
+                        announce(&subscriber, &config);
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == command_topic => {
+                    match serde_json::from_slice::<Command>(&publish.payload) {
+                        Ok(command) => apply(command, &commands),
+                        Err(e) => warn!("Ignoring malformed MQTT command: {e}"),
+                    }
+                }
+                Ok(_) => {}
+                // Reconnects on the next iteration.
+                Err(e) => {
+                    warn!("MQTT connection failed: {e}");
+                    thread::sleep(RETRY_DELAY);
+                }
+            }
+        }
+    });
+
+    thread::spawn(move || publish(levels_rx, &client, &state_topic, &control));
+    levels_tx
+}
+
+/// Publishes the state about every `PUBLISH_INTERVAL`, from the latest levels.
+fn publish(levels: Receiver<Levels>, client: &Client, topic: &str, control: &Handle) {
+    let mut published = Instant::now() - PUBLISH_INTERVAL;
+    for levels in levels {
+        if published.elapsed() < PUBLISH_INTERVAL {
+            continue;
+        }
+        published = Instant::now();
+
+        let status = control.status();
+        let state = State {
+            state: if status.off { "OFF" } else { "ON" },
+            brightness: if status.off {
+                0
+            } else {
+                levels.brightness as u8
+            },
+            level: levels.normalized,
+            rms: levels.rms,
+            profile: status.profile,
+            low_power: status.low_power,
+        };
+        let payload = serde_json::to_vec(&state).unwrap_or_default();
+        // Only fails once the connection thread is gone, the next chunk tries again.
+        let _ = client.try_publish(topic, QoS::AtMostOnce, true, payload);
+    }
+}
+
+/// Publishes the Home Assistant discovery payloads: a light that can be forced off, and sensors
+/// for the level and the brightness.
+fn announce(client: &Client, config: &Mqtt) {
+    let topic = &config.topic;
+    let id = id(config);
+    let device = json!({
+        "identifiers": [id],
+        "name": "Keyboard backlight",
+        "manufacturer": "DynKBacklight",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    let entities = [
+        (
+            "light",
+            "light",
+            json!({
+                "name": "Keyboard backlight",
+                "schema": "json",
+                "command_topic": format!("{topic}/set"),
+            }),
+        ),
+        (
+            "sensor",
+            "level",
+            json!({
+                "name": "Sound level",
+                "unit_of_measurement": "%",
+                "value_template": "{{ (value_json.level * 100) | round(0) }}",
+            }),
+        ),
+        (
+            "sensor",
+            "brightness",
+            json!({
+                "name": "Keyboard brightness",
+                "unit_of_measurement": "%",
+                "value_template": "{{ value_json.brightness }}",
+            }),
+        ),
+    ];
+
+    for (component, object, mut payload) in entities {
+        payload["unique_id"] = json!(format!("{id}_{object}"));
+        payload["state_topic"] = json!(format!("{topic}/state"));
+        payload["availability_topic"] = json!(format!("{topic}/availability"));
+        payload["device"] = device.clone();
+        let discovery = format!(
+            "{}/{component}/{id}/{object}/config",
+            config.discovery_prefix
+        );
+        let _ = client.try_publish(discovery, QoS::AtLeastOnce, true, payload.to_string());
+    }
+}
+
+/// Identifies the instance to the broker and Home Assistant, from its topic: several instances
+/// can share a broker under different topics.
+fn id(config: &Mqtt) -> String {
+    config.topic.replace('/', "_")
+}
+
+fn apply(command: Command, control: &Handle) {
+    if let Some(name) = command.profile
+        && let Err(e) = control.select_profile(&name)
+    {
+        warn!("MQTT profile switch failed: {e}");
+    }
+    let mut params = control.params().lock().unwrap();
+    if let Some(low_power) = command.low_power {
+        params.low_power = low_power;
+    }
+    match command.state.as_deref() {
+        Some("OFF") => {
+            params.off = true;
+            info!("Backlight forced off over MQTT");
+        }
+        Some("ON") => {
+            params.off = false;
+            info!("Backlight follows the level again");
+        }
+        Some(other) => warn!("Ignoring unknown MQTT state {other}"),
+        None => {}
+    }
+}
@@ -0,0 +1,68 @@
+//! The microphone's mute switch, as PulseAudio or PipeWire report it.
+//!
+//! Muted samples are rarely all zeros, residual noise would keep the keyboard flickering, so a
+//! muted microphone is taken as silence. The state is polled with `pactl` for the default
+//! source, which the hardware mic-mute key toggles on most desktops.
+
+use std::process::{Command, Stdio};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How often the mute state is polled.
+const POLL: Duration = Duration::from_millis(500);
+
+static MUTED: AtomicBool = AtomicBool::new(false);
+static WATCH: Once = Once::new();
+
+/// Whether the default source is muted, `false` until [`watch`] is called.
+pub fn muted() -> bool {
+    MUTED.load(Ordering::Relaxed)
+}
+
+/// Follows the default source's mute state from a background thread, once however many times it
+/// is called.
+///
+/// Without `pactl`, or without a sound server, the microphone is never considered muted.
+pub fn watch() {
+    WATCH.call_once(|| {
+        thread::spawn(|| {
+            loop {
+                match query() {
+                    Some(muted) => {
+                        if MUTED.swap(muted, Ordering::Relaxed) != muted {
+                            info!("Microphone {}", if muted { "muted" } else { "unmuted" });
+                        }
+                    }
+                    None => {
+                        debug!("The microphone's mute state is unknown, ignoring it");
+                        MUTED.store(false, Ordering::Relaxed);
+                        return;
+                    }
+                }
+                thread::sleep(POLL);
+            }
+        });
+    });
+}
+
+/// Asks the sound server whether the default source is muted, `None` if it can't tell.
+fn query() -> Option<bool> {
+    let output = Command::new("pactl")
+        .args(["get-source-mute", "@DEFAULT_SOURCE@"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    // `Mute: yes`, translated in some locales, hence only the last word.
+    match String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .last()
+    {
+        Some("yes") => Some(true),
+        Some("no") => Some(false),
+        _ => None,
+    }
+}
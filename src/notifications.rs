@@ -0,0 +1,60 @@
+use std::thread;
+use std::time::Instant;
+
+use zbus::MatchRule;
+use zbus::blocking::fdo::MonitoringProxy;
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::message::Type;
+
+use backlight_mic::config::Flash;
+use backlight_mic::{Flashing, RETRY_DELAY, SharedParams};
+
+/// Interface desktop notifications are sent to.
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+/// Watches the session bus from a background thread and plays `flash` over the backlight
+/// whenever a desktop notification is sent.
+///
+/// The bus is reconnected to when it goes away, it never brings down the rest of the program.
+pub fn spawn(flash: Flash, params: SharedParams) {
+    thread::spawn(move || {
+        let mut reported = false;
+        loop {
+            match watch(&flash, &params) {
+                Ok(()) => warn!("Stopped watching desktop notifications, the bus went away"),
+                Err(e) if !reported => {
+                    warn!("Failed to watch desktop notifications, retrying: {e}");
+                    reported = true;
+                }
+                Err(_) => {}
+            }
+            thread::sleep(RETRY_DELAY);
+        }
+    });
+}
+
+/// Flashes for every `Notify` call until the connection ends.
+fn watch(flash: &Flash, params: &SharedParams) -> zbus::Result<()> {
+    let connection = Connection::session()?;
+    // The notifications are calls to the notification daemon, only a monitor sees them.
+    let rule = MatchRule::builder()
+        .msg_type(Type::MethodCall)
+        .interface(INTERFACE)?
+        .member("Notify")?
+        .build();
+    MonitoringProxy::new(&connection)?.become_monitor(&[rule], 0)?;
+    info!("Flashing on desktop notifications");
+
+    for message in MessageIterator::from(&connection) {
+        let message = message?;
+        let header = message.header();
+        if header.member().is_some_and(|member| member == "Notify") {
+            debug!("Desktop notification, flashing");
+            params.lock().unwrap().flash = Some(Flashing {
+                flash: flash.clone(),
+                started: Instant::now(),
+            });
+        }
+    }
+    Ok(())
+}
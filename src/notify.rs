@@ -0,0 +1,60 @@
+//! Desktop notifications for errors that need the user's attention.
+
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Minimum time between two alerts of the same kind.
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+/// When each kind of alert was last raised.
+static LAST_SENT: LazyLock<Mutex<HashMap<&'static str, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+/// The most recent alert, including those not shown because of the cooldown.
+static LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// The most recent alert as `summary: body`, if any was raised.
+pub fn last_error() -> Option<String> {
+    LAST_ERROR.lock().unwrap().clone()
+}
+
+/// Logs an error and shows it as a desktop notification, at most once per `COOLDOWN` per `kind`.
+///
+/// Notifications go through `notify-send`; if it is missing the message is only logged.
+pub fn alert(kind: &'static str, summary: &str, body: &str) {
+    *LAST_ERROR.lock().unwrap() = Some(format!("{summary}: {body}"));
+    {
+        let mut last_sent = LAST_SENT.lock().unwrap();
+        let now = Instant::now();
+        if last_sent
+            .get(kind)
+            .is_some_and(|sent| now.duration_since(*sent) < COOLDOWN)
+        {
+            return;
+        }
+        last_sent.insert(kind, now);
+    }
+
+    error!(kind, error = body, "{summary}: {body}");
+
+    // Don't wait for it, this may be called from the audio callback.
+    let spawned = Command::new("notify-send")
+        .args([
+            "--app-name=DynKBacklight",
+            "--urgency=critical",
+            summary,
+            body,
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match spawned {
+        // Reap it in the background so it doesn't linger as a zombie.
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => warn!("Failed to run notify-send: {e}"),
+    }
+}
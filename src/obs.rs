@@ -0,0 +1,198 @@
+use std::io;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+use backlight_mic::config::Obs;
+use backlight_mic::{RETRY_DELAY, RmsState, process_level};
+
+/// Version of the obs-websocket RPC spoken, the one of obs-websocket 5.
+const RPC_VERSION: u32 = 1;
+/// Subscription to the `InputVolumeMeters` events, which OBS only sends when asked.
+const INPUT_VOLUME_METERS: u32 = 1 << 16;
+/// How long without meters before the keyboard goes dark, OBS sends them every 50 ms.
+const METERS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Op code of OBS's first message, which may ask for a password.
+const HELLO: u8 = 0;
+/// Op code of the answer to the hello, with the subscriptions.
+const IDENTIFY: u8 = 1;
+/// Op code of OBS accepting the identification.
+const IDENTIFIED: u8 = 2;
+/// Op code of the events subscribed to.
+const EVENT: u8 = 5;
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// A message from OBS.
+#[derive(Deserialize)]
+struct Envelope {
+    op: u8,
+    d: Value,
+}
+
+/// The levels of the mixer's inputs, sent every 50 ms.
+#[derive(Deserialize)]
+struct Meters {
+    inputs: Vec<Meter>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meter {
+    input_name: String,
+    /// Magnitude, peak and pre-fader peak of each channel, after the input's filters and volume.
+    input_levels_mul: Vec<Vec<f32>>,
+}
+
+/// Drives the pipeline from the mixer levels of the OBS instance `config` names, forever.
+///
+/// OBS not running or going away is waited for, only the first failure in a row is logged.
+pub fn follow(config: &Obs, state: &mut RmsState) {
+    let mut reported = false;
+    loop {
+        match connect(config) {
+            Ok(mut socket) => {
+                info!("Following the OBS audio mixer on {}", config.url);
+                reported = false;
+                let e = meter(&mut socket, config, state);
+                warn!("Lost OBS, waiting for it: {e}");
+                process_level(0.0, state);
+            }
+            Err(e) => {
+                if !reported {
+                    warn!("OBS unavailable on {}, waiting for it: {e}", config.url);
+                    reported = true;
+                }
+            }
+        }
+        thread::sleep(RETRY_DELAY);
+    }
+}
+
+/// Connects to obs-websocket and subscribes to the volume meters.
+fn connect(config: &Obs) -> Result<Socket, String> {
+    let (mut socket, _) = tungstenite::connect(&config.url).map_err(|e| e.to_string())?;
+    if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+        stream
+            .set_read_timeout(Some(METERS_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+    }
+
+    let hello = next(&mut socket)?.ok_or("OBS sent no hello")?;
+    if hello.op != HELLO {
+        return Err("OBS sent no hello".to_string());
+    }
+    let mut identify = json!({
+        "rpcVersion": RPC_VERSION,
+        "eventSubscriptions": INPUT_VOLUME_METERS,
+    });
+    if let Some(auth) = hello.d.get("authentication") {
+        let password = config
+            .password
+            .as_deref()
+            .ok_or("OBS asks for a password, set `password` in the `[obs]` section")?;
+        let (Some(challenge), Some(salt)) = (auth["challenge"].as_str(), auth["salt"].as_str())
+        else {
+            return Err("OBS sent a malformed authentication challenge".to_string());
+        };
+        identify["authentication"] = authentication(password, salt, challenge).into();
+    }
+    socket
+        .send(Message::text(
+            json!({"op": IDENTIFY, "d": identify}).to_string(),
+        ))
+        .map_err(|e| e.to_string())?;
+
+    match next(&mut socket)? {
+        Some(identified) if identified.op == IDENTIFIED => Ok(socket),
+        _ => Err("OBS did not accept the connection".to_string()),
+    }
+}
+
+/// The answer to OBS's challenge: `base64(sha256(base64(sha256(password + salt)) + challenge))`.
+fn authentication(password: &str, salt: &str, challenge: &str) -> String {
+    let secret = BASE64.encode(Sha256::digest(format!("{password}{salt}")));
+    BASE64.encode(Sha256::digest(format!("{secret}{challenge}")))
+}
+
+/// Feeds the level of every meters event to the pipeline until the connection fails, returning
+/// why.
+fn meter(socket: &mut Socket, config: &Obs, state: &mut RmsState) -> String {
+    loop {
+        let event = match next(socket) {
+            Ok(Some(event)) => event,
+            Ok(None) => {
+                process_level(0.0, state);
+                continue;
+            }
+            Err(e) => return e,
+        };
+        if event.op != EVENT || event.d["eventType"] != "InputVolumeMeters" {
+            continue;
+        }
+        match Meters::deserialize(&event.d["eventData"]) {
+            Ok(meters) => process_level(level(&meters.inputs, &config.inputs), state),
+            Err(e) => warn!("Ignoring malformed meters: {e}"),
+        }
+    }
+}
+
+/// RMS level of the mix of the `followed` inputs among `meters`, all of them if empty: each
+/// input's magnitude is averaged over its channels, and the inputs add up as uncorrelated
+/// sounds do.
+fn level(meters: &[Meter], followed: &[String]) -> f32 {
+    meters
+        .iter()
+        .filter(|meter| followed.is_empty() || followed.contains(&meter.input_name))
+        .map(|meter| {
+            let channels = meter.input_levels_mul.len().max(1) as f32;
+            let power: f32 = meter
+                .input_levels_mul
+                .iter()
+                .filter_map(|channel| channel.first())
+                .map(|magnitude| magnitude * magnitude)
+                .sum();
+            power / channels
+        })
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// The next message from OBS, `None` if none came within [`METERS_TIMEOUT`].
+fn next(socket: &mut Socket) -> Result<Option<Envelope>, String> {
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                return serde_json::from_str(&text)
+                    .map(Some)
+                    .map_err(|e| format!("malformed message: {e}"));
+            }
+            Ok(Message::Close(frame)) => {
+                return Err(match frame {
+                    Some(frame) => format!("OBS closed the connection: {}", frame.reason),
+                    None => "OBS closed the connection".to_string(),
+                });
+            }
+            // Pings are answered by tungstenite.
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None);
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::io;
+use std::net::UdpSocket;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use rosc::{OscMessage, OscPacket, OscType, decoder, encoder};
+
+use backlight_mic::Levels;
+use backlight_mic::control::Handle;
+
+/// Prefix of every OSC address.
+const PREFIX: &str = "/dynkb";
+
+/// Lowest and highest threshold factor `/dynkb/sensitivity` maps to, as the terminal UI allows.
+const THRESHOLD_RANGE: (f32, f32) = (0.5, 5.0);
+
+/// Sends every chunk's levels as OSC messages to `address` from its own thread, returning where
+/// to send them.
+///
+/// Failing to resolve the address is logged, it never brings down the rest of the program.
+pub fn spawn(address: String) -> Sender<Levels> {
+    let (levels_tx, levels_rx) = mpsc::channel();
+    let socket = match connect(&address) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to send OSC to {address}: {e}");
+            return levels_tx;
+        }
+    };
+    info!("Sending OSC to {address}");
+
+    thread::spawn(move || send(levels_rx, &socket));
+    levels_tx
+}
+
+fn connect(address: &str) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(address)?;
+    Ok(socket)
+}
+
+/// Sends `/dynkb/level` and one `/dynkb/band/N` per band for every chunk, and `/dynkb/beat` when
+/// a beat starts (1) or ends (0).
+fn send(levels: Receiver<Levels>, socket: &UdpSocket) {
+    let mut beat = false;
+    for levels in levels {
+        let mut messages = vec![message("level", OscType::Float(levels.normalized))];
+        for (i, band) in levels.bands.iter().enumerate() {
+            messages.push(message(&format!("band/{i}"), OscType::Float(*band)));
+        }
+        if levels.beat != beat {
+            beat = levels.beat;
+            messages.push(message("beat", OscType::Int(beat as i32)));
+        }
+
+        for packet in messages {
+            // Nobody listening is not an error, VJ software comes and goes.
+            if let Ok(bytes) = encoder::encode(&packet) {
+                let _ = socket.send(&bytes);
+            }
+        }
+    }
+}
+
+fn message(name: &str, arg: OscType) -> OscPacket {
+    OscPacket::Message(OscMessage {
+        addr: format!("{PREFIX}/{name}"),
+        args: vec![arg],
+    })
+}
+
+/// Applies the OSC messages received on `address` from its own thread.
+///
+/// Failing to listen is logged, it never brings down the rest of the program.
+pub fn listen(address: String, control: Handle) {
+    let socket = match UdpSocket::bind(&address) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to listen for OSC on {address}: {e}");
+            return;
+        }
+    };
+    info!("Listening for OSC on {address}");
+
+    thread::spawn(move || {
+        let mut buf = [0; decoder::MTU];
+        loop {
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("Failed to receive OSC: {e}");
+                    continue;
+                }
+            };
+            match decoder::decode_udp(&buf[..len]) {
+                Ok((_, packet)) => receive(packet, &control),
+                Err(e) => warn!("Ignoring malformed OSC packet: {e:?}"),
+            }
+        }
+    });
+}
+
+/// Applies a message, or every message of a bundle.
+fn receive(packet: OscPacket, control: &Handle) {
+    match packet {
+        OscPacket::Message(message) => apply(message, control),
+        OscPacket::Bundle(bundle) => {
+            for packet in bundle.content {
+                receive(packet, control);
+            }
+        }
+    }
+}
+
+/// Handles `/dynkb/sensitivity` (0.0-1.0, 1.0 triggers on the faintest rise), `/dynkb/mode`
+/// (`on`, `off` or `paused`) and `/dynkb/profile` (a profile name).
+fn apply(message: OscMessage, control: &Handle) {
+    let Some(name) = message.addr.strip_prefix(PREFIX) else {
+        return;
+    };
+    let arg = message.args.into_iter().next();
+    match (name, arg) {
+        ("/sensitivity", Some(arg)) => {
+            let Some(sensitivity) = number(arg) else {
+                warn!("Ignoring OSC sensitivity that is not a number");
+                return;
+            };
+            let (lowest, highest) = THRESHOLD_RANGE;
+            control.params().lock().unwrap().threshold_factor =
+                highest - sensitivity.clamp(0.0, 1.0) * (highest - lowest);
+        }
+        ("/mode", Some(OscType::String(mode))) => {
+            let mut params = control.params().lock().unwrap();
+            match mode.as_str() {
+                "on" => (params.off, params.paused) = (false, false),
+                "off" => (params.off, params.paused) = (true, false),
+                "paused" => (params.off, params.paused) = (false, true),
+                other => warn!("Ignoring unknown OSC mode {other}"),
+            }
+        }
+        ("/profile", Some(OscType::String(profile))) => {
+            if let Err(e) = control.select_profile(&profile) {
+                warn!("OSC profile switch failed: {e}");
+            }
+        }
+        (other, _) => debug!("Ignoring OSC message {PREFIX}{other}"),
+    }
+}
+
+/// The value of a numeric argument, control surfaces send either floats or ints.
+fn number(arg: OscType) -> Option<f32> {
+    match arg {
+        OscType::Float(value) => Some(value),
+        OscType::Double(value) => Some(value as f32),
+        OscType::Int(value) => Some(value as f32),
+        OscType::Long(value) => Some(value as f32),
+        _ => None,
+    }
+}
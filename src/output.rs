@@ -0,0 +1,155 @@
+//! Writing the brightness to the backlight, once per chunk or at a fixed interpolated rate.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{LOW_POWER_RATE, Timing, notify, set_brightness};
+
+/// Number of backlight writes since startup.
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of backlight writes attempted since startup.
+pub fn write_count() -> usize {
+    WRITES.load(Ordering::Relaxed)
+}
+
+/// Whether writes are only logged, see [`set_dry_run`].
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Logs the brightness instead of writing it to the backlight (`true`), or writes it (`false`).
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+/// Longest backlight write since the last `take_write_latency`, in µs.
+static WRITE_LATENCY: AtomicU64 = AtomicU64::new(0);
+
+/// The longest backlight write since the previous call.
+pub fn take_write_latency() -> Duration {
+    Duration::from_micros(WRITE_LATENCY.swap(0, Ordering::Relaxed))
+}
+
+/// Where the pipeline sends the brightness of each chunk.
+pub enum Output {
+    /// Every chunk is written as soon as it is processed.
+    Direct,
+    /// A writer thread ramps towards the latest chunk at its own rate.
+    Interpolated(Arc<Mutex<Ramp>>),
+}
+
+/// The brightness change the writer thread is interpolating.
+pub struct Ramp {
+    from: f32,
+    to: f32,
+    started: Instant,
+    /// How long the change takes, one chunk.
+    duration: Duration,
+    paused: bool,
+    /// Low-power mode, chunks are written directly and the writer thread idles.
+    throttled: bool,
+}
+
+impl Ramp {
+    /// The interpolated brightness at `now`.
+    fn value(&self, now: Instant) -> f32 {
+        let progress = now.duration_since(self.started).as_secs_f32() / self.duration.as_secs_f32();
+        self.from + (self.to - self.from) * progress.min(1.0)
+    }
+}
+
+impl Output {
+    /// Starts the writer thread if `timing` asks for a fixed update rate.
+    pub fn new(timing: Timing) -> Self {
+        let Some(rate) = timing.update_rate else {
+            return Self::Direct;
+        };
+
+        let ramp = Arc::new(Mutex::new(Ramp {
+            from: 0.0,
+            to: 0.0,
+            started: Instant::now(),
+            duration: timing.chunk_size,
+            paused: false,
+            throttled: false,
+        }));
+        let writer_ramp = Arc::clone(&ramp);
+        let period = Duration::from_secs_f32(1.0 / rate);
+        let throttled_period = Duration::from_secs_f32(1.0 / LOW_POWER_RATE);
+        thread::spawn(move || {
+            let mut written = None;
+            let mut throttled = false;
+            // Stops once the pipeline is gone.
+            while Arc::strong_count(&writer_ramp) > 1 {
+                thread::sleep(if throttled { throttled_period } else { period });
+                let (level, paused) = {
+                    let ramp = writer_ramp.lock().unwrap();
+                    throttled = ramp.throttled;
+                    (ramp.value(Instant::now()), ramp.paused || ramp.throttled)
+                };
+                // The backlight only takes whole percents, skip writes that change nothing.
+                // A failed write is tried again, even if the level stays the same.
+                if !paused && written != Some(level as u8) && write(level) {
+                    written = Some(level as u8);
+                }
+            }
+        });
+        Self::Interpolated(ramp)
+    }
+
+    /// Moves the backlight to `level` percent, unless `paused`; `throttled` writes it right away.
+    pub fn set(&self, level: f32, paused: bool, throttled: bool) {
+        match self {
+            Self::Direct => {
+                if !paused {
+                    write(level);
+                }
+            }
+            Self::Interpolated(ramp) if throttled => {
+                let mut ramp = ramp.lock().unwrap();
+                // Resume interpolating from here once throttling ends.
+                ramp.from = level;
+                ramp.to = level;
+                ramp.throttled = true;
+                drop(ramp);
+                if !paused {
+                    write(level);
+                }
+            }
+            Self::Interpolated(ramp) => {
+                let mut ramp = ramp.lock().unwrap();
+                let now = Instant::now();
+                // Continue from wherever the previous ramp got to.
+                ramp.from = ramp.value(now);
+                ramp.to = level;
+                ramp.started = now;
+                ramp.paused = paused;
+                ramp.throttled = false;
+            }
+        }
+    }
+}
+
+/// Writes `level`, returning whether it went through.
+fn write(level: f32) -> bool {
+    WRITES.fetch_add(1, Ordering::Relaxed);
+    if DRY_RUN.load(Ordering::Relaxed) {
+        info!(
+            brightness = level as u8,
+            "Would set the brightness to {}%", level as u8
+        );
+        return true;
+    }
+    let started = Instant::now();
+    let result = set_brightness(level);
+    WRITE_LATENCY.fetch_max(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    if let Err(e) = &result {
+        notify::alert(
+            "brightness",
+            "Failed to set the keyboard backlight",
+            &e.to_string(),
+        );
+    }
+    result.is_ok()
+}
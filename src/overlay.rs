@@ -0,0 +1,256 @@
+use std::num::NonZeroU32;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState, Region};
+use smithay_client_toolkit::output::{OutputHandler, OutputState};
+use smithay_client_toolkit::reexports::client::globals::registry_queue_init;
+use smithay_client_toolkit::reexports::client::protocol::{wl_output, wl_shm, wl_surface};
+use smithay_client_toolkit::reexports::client::{Connection, QueueHandle};
+use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
+use smithay_client_toolkit::shell::WaylandSurface;
+use smithay_client_toolkit::shell::wlr_layer::{
+    Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
+    LayerSurfaceConfigure,
+};
+use smithay_client_toolkit::shm::slot::SlotPool;
+use smithay_client_toolkit::shm::{Shm, ShmHandler};
+use smithay_client_toolkit::{
+    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
+    registry_handlers,
+};
+
+use backlight_mic::Levels;
+
+/// Height of the overlay strip, in pixels.
+const HEIGHT: u32 = 48;
+/// Width assumed until the compositor tells us the real one.
+const INITIAL_WIDTH: u32 = 1024;
+/// Premultiplied ARGB colors of the spectrum bars and the brightness line.
+const BAR_COLOR: u32 = 0xa0_00_80_a0;
+const BRIGHTNESS_COLOR: u32 = 0xe0_e0_c0_00;
+/// Thickness of the brightness line along the top of the strip.
+const BRIGHTNESS_HEIGHT: u32 = 3;
+
+/// Starts the overlay on its own thread and returns where to send the levels.
+///
+/// Failing to set up the overlay is logged, it never brings down the rest of the program.
+pub fn spawn() -> Sender<Levels> {
+    let (levels_tx, levels_rx) = mpsc::channel();
+    thread::spawn(move || {
+        if let Err(e) = run(levels_rx) {
+            error!("Overlay error: {e}");
+        }
+    });
+    levels_tx
+}
+
+fn run(levels: Receiver<Levels>) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut event_queue) = registry_queue_init(&conn)?;
+    let qh = event_queue.handle();
+
+    let compositor = CompositorState::bind(&globals, &qh)?;
+    let layer_shell = LayerShell::bind(&globals, &qh)?;
+    let shm = Shm::bind(&globals, &qh)?;
+
+    let surface = compositor.create_surface(&qh);
+    // An empty input region lets clicks through to whatever is below.
+    let region = Region::new(&compositor)?;
+    surface.set_input_region(Some(region.wl_region()));
+
+    let layer =
+        layer_shell.create_layer_surface(&qh, surface, Layer::Overlay, Some("dynkbacklight"), None);
+    layer.set_anchor(Anchor::BOTTOM | Anchor::LEFT | Anchor::RIGHT);
+    layer.set_keyboard_interactivity(KeyboardInteractivity::None);
+    layer.set_size(0, HEIGHT);
+    layer.commit();
+
+    let mut overlay = Overlay {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+        pool: SlotPool::new((INITIAL_WIDTH * HEIGHT * 4) as usize, &shm)?,
+        shm,
+        layer,
+        levels,
+        current: Levels::default(),
+        width: INITIAL_WIDTH,
+        height: HEIGHT,
+        configured: false,
+        closed: false,
+    };
+
+    while !overlay.closed {
+        event_queue.blocking_dispatch(&mut overlay)?;
+    }
+    Ok(())
+}
+
+struct Overlay {
+    registry_state: RegistryState,
+    output_state: OutputState,
+    shm: Shm,
+    pool: SlotPool,
+    layer: LayerSurface,
+    levels: Receiver<Levels>,
+    current: Levels,
+    width: u32,
+    height: u32,
+    configured: bool,
+    closed: bool,
+}
+
+impl Overlay {
+    /// Renders the latest levels and asks for a frame callback to render the next ones.
+    fn draw(&mut self, qh: &QueueHandle<Self>) {
+        if let Some(latest) = self.levels.try_iter().last() {
+            self.current = latest;
+        }
+
+        let (width, height) = (self.width, self.height);
+        let stride = width as i32 * 4;
+        let (buffer, canvas) = match self.pool.create_buffer(
+            width as i32,
+            height as i32,
+            stride,
+            wl_shm::Format::Argb8888,
+        ) {
+            Ok(created) => created,
+            Err(e) => {
+                error!("Overlay buffer error: {e}");
+                self.closed = true;
+                return;
+            }
+        };
+
+        let bands = &self.current.bands;
+        let brightness_width = (width as f32 * self.current.brightness / 100.0) as u32;
+        for (index, pixel) in canvas.chunks_exact_mut(4).enumerate() {
+            let x = index as u32 % width;
+            let y = index as u32 / width;
+
+            let color = if y < BRIGHTNESS_HEIGHT {
+                if x < brightness_width {
+                    BRIGHTNESS_COLOR
+                } else {
+                    0
+                }
+            } else if bands.is_empty() {
+                0
+            } else {
+                let band = bands[(x as usize * bands.len() / width as usize).min(bands.len() - 1)];
+                let bar_top = height - (band * (height - BRIGHTNESS_HEIGHT) as f32) as u32;
+                if y >= bar_top { BAR_COLOR } else { 0 }
+            };
+            pixel.copy_from_slice(&color.to_le_bytes());
+        }
+
+        let surface = self.layer.wl_surface();
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+        surface.frame(qh, surface.clone());
+        if let Err(e) = buffer.attach_to(surface) {
+            error!("Overlay buffer error: {e}");
+            self.closed = true;
+            return;
+        }
+        self.layer.commit();
+    }
+}
+
+impl CompositorHandler for Overlay {
+    fn scale_factor_changed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: i32,
+    ) {
+    }
+
+    fn transform_changed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: wl_output::Transform,
+    ) {
+    }
+
+    fn frame(&mut self, _: &Connection, qh: &QueueHandle<Self>, _: &wl_surface::WlSurface, _: u32) {
+        self.draw(qh);
+    }
+
+    fn surface_enter(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: &wl_output::WlOutput,
+    ) {
+    }
+
+    fn surface_leave(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: &wl_output::WlOutput,
+    ) {
+    }
+}
+
+impl LayerShellHandler for Overlay {
+    fn closed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &LayerSurface) {
+        self.closed = true;
+    }
+
+    fn configure(
+        &mut self,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+        _: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _: u32,
+    ) {
+        self.width = NonZeroU32::new(configure.new_size.0).map_or(INITIAL_WIDTH, NonZeroU32::get);
+        self.height = NonZeroU32::new(configure.new_size.1).map_or(HEIGHT, NonZeroU32::get);
+
+        // Later frames are driven by the frame callbacks.
+        if !self.configured {
+            self.configured = true;
+            self.draw(qh);
+        }
+    }
+}
+
+impl OutputHandler for Overlay {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn output_destroyed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+}
+
+impl ShmHandler for Overlay {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl ProvidesRegistryState for Overlay {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+
+    registry_handlers![OutputState];
+}
+
+delegate_compositor!(Overlay);
+delegate_output!(Overlay);
+delegate_shm!(Overlay);
+delegate_layer!(Overlay);
+delegate_registry!(Overlay);
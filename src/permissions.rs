@@ -0,0 +1,114 @@
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use backlight_mic::BACKLIGHT;
+
+/// Where the rule is installed.
+const RULE_PATH: &str = "/etc/udev/rules.d/90-dynkbacklight.rules";
+
+/// Prints the udev rule granting `group` (the user's primary group if unset) write access to the
+/// keyboard backlight, and installs it unless `print`.
+///
+/// Installing needs root, it goes through `sudo` when run as a user.
+pub fn setup(group: Option<String>, print: bool) -> io::Result<()> {
+    let led = Path::new(BACKLIGHT);
+    if !led.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no keyboard backlight at {BACKLIGHT}"),
+        ));
+    }
+    let name = led
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let group = match group {
+        Some(group) => group,
+        None => primary_group()?,
+    };
+    let rule = rule(name, &group);
+    if print {
+        print!("{rule}");
+        return Ok(());
+    }
+
+    println!("Installing to {RULE_PATH}:\n{rule}");
+    privileged(&["tee", RULE_PATH], Some(&rule))?;
+    privileged(&["udevadm", "control", "--reload"], None)?;
+    privileged(
+        &[
+            "udevadm",
+            "trigger",
+            "--subsystem-match=leds",
+            "--action=add",
+        ],
+        None,
+    )?;
+    println!(
+        "Members of `{group}` can now write the keyboard backlight, log in again if you just joined it."
+    );
+    Ok(())
+}
+
+/// A rule making `led`'s brightness writable by `group` whenever it appears.
+fn rule(led: &str, group: &str) -> String {
+    let brightness = "/sys/class/leds/%k/brightness";
+    format!(
+        "# Lets members of {group} set the keyboard backlight, written by `backlight_mic setup-permissions`.\n\
+         ACTION==\"add\", SUBSYSTEM==\"leds\", KERNEL==\"{led}\", \
+         RUN+=\"/bin/chgrp {group} {brightness}\", RUN+=\"/bin/chmod g+w {brightness}\"\n"
+    )
+}
+
+/// Name of the primary group of the user, the one who ran `sudo` if it did.
+fn primary_group() -> io::Result<String> {
+    let gid = match std::env::var("SUDO_GID")
+        .ok()
+        .and_then(|gid| gid.parse().ok())
+    {
+        Some(gid) => gid,
+        None => unsafe { libc::getgid() },
+    };
+    // Called once at startup, before any other thread could use the same buffer.
+    let entry = unsafe { libc::getgrgid(gid) };
+    if entry.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no group with id {gid}, pass --group"),
+        ));
+    }
+    let name = unsafe { CStr::from_ptr((*entry).gr_name) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// Runs `command` as root, through `sudo` unless already root, feeding it `input`.
+fn privileged(command: &[&str], input: Option<&str>) -> io::Result<()> {
+    let root = unsafe { libc::geteuid() } == 0;
+    let (program, args) = if root {
+        (command[0], &command[1..])
+    } else {
+        ("sudo", command)
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "`{}` failed: {status}",
+            command.join(" ")
+        )));
+    }
+    Ok(())
+}
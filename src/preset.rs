@@ -0,0 +1,293 @@
+//! Presets: a profile and the effect settings it was tuned with, bundled into one file to switch
+//! between looks or share them.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{
+    Compressor, Config, Driver, Duck, Layers, Profile, Stage, ThresholdMode, Weighting,
+};
+
+/// Names of the presets shipped with the program, see [`built_in`].
+pub const BUILT_IN: [&str; 4] = ["edm", "speech", "ambient", "gaming"];
+/// Version of the shared format [`export`] writes, raised whenever a release changes what an
+/// existing setting means; settings can be added without raising it.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A profile with the settings of the effects around it, stored as TOML in [`dir`].
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    /// What the preset is meant for, shown by `preset list`.
+    pub description: String,
+    /// The response parameters, mapping expression included.
+    pub profile: Profile,
+    /// See [`Config::driver`].
+    pub driver: Driver,
+    /// See [`Config::weighting`].
+    pub weighting: Weighting,
+    /// See [`Config::ducking`].
+    pub ducking: Vec<Duck>,
+    /// See [`Config::layers`].
+    pub layers: Option<Layers>,
+    /// See [`Config::plugin`].
+    pub plugin: Option<String>,
+    /// See [`Config::pipeline`].
+    pub pipeline: Vec<Stage>,
+}
+
+impl Preset {
+    /// The active profile of `config` and its effect settings.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            description: String::new(),
+            profile: config.active_profile(),
+            driver: config.driver,
+            weighting: config.weighting,
+            ducking: config.ducking.clone(),
+            layers: config.layers.clone(),
+            plugin: config.plugin.clone(),
+            pipeline: config.pipeline.clone(),
+        }
+    }
+
+    /// Stores the preset in `config` as the profile `name`, made active, and its effect settings.
+    pub fn apply(&self, name: &str, config: &mut Config) {
+        config
+            .profiles
+            .insert(name.to_string(), self.profile.clone());
+        config.profile = name.to_string();
+        config.driver = self.driver;
+        config.weighting = self.weighting;
+        config.ducking = self.ducking.clone();
+        config.layers = self.layers.clone();
+        config.plugin = self.plugin.clone();
+        config.pipeline = self.pipeline.clone();
+    }
+
+    /// Reads the preset file at `path`.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the preset to `path`, creating its directory if needed.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+
+    /// The preset called `name`, saved or built in; a saved one hides the built-in one.
+    pub fn load(name: &str) -> io::Result<Self> {
+        match Self::read(&path(name)?) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => built_in(name).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no preset named `{name}`"))
+            }),
+            preset => preset,
+        }
+    }
+
+    /// Saves the preset in [`dir`] as `name`, replacing any preset of that name.
+    pub fn save(&self, name: &str) -> io::Result<()> {
+        self.write(&path(name)?)
+    }
+}
+
+/// A preset as shared in a JSON file, see [`export`] and [`import`].
+#[derive(Serialize, Deserialize)]
+struct Shared {
+    /// Version of the format the file was written in, see [`FORMAT_VERSION`].
+    version: u32,
+    /// Name the preset was exported as.
+    #[serde(default)]
+    name: Option<String>,
+    preset: Preset,
+}
+
+/// Writes `preset`, called `name`, to `path` in the versioned JSON format meant for sharing.
+pub fn export(preset: &Preset, name: &str, path: &Path) -> io::Result<()> {
+    let shared = Shared {
+        version: FORMAT_VERSION,
+        name: Some(name.to_string()),
+        preset: preset.clone(),
+    };
+    let text = serde_json::to_string_pretty(&shared).map_err(io::Error::other)?;
+    fs::write(path, text + "\n")
+}
+
+/// Reads a preset shared as JSON, with the name it was exported as if any, or saved as TOML.
+///
+/// Presets exported by newer releases still load: settings and pipeline stages this release
+/// doesn't know are left out with a warning instead of failing the whole preset.
+pub fn import(path: &Path) -> io::Result<(Option<String>, Preset)> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "toml")
+    {
+        return Ok((None, Preset::read(path)?));
+    }
+    let text = fs::read_to_string(path)?;
+    let mut value: Value = serde_json::from_str(&text).map_err(invalid)?;
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| invalid("not a preset, it has no `version`"))?;
+    if version > FORMAT_VERSION as u64 {
+        warn!(
+            "{} was exported by a newer release, in version {version} of the format",
+            path.display()
+        );
+    }
+    if let Some(Value::Array(stages)) = value.pointer_mut("/preset/pipeline") {
+        stages.retain(|stage| {
+            let known = Stage::deserialize(stage).is_ok();
+            if !known {
+                warn!("Ignoring the unknown pipeline stage {stage}");
+            }
+            known
+        });
+    }
+    let shared: Shared = serde_ignored::deserialize(value, |setting| {
+        warn!("Ignoring the unknown setting `{setting}`");
+    })
+    .map_err(invalid)?;
+    Ok((shared.name, shared.preset))
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Where saved presets live, `presets/` next to the config file.
+pub fn dir() -> PathBuf {
+    Config::path().with_file_name("presets")
+}
+
+/// Where the preset `name` is saved, `NAME.toml` in [`dir`].
+fn path(name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{name}` is not a valid preset name"),
+        ));
+    }
+    Ok(dir().join(format!("{name}.toml")))
+}
+
+/// Every preset by name, the built-in ones and the saved ones, which hide built-in ones of the
+/// same name. Unreadable files are skipped with a warning.
+pub fn list() -> io::Result<BTreeMap<String, Preset>> {
+    let mut presets: BTreeMap<_, _> = BUILT_IN
+        .iter()
+        .filter_map(|name| Some((name.to_string(), built_in(name)?)))
+        .collect();
+    let entries = match fs::read_dir(dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(presets),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "toml") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        match Preset::read(&path) {
+            Ok(preset) => {
+                presets.insert(name.to_string(), preset);
+            }
+            Err(e) => warn!("Skipping the preset {}: {e}", path.display()),
+        }
+    }
+    Ok(presets)
+}
+
+/// Applies the built-in preset `name` of `--preset`, if any, over `config`. The result is only
+/// meant to run: saving it would write the preset into the config file for good.
+pub fn apply_built_in(name: Option<&str>, config: &mut Config) {
+    if let Some(name) = name
+        && let Some(preset) = built_in(name)
+    {
+        preset.apply(name, config);
+    }
+}
+
+/// The built-in preset called `name`, one of [`BUILT_IN`].
+pub fn built_in(name: &str) -> Option<Preset> {
+    let preset = match name {
+        "edm" => Preset {
+            description: "Hard flashes on every kick that fade fast, for electronic music"
+                .to_string(),
+            profile: Profile {
+                boost: 2.2,
+                threshold_factor: 1.3,
+                attack: 4000.0,
+                release: 60.0,
+                average_ms: 400,
+                ..Profile::default()
+            },
+            ..Preset::default()
+        },
+        "speech" => Preset {
+            description: "Calm swells following a voice, for calls, podcasts and videos"
+                .to_string(),
+            profile: Profile {
+                boost: 1.0,
+                threshold_factor: 0.5,
+                threshold_mode: ThresholdMode::Adaptive,
+                attack: 300.0,
+                release: 15.0,
+                average_ms: 1500,
+                compressor: Some(Compressor::default()),
+                ..Profile::default()
+            },
+            weighting: Weighting::K,
+            ..Preset::default()
+        },
+        "ambient" => Preset {
+            description: "A slow glow breathing with the music, for background listening"
+                .to_string(),
+            profile: Profile {
+                boost: 1.2,
+                threshold_factor: 1.1,
+                attack: 150.0,
+                release: 8.0,
+                average_ms: 3000,
+                compressor: Some(Compressor::default()),
+                ..Profile::default()
+            },
+            layers: Some(Layers { glow: 60.0 }),
+            ..Preset::default()
+        },
+        "gaming" => Preset {
+            description: "Punchy flashes on explosions and gunfire over the game's ambience"
+                .to_string(),
+            profile: Profile {
+                boost: 1.8,
+                threshold_factor: 1.5,
+                threshold_mode: ThresholdMode::Adaptive,
+                attack: 4000.0,
+                release: 40.0,
+                average_ms: 800,
+                compressor: Some(Compressor {
+                    ratio: 4.0,
+                    ..Compressor::default()
+                }),
+                ..Profile::default()
+            },
+            ..Preset::default()
+        },
+        _ => return None,
+    };
+    Some(preset)
+}
@@ -0,0 +1,66 @@
+//! Realtime scheduling for the audio processing thread.
+
+use std::io;
+use std::process::{Command, Stdio};
+
+use crate::error::{Error, Result};
+
+/// SCHED_FIFO priority requested, below the audio server's own threads.
+pub const PRIORITY: i32 = 10;
+/// CPU time, in microseconds, a realtime thread may use without blocking.
+const RTTIME_LIMIT: u64 = 200_000;
+
+/// Gives the calling thread realtime priority, directly when allowed or else through RTKit.
+///
+/// Threads spawned by it afterwards inherit the priority.
+pub fn promote() -> Result<()> {
+    let param = libc::sched_param {
+        sched_priority: PRIORITY,
+    };
+    // SAFETY: `param` is a valid sched_param and 0 designates the calling thread.
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } == 0 {
+        return Ok(());
+    }
+    let direct = io::Error::last_os_error();
+
+    // RTKit only accepts threads that can't hog the CPU, their runtime has to be limited.
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid rlimit for the calls to fill and read.
+    unsafe {
+        if libc::getrlimit(libc::RLIMIT_RTTIME, &mut limit) != 0 {
+            return Err(Error::Realtime(io::Error::last_os_error()));
+        }
+        limit.rlim_max = limit.rlim_max.min(RTTIME_LIMIT);
+        limit.rlim_cur = limit.rlim_max;
+        if libc::setrlimit(libc::RLIMIT_RTTIME, &limit) != 0 {
+            return Err(Error::Realtime(io::Error::last_os_error()));
+        }
+    }
+
+    // SAFETY: gettid has no preconditions.
+    let thread = unsafe { libc::gettid() };
+    let status = Command::new("busctl")
+        .args([
+            "call",
+            "--system",
+            "org.freedesktop.RealtimeKit1",
+            "/org/freedesktop/RealtimeKit1",
+            "org.freedesktop.RealtimeKit1",
+            "MakeThreadRealtime",
+            "tu",
+            &thread.to_string(),
+            &PRIORITY.to_string(),
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        // RTKit is missing or refused, the direct attempt says more about why.
+        _ => Err(Error::Realtime(direct)),
+    }
+}
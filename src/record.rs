@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use backlight_mic::Levels;
+
+use crate::emit::timestamp;
+
+/// First line of the recordings.
+const HEADER: &str = "timestamp,rms,normalized,brightness";
+
+/// Creates `path` and writes every chunk's levels to it on its own thread, returning where to
+/// send them.
+pub fn spawn(path: &Path) -> io::Result<Sender<Levels>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "{HEADER}")?;
+    file.flush()?;
+    info!("Recording levels to {}", path.display());
+
+    let (levels_tx, levels_rx) = mpsc::channel();
+    thread::spawn(move || {
+        if let Err(e) = run(file, levels_rx) {
+            error!("Stopped recording levels: {e}");
+        }
+    });
+    Ok(levels_tx)
+}
+
+fn run(mut file: BufWriter<File>, levels: Receiver<Levels>) -> io::Result<()> {
+    for levels in levels {
+        writeln!(
+            file,
+            "{:.3},{},{},{}",
+            timestamp(),
+            levels.rms,
+            levels.normalized,
+            levels.brightness
+        )?;
+        // The program is usually stopped with Ctrl-C, keep the file complete.
+        file.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! Driving the pipeline from levels recorded with `--record` instead of live audio.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::{RmsState, process_level};
+
+/// A recorded chunk.
+#[derive(Clone, Copy)]
+pub struct Row {
+    /// When the chunk was processed, in seconds.
+    pub timestamp: f64,
+    /// RMS level of the chunk.
+    pub rms: f32,
+}
+
+/// The chunks of a recording, in order.
+pub struct Recording {
+    /// Recorded chunks, oldest first.
+    pub rows: Vec<Row>,
+}
+
+impl Recording {
+    /// Reads a CSV recording, finding the `timestamp` and `rms` columns by the header.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(Error::Replay)?;
+        let mut lines = text.lines().enumerate();
+
+        let header: Vec<&str> = lines
+            .next()
+            .map_or(Vec::new(), |(_, line)| line.split(',').collect());
+        let column = |name| {
+            header
+                .iter()
+                .position(|column| column.trim() == name)
+                .ok_or_else(|| invalid(format!("no `{name}` column in the header")))
+        };
+        let (timestamp, rms) = (column("timestamp")?, column("rms")?);
+
+        let mut rows = Vec::new();
+        for (index, line) in lines.filter(|(_, line)| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let field = |column: usize| fields.get(column).copied().unwrap_or_default();
+            // `inf` and `NaN` parse as numbers, but aren't times.
+            let timestamp = field(timestamp)
+                .parse::<f64>()
+                .ok()
+                .filter(|timestamp| timestamp.is_finite());
+            let (Some(timestamp), Ok(rms)) = (timestamp, field(rms).parse()) else {
+                return Err(invalid(format!(
+                    "line {}: malformed row `{line}`",
+                    index + 1
+                )));
+            };
+            rows.push(Row { timestamp, rms });
+        }
+        Ok(Self { rows })
+    }
+
+    /// The typical time between two chunks, to process them as they were recorded.
+    pub fn chunk_size(&self) -> Option<Duration> {
+        let mut intervals: Vec<f64> = self
+            .rows
+            .windows(2)
+            .map(|pair| pair[1].timestamp - pair[0].timestamp)
+            .filter(|interval| *interval > 0.0)
+            .collect();
+        intervals.sort_by(f64::total_cmp);
+        // The median, a few late chunks shouldn't change it.
+        intervals
+            .get(intervals.len() / 2)
+            .and_then(|interval| Duration::try_from_secs_f64(*interval).ok())
+    }
+
+    /// Feeds every chunk through the pipeline, respecting the recorded timing.
+    pub fn play(&self, state: &mut RmsState) {
+        let Some(first) = self.rows.first() else {
+            return;
+        };
+        let start = Instant::now();
+        for row in &self.rows {
+            // A row too far ahead to wait for is played at once.
+            let due = Duration::try_from_secs_f64((row.timestamp - first.timestamp).max(0.0))
+                .unwrap_or_default();
+            sleep(due.saturating_sub(start.elapsed()));
+            process_level(row.rms, state);
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::Replay(io::Error::new(io::ErrorKind::InvalidData, message))
+}
@@ -0,0 +1,83 @@
+//! Putting the backlight back as it was before the program started.
+//!
+//! The brightness found at startup is kept in a state file until a clean exit restores it and
+//! removes the file. A file still there at the next start means that run crashed or was killed,
+//! its brightness is then restored first.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::sink;
+
+/// The brightness this run restores on exit, once [`save`]d.
+static SAVED: Mutex<Option<f32>> = Mutex::new(None);
+
+/// Location of the state file, `$XDG_STATE_HOME/dynkbacklight/brightness`.
+pub fn path() -> PathBuf {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("dynkbacklight").join("brightness")
+}
+
+/// Remembers the brightness of the selected sink, before the pipeline writes it.
+///
+/// If the previous run didn't exit cleanly its brightness is restored first, and kept as the one
+/// to restore.
+pub fn save() {
+    let path = path();
+    let previous = fs::read_to_string(&path)
+        .ok()
+        .and_then(|saved| saved.trim().parse::<f32>().ok());
+    let brightness = match previous {
+        Some(saved) => {
+            warn!("The previous run didn't exit cleanly, restoring the brightness to {saved:.0}%");
+            if let Err(e) = sink::set(saved) {
+                warn!("Failed to restore the brightness: {e}");
+            }
+            saved
+        }
+        None => match sink::get() {
+            Ok(brightness) => {
+                if let Err(e) = write(&path, brightness) {
+                    warn!("Failed to save the brightness to restore: {e}");
+                    return;
+                }
+                brightness
+            }
+            Err(e) => {
+                debug!("Not saving the brightness, it can't be read: {e}");
+                return;
+            }
+        },
+    };
+    *SAVED.lock().unwrap() = Some(brightness);
+}
+
+fn write(path: &Path, brightness: f32) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, format!("{brightness}\n"))
+}
+
+/// Sets the brightness [`save`]d at startup again and removes the state file, on a clean exit.
+///
+/// The file stays if the write fails, for the next start to try again.
+pub fn restore() {
+    let Some(brightness) = SAVED.lock().unwrap().take() else {
+        return;
+    };
+    match sink::set(brightness) {
+        Ok(()) => {
+            info!("Restored the brightness to {brightness:.0}%");
+            let _ = fs::remove_file(path());
+        }
+        Err(e) => warn!("Failed to restore the brightness: {e}"),
+    }
+}
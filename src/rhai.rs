@@ -0,0 +1,136 @@
+//! Custom effects written in Rhai, a scripting language built in, for scripts without a Lua or
+//! WASM toolchain.
+//!
+//! `effect.rhai`, next to the config file, defines `map(rms, bands)`. It is called for every
+//! chunk, after the Lua mapping, with the level the pipeline drives with and the band magnitudes
+//! (an array, lowest frequency first, empty without samples). `this` is a map kept from one call
+//! to the next, where the pipeline sets `normalized`, `threshold`, `beat`, `brightness` (its own
+//! brightness so far) and `chunk_secs` before each call; everything else in it is the script's
+//! own.
+//!
+//! `map` returns the brightness in percent, a color like `"#ff8000"` (its brightest channel), or
+//! nothing to keep the pipeline's. A call may run at most [`MAX_OPERATIONS`] operations. The file
+//! is reloaded when it changes.
+
+use std::path::PathBuf;
+
+use ::rhai::{AST, Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FLOAT, Map, Scope};
+
+use crate::Chunk;
+use crate::config::{Config, color_brightness};
+use crate::error::Result;
+use crate::notify;
+use crate::watch::Watched;
+
+/// Operations a call of `map` may run, far more than an effect needs.
+pub const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A loaded `effect.rhai`.
+pub struct Effect {
+    file: Watched,
+    engine: Engine,
+    ast: AST,
+    /// `this` of the script.
+    state: Dynamic,
+}
+
+impl Effect {
+    /// Location of the script, `effect.rhai` next to the config file.
+    pub fn path() -> PathBuf {
+        Config::path().with_file_name("effect.rhai")
+    }
+
+    /// Loads the script at [`path`](Self::path), `None` if there is none.
+    pub fn load() -> Result<Option<Self>> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::open(path).map(Some)
+    }
+
+    /// Loads the script at `path`, reloaded from there when it changes.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let file = Watched::new(path);
+        let ast = engine.compile_file(file.path().to_path_buf())?;
+        info!("Running the effect {}", file.path().display());
+        Ok(Self {
+            file,
+            engine,
+            ast,
+            state: Map::new().into(),
+        })
+    }
+
+    /// The brightness the script maps `chunk` to, in percent; `None` to keep the pipeline's, also
+    /// when the script fails.
+    pub fn map(&mut self, chunk: &Chunk) -> Option<f32> {
+        self.reload();
+        match self.call(chunk) {
+            Ok(brightness) => brightness.map(|brightness| brightness.clamp(0.0, 100.0)),
+            Err(e) => {
+                notify::alert("rhai", "The Rhai effect failed", &e.to_string());
+                None
+            }
+        }
+    }
+
+    fn call(&mut self, chunk: &Chunk) -> std::result::Result<Option<f32>, Box<EvalAltResult>> {
+        if let Some(mut state) = self.state.write_lock::<Map>() {
+            state.insert("normalized".into(), (chunk.normalized as FLOAT).into());
+            state.insert("threshold".into(), (chunk.threshold as FLOAT).into());
+            state.insert("beat".into(), chunk.beat.into());
+            state.insert("brightness".into(), (chunk.brightness as FLOAT).into());
+            state.insert("chunk_secs".into(), (chunk.chunk_secs as FLOAT).into());
+        }
+        let bands: Array = chunk
+            .bands
+            .iter()
+            .map(|band| (*band as FLOAT).into())
+            .collect();
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let value: Dynamic = self.engine.call_fn_with_options(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            "map",
+            (chunk.rms as FLOAT, bands),
+        )?;
+        if value.is_unit() {
+            Ok(None)
+        } else if let Ok(brightness) = value.as_float() {
+            Ok(Some(brightness as f32))
+        } else if let Ok(brightness) = value.as_int() {
+            Ok(Some(brightness as f32))
+        } else if let Ok(color) = value.clone().into_string() {
+            color_brightness(&color)
+                .map(Some)
+                .ok_or_else(|| format!("`{color}` is not a color like #ff8000").into())
+        } else {
+            Err(format!(
+                "map returned a {}, not a number or a color",
+                value.type_name()
+            )
+            .into())
+        }
+    }
+
+    /// Compiles the script again if it changed, keeping `this` and the previous version if it
+    /// doesn't compile.
+    fn reload(&mut self) {
+        if !self.file.changed() {
+            return;
+        }
+        match self.engine.compile_file(self.file.path().to_path_buf()) {
+            Ok(ast) => {
+                info!("Reloaded {}", self.file.path().display());
+                self.ast = ast;
+            }
+            Err(e) => notify::alert("rhai", "The Rhai effect failed to load", &e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Levels measured from something other than the audio, e.g. the room's brightness.
+//!
+//! Each [`Sensor`] is polled on its own thread by [`spawn`], away from the audio processing,
+//! and the pipeline [`mix`]es the latest levels with the audio once per chunk.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{Mix, MixMode};
+
+mod ambient;
+mod cpu;
+mod disk;
+mod network;
+#[cfg(feature = "screen")]
+mod screen;
+mod typing;
+
+pub use ambient::Ambient;
+pub use cpu::Cpu;
+pub use disk::Disk;
+pub use network::Network;
+#[cfg(feature = "screen")]
+pub use screen::Screen;
+pub use typing::Typing;
+
+/// How often the sensors are polled.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Latest level of each running sensor, by name.
+static LEVELS: Mutex<BTreeMap<String, f32>> = Mutex::new(BTreeMap::new());
+
+/// Something measured as a level, polled every [`POLL_INTERVAL`].
+pub trait Sensor: Send {
+    /// Short name, for logs and to tell the levels apart.
+    fn name(&self) -> String;
+
+    /// The current level, 0.0-1.0.
+    fn level(&mut self) -> io::Result<f32>;
+}
+
+/// Polls `sensor` on its own thread, publishing its level for [`mix`].
+///
+/// A failing sensor reads as 0.0 and is retried, only its first failure is logged.
+pub fn spawn(mut sensor: Box<dyn Sensor>) {
+    let name = sensor.name();
+    info!("Following the {name} sensor");
+    thread::spawn(move || {
+        let mut failing = false;
+        loop {
+            let level = match sensor.level() {
+                Ok(level) => {
+                    failing = false;
+                    level.clamp(0.0, 1.0)
+                }
+                Err(e) => {
+                    if !failing {
+                        warn!("The {name} sensor failed: {e}");
+                        failing = true;
+                    }
+                    0.0
+                }
+            };
+            LEVELS.lock().unwrap().insert(name.clone(), level);
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+/// Combines the audio-driven `audio` level with the sensors' as `mix` says, 0.0-1.0.
+pub fn mix(audio: f32, mix: &Mix) -> f32 {
+    let levels = LEVELS.lock().unwrap();
+    let audio_weight = mix.weight("audio");
+    let audio = audio * audio_weight;
+    let sensors = levels
+        .iter()
+        .map(|(name, level)| (mix.weight(name), level * mix.weight(name)));
+
+    let level = match mix.mode {
+        MixMode::Layer => {
+            let baseline = sensors.map(|(_, level)| level).fold(0.0, f32::max);
+            baseline + audio * (1.0 - baseline)
+        }
+        MixMode::Max => sensors.map(|(_, level)| level).fold(audio, f32::max),
+        MixMode::Sum => sensors.map(|(_, level)| level).sum::<f32>() + audio,
+        MixMode::Blend => {
+            let (weights, sum) = sensors
+                .fold((audio_weight, audio), |(weights, sum), (weight, level)| {
+                    (weights + weight, sum + level)
+                });
+            if weights > 0.0 { sum / weights } else { 0.0 }
+        }
+    };
+    level.clamp(0.0, 1.0)
+}
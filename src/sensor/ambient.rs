@@ -0,0 +1,66 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::Sensor;
+
+/// Where the kernel lists the industrial I/O devices, ambient light sensors among them.
+const IIO_DEVICES: &str = "/sys/bus/iio/devices";
+/// Illuminance read as full level, in lux; an office is about 500, daylight far above.
+const FULL_LUX: f32 = 1000.0;
+
+/// The room's brightness, from an iio ambient light sensor.
+///
+/// The level follows the illuminance on a logarithmic scale, like the eye perceives it.
+pub struct Ambient {
+    /// The device directory of the sensor.
+    device: PathBuf,
+}
+
+impl Ambient {
+    /// Finds the first iio device reporting an illuminance.
+    pub fn find() -> io::Result<Self> {
+        for entry in fs::read_dir(IIO_DEVICES)? {
+            let device = entry?.path();
+            if device.join("in_illuminance_input").exists()
+                || device.join("in_illuminance_raw").exists()
+            {
+                return Ok(Self { device });
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no ambient light sensor",
+        ))
+    }
+
+    /// The illuminance in lux, processed by the driver or computed from the raw reading.
+    fn lux(&self) -> io::Result<f32> {
+        if let Ok(lux) = read(&self.device.join("in_illuminance_input")) {
+            return Ok(lux);
+        }
+        let raw = read(&self.device.join("in_illuminance_raw"))?;
+        let offset = read(&self.device.join("in_illuminance_offset")).unwrap_or(0.0);
+        let scale = read(&self.device.join("in_illuminance_scale")).unwrap_or(1.0);
+        Ok((raw + offset) * scale)
+    }
+}
+
+impl Sensor for Ambient {
+    fn name(&self) -> String {
+        "ambient".to_string()
+    }
+
+    fn level(&mut self) -> io::Result<f32> {
+        let lux = self.lux()?.max(0.0);
+        Ok((lux + 1.0).log10() / (FULL_LUX + 1.0).log10())
+    }
+}
+
+/// Reads a number from a sysfs attribute.
+fn read(path: &Path) -> io::Result<f32> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::io;
+
+use super::Sensor;
+
+/// Where the kernel counts the time spent by the CPUs.
+const PROC_STAT: &str = "/proc/stat";
+
+/// The CPU load, the share of time all CPUs were busy since the previous poll.
+#[derive(Default)]
+pub struct Cpu {
+    /// Busy and total time at the previous poll, in clock ticks.
+    previous: Option<(u64, u64)>,
+}
+
+impl Cpu {
+    /// Starts measuring from the next poll.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Sensor for Cpu {
+    fn name(&self) -> String {
+        "cpu".to_string()
+    }
+
+    fn level(&mut self) -> io::Result<f32> {
+        let (busy, total) = times()?;
+        let level = match self.previous {
+            Some((previous_busy, previous_total)) if total > previous_total => {
+                busy.saturating_sub(previous_busy) as f32 / (total - previous_total) as f32
+            }
+            _ => 0.0,
+        };
+        self.previous = Some((busy, total));
+        Ok(level)
+    }
+}
+
+/// Busy and total time of all CPUs since boot, in clock ticks.
+fn times() -> io::Result<(u64, u64)> {
+    let stat = fs::read_to_string(PROC_STAT)?;
+    // cpu  user nice system idle iowait irq softirq steal guest guest_nice
+    let fields: Vec<u64> = stat
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("cpu "))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no cpu line"))?
+        .split_whitespace()
+        .filter_map(|field| field.parse().ok())
+        .collect();
+    // Guest time is already counted in user and nice.
+    let total: u64 = fields.iter().take(8).sum();
+    let idle =
+        fields.get(3).copied().unwrap_or_default() + fields.get(4).copied().unwrap_or_default();
+    Ok((total.saturating_sub(idle), total))
+}
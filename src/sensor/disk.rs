@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use super::Sensor;
+
+/// Where the kernel counts the I/O of every block device.
+const DISKSTATS: &str = "/proc/diskstats";
+/// Where the kernel lists whole disks, partitions aside.
+const BLOCK_CLASS: &str = "/sys/block";
+
+/// Disk activity, the utilization of the busiest disk since the previous poll.
+///
+/// Like `iostat`'s `%util`, the level is the share of time the disk had I/O in flight.
+#[derive(Default)]
+pub struct Disk {
+    /// Milliseconds each disk spent doing I/O at the previous poll, and when it happened.
+    previous: Option<(HashMap<String, u64>, Instant)>,
+}
+
+impl Disk {
+    /// Starts measuring from the next poll.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Sensor for Disk {
+    fn name(&self) -> String {
+        "disk".to_string()
+    }
+
+    fn level(&mut self) -> io::Result<f32> {
+        let busy = busy_times()?;
+        let now = Instant::now();
+        let level = match &self.previous {
+            Some((previous, since)) => {
+                let elapsed = now.duration_since(*since).as_secs_f32() * 1000.0;
+                busy.iter()
+                    .filter_map(|(disk, busy)| {
+                        let previous = previous.get(disk)?;
+                        Some(busy.saturating_sub(*previous) as f32 / elapsed.max(1.0))
+                    })
+                    .fold(0.0, f32::max)
+            }
+            None => 0.0,
+        };
+        self.previous = Some((busy, now));
+        Ok(level)
+    }
+}
+
+/// Milliseconds each whole disk spent doing I/O since boot, virtual ones aside.
+fn busy_times() -> io::Result<HashMap<String, u64>> {
+    let stats = fs::read_to_string(DISKSTATS)?;
+    let mut busy = HashMap::new();
+    for line in stats.lines() {
+        // major minor name, 11 or more counters, the 10th being the time doing I/O.
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (Some(name), Some(ticks)) = (fields.get(2), fields.get(12)) else {
+            continue;
+        };
+        let virtual_disk = name.starts_with("loop") || name.starts_with("ram");
+        if virtual_disk || !Path::new(BLOCK_CLASS).join(name).exists() {
+            continue;
+        }
+        if let Ok(ticks) = ticks.parse() {
+            busy.insert(name.to_string(), ticks);
+        }
+    }
+    Ok(busy)
+}
@@ -0,0 +1,68 @@
+use std::fs;
+use std::io;
+use std::time::Instant;
+
+use super::Sensor;
+
+/// Where the kernel lists the network interfaces.
+const NET_CLASS: &str = "/sys/class/net";
+/// Throughput read as no activity and as full level, in bytes per second.
+const RANGE: (f32, f32) = (1e3, 1e8);
+
+/// Network activity, the bytes received and sent by all interfaces since the previous poll.
+///
+/// The level follows the throughput on a logarithmic scale from 1 kB/s to 100 MB/s, so a
+/// download lights up the keyboard as well as a chat message does.
+#[derive(Default)]
+pub struct Network {
+    /// Bytes transferred at the previous poll, and when it happened.
+    previous: Option<(u64, Instant)>,
+}
+
+impl Network {
+    /// Starts measuring from the next poll.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Sensor for Network {
+    fn name(&self) -> String {
+        "network".to_string()
+    }
+
+    fn level(&mut self) -> io::Result<f32> {
+        let bytes = transferred()?;
+        let now = Instant::now();
+        let level = match self.previous {
+            Some((previous, since)) => {
+                let rate = bytes.saturating_sub(previous) as f32
+                    / now.duration_since(since).as_secs_f32().max(f32::EPSILON);
+                let (low, high) = RANGE;
+                (rate.max(low) / low).log10() / (high / low).log10()
+            }
+            None => 0.0,
+        };
+        self.previous = Some((bytes, now));
+        Ok(level)
+    }
+}
+
+/// Bytes received and sent by all interfaces but the loopback since they came up.
+fn transferred() -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(NET_CLASS)? {
+        let interface = entry?.path();
+        if interface.file_name().is_some_and(|name| name == "lo") {
+            continue;
+        }
+        for counter in ["rx_bytes", "tx_bytes"] {
+            let path = interface.join("statistics").join(counter);
+            // Interfaces can disappear between listing and reading.
+            if let Ok(value) = fs::read_to_string(path) {
+                total += value.trim().parse::<u64>().unwrap_or_default();
+            }
+        }
+    }
+    Ok(total)
+}
@@ -0,0 +1,126 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::Sensor;
+
+/// Where udev links the keyboards' event devices, as `*-event-kbd`.
+const KEYBOARDS: &str = "/dev/input/by-path";
+/// Event type of key presses and releases.
+const EV_KEY: u16 = 0x01;
+/// Level added by each key press.
+const PRESS: f32 = 0.15;
+/// Time for the level to fall by half once typing stops.
+const HALF_LIFE: Duration = Duration::from_millis(400);
+
+/// Typing activity, read from the keyboards' evdev devices.
+///
+/// Each key press bumps the level, which decays between presses, so the faster the typing the
+/// brighter the keys. Reading the devices usually takes membership of the `input` group.
+pub struct Typing {
+    envelope: Arc<Mutex<Envelope>>,
+}
+
+/// The decaying typing level.
+struct Envelope {
+    level: f32,
+    updated: Instant,
+}
+
+impl Envelope {
+    /// Decays the level up to now.
+    fn decay(&mut self) {
+        let now = Instant::now();
+        let halvings = now.duration_since(self.updated).as_secs_f32() / HALF_LIFE.as_secs_f32();
+        self.level *= 0.5f32.powf(halvings);
+        self.updated = now;
+    }
+}
+
+impl Typing {
+    /// Opens every keyboard and reads its events from a thread each.
+    pub fn open() -> io::Result<Self> {
+        let envelope = Arc::new(Mutex::new(Envelope {
+            level: 0.0,
+            updated: Instant::now(),
+        }));
+
+        let mut keyboards = 0;
+        for path in keyboard_paths()? {
+            let device = match File::open(&path) {
+                Ok(device) => device,
+                Err(e) => {
+                    warn!("Failed to open the keyboard {}: {e}", path.display());
+                    continue;
+                }
+            };
+            keyboards += 1;
+            let envelope = Arc::clone(&envelope);
+            thread::spawn(move || {
+                if let Err(e) = read_presses(device, &envelope) {
+                    warn!("Stopped reading the keyboard {}: {e}", path.display());
+                }
+            });
+        }
+        if keyboards == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no readable keyboard in /dev/input",
+            ));
+        }
+        Ok(Self { envelope })
+    }
+}
+
+impl Sensor for Typing {
+    fn name(&self) -> String {
+        "typing".to_string()
+    }
+
+    fn level(&mut self) -> io::Result<f32> {
+        let mut envelope = self.envelope.lock().unwrap();
+        envelope.decay();
+        Ok(envelope.level)
+    }
+}
+
+/// The event devices of the keyboards.
+fn keyboard_paths() -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(KEYBOARDS)? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with("-event-kbd"))
+        {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Bumps `envelope` for each key press read from `device`, until it goes away.
+fn read_presses(mut device: File, envelope: &Mutex<Envelope>) -> io::Result<()> {
+    // struct input_event: a timeval, then the type, code and value.
+    let mut event = [0u8; size_of::<libc::input_event>()];
+    let offset = size_of::<libc::timeval>();
+    loop {
+        device.read_exact(&mut event)?;
+        let kind = u16::from_ne_bytes([event[offset], event[offset + 1]]);
+        let value = i32::from_ne_bytes([
+            event[offset + 4],
+            event[offset + 5],
+            event[offset + 6],
+            event[offset + 7],
+        ]);
+        // 1 is a press, 0 a release and 2 an autorepeat.
+        if kind == EV_KEY && value == 1 {
+            let mut envelope = envelope.lock().unwrap();
+            envelope.decay();
+            envelope.level = (envelope.level + PRESS).min(1.0);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! The hot inner loops, in `LANES`-wide blocks the compiler keeps in SIMD registers when the
+//! `simd` feature is enabled, and as plain loops otherwise.
+
+/// Number of samples processed per block, 8 fills an AVX register and two NEON ones.
+#[cfg(feature = "simd")]
+const LANES: usize = 8;
+
+/// Sum of the squares of `samples`.
+pub fn sum_squares(samples: &[f32]) -> f32 {
+    #[cfg(feature = "simd")]
+    {
+        let blocks = samples.chunks_exact(LANES);
+        let rest: f32 = blocks.remainder().iter().map(|x| x * x).sum();
+        let mut sums = [0.0f32; LANES];
+        for block in blocks {
+            for (sum, x) in sums.iter_mut().zip(block) {
+                *sum += x * x;
+            }
+        }
+        sums.iter().sum::<f32>() + rest
+    }
+    #[cfg(not(feature = "simd"))]
+    samples.iter().map(|x| x * x).sum()
+}
+
+/// Writes `samples` multiplied by `window` to `out`, zero-padding past the end of `samples`.
+pub fn apply_window(samples: &[f32], window: &[f32], out: &mut [f32]) {
+    let len = samples.len().min(out.len());
+    let (windowed, padding) = out.split_at_mut(len);
+    padding.fill(0.0);
+
+    #[cfg(feature = "simd")]
+    {
+        let mut out_blocks = windowed.chunks_exact_mut(LANES);
+        let mut sample_blocks = samples[..len].chunks_exact(LANES);
+        let mut window_blocks = window[..len].chunks_exact(LANES);
+        for ((out, samples), window) in (&mut out_blocks)
+            .zip(&mut sample_blocks)
+            .zip(&mut window_blocks)
+        {
+            for i in 0..LANES {
+                out[i] = samples[i] * window[i];
+            }
+        }
+        let rest = out_blocks.into_remainder();
+        for ((out, sample), coefficient) in rest
+            .iter_mut()
+            .zip(sample_blocks.remainder())
+            .zip(window_blocks.remainder())
+        {
+            *out = sample * coefficient;
+        }
+    }
+    #[cfg(not(feature = "simd"))]
+    for ((out, sample), coefficient) in windowed.iter_mut().zip(samples).zip(window) {
+        *out = sample * coefficient;
+    }
+}
@@ -0,0 +1,316 @@
+//! Where the brightness goes: the keyboard backlight's sysfs file, or a service setting it.
+//!
+//! The pipeline writes through the [`select`]ed [`Sink`], the sysfs one unless told otherwise.
+//! Sandboxes such as Flatpak can't write sysfs, [`auto`] then prefers the D-Bus services.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{self, SinkKind};
+use crate::{BACKLIGHT, helper};
+
+mod command;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod ddc;
+mod file;
+mod memory;
+#[cfg(feature = "sink-plugins")]
+mod plugin;
+
+pub use command::Command;
+#[cfg(feature = "dbus")]
+pub use dbus::{Logind, OpenRazer, UPower};
+pub use ddc::Ddc;
+pub use file::File;
+pub use memory::Memory;
+#[cfg(feature = "sink-plugins")]
+pub use plugin::{ABI_VERSION, Plugin};
+
+/// Shortest time between two warnings about a write the kernel didn't take as is.
+const VERIFY_WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delay before retrying after a failed write, doubled after every further failure.
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Longest delay between two attempts while writes keep failing.
+const MAX_WRITE_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The sink the pipeline writes to, sysfs until another is selected.
+static SINK: Mutex<Option<Box<dyn Sink>>> = Mutex::new(None);
+
+/// The failing writes to the selected sink, while it is degraded.
+static FAILURES: Mutex<Option<Failures>> = Mutex::new(None);
+
+/// Consecutive failed writes and when to try again.
+struct Failures {
+    count: u32,
+    retry_at: Instant,
+    error: String,
+}
+
+/// Something the brightness can be written to.
+pub trait Sink: Send {
+    /// Short name, for logs.
+    fn name(&self) -> String;
+
+    /// Sets the brightness, in percent.
+    fn set(&mut self, percent: f32) -> io::Result<()>;
+
+    /// The current brightness, in percent.
+    fn get(&mut self) -> io::Result<f32>;
+}
+
+/// Writes the brightness to `sink` from now on.
+pub fn select(sink: Box<dyn Sink>) {
+    info!("Writing the brightness to {}", sink.name());
+    *SINK.lock().unwrap() = Some(sink);
+    *FAILURES.lock().unwrap() = None;
+}
+
+/// Sets the brightness of the selected sink, in percent.
+///
+/// After a failed write the sink is degraded: writes are skipped, returning an error, until the
+/// next attempt is due [`WRITE_RETRY_DELAY`] later, then twice as long after every further
+/// failure up to [`MAX_WRITE_RETRY_DELAY`]. A successful write ends it.
+pub fn set(percent: f32) -> io::Result<()> {
+    let mut failures = FAILURES.lock().unwrap();
+    let now = Instant::now();
+    if let Some(failures) = &*failures
+        && now < failures.retry_at
+    {
+        return Err(io::Error::other(format!(
+            "skipped while retrying, {}",
+            failures.error
+        )));
+    }
+
+    let result = SINK
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| Box::new(Sysfs::new()))
+        .set(percent);
+    match &result {
+        Ok(()) => {
+            if let Some(failures) = failures.take() {
+                info!(
+                    "The brightness sink works again after {} failed writes",
+                    failures.count
+                );
+            }
+        }
+        Err(e) => {
+            let count = failures.as_ref().map_or(0, |failures| failures.count) + 1;
+            let delay = WRITE_RETRY_DELAY
+                .saturating_mul(1 << (count - 1).min(16))
+                .min(MAX_WRITE_RETRY_DELAY);
+            *failures = Some(Failures {
+                count,
+                retry_at: now + delay,
+                error: e.to_string(),
+            });
+        }
+    }
+    result
+}
+
+/// Why writes to the selected sink fail, while they are being retried.
+pub fn degraded() -> Option<String> {
+    FAILURES
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|failures| failures.error.clone())
+}
+
+/// The brightness of the selected sink, in percent.
+pub fn get() -> io::Result<f32> {
+    SINK.lock()
+        .unwrap()
+        .get_or_insert_with(|| Box::new(Sysfs::new()))
+        .get()
+}
+
+/// Opens the sink `config` asks for.
+pub fn open(config: &config::Sink) -> io::Result<Box<dyn Sink>> {
+    Ok(match config.kind {
+        SinkKind::Auto if !sandboxed() => Box::new(Sysfs::new().verify(config.verify)),
+        SinkKind::Auto => auto(),
+        SinkKind::Sysfs => Box::new(Sysfs::new().verify(config.verify)),
+        SinkKind::Command => Box::new(Command::new(
+            config.set.clone(),
+            config.get.clone(),
+            config.device.clone(),
+        )?),
+        SinkKind::Ddc => Box::new(Ddc::open(config.display)?),
+        SinkKind::File => {
+            let Some(path) = &config.path else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the file sink needs a `path`",
+                ));
+            };
+            Box::new(File::new(
+                path.clone(),
+                config.min,
+                config.max,
+                config.format.clone(),
+            ))
+        }
+        #[cfg(feature = "sink-plugins")]
+        SinkKind::Plugin => {
+            let Some(path) = &config.path else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the plugin sink needs a `path`",
+                ));
+            };
+            Box::new(Plugin::open(path, &config.options)?)
+        }
+        #[cfg(not(feature = "sink-plugins"))]
+        SinkKind::Plugin => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this sink needs the `sink-plugins` feature",
+            ));
+        }
+        #[cfg(feature = "dbus")]
+        SinkKind::Logind => Box::new(Logind::connect()?),
+        #[cfg(feature = "dbus")]
+        SinkKind::Upower => Box::new(UPower::connect()?),
+        #[cfg(feature = "dbus")]
+        SinkKind::Openrazer => Box::new(OpenRazer::connect()?),
+        #[cfg(not(feature = "dbus"))]
+        SinkKind::Logind | SinkKind::Upower | SinkKind::Openrazer => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this sink needs the `dbus` feature",
+            ));
+        }
+    })
+}
+
+/// Whether this process runs in a sandbox such as Flatpak, where sysfs is read-only.
+pub fn sandboxed() -> bool {
+    Path::new("/.flatpak-info").exists() || std::env::var_os("container").is_some()
+}
+
+/// The sink to use when none is configured: sysfs, or in a sandbox the first D-Bus service that
+/// answers (logind, UPower, then OpenRazer).
+pub fn auto() -> Box<dyn Sink> {
+    if !sandboxed() {
+        return Box::new(Sysfs::new());
+    }
+    #[cfg(feature = "dbus")]
+    {
+        info!("Running in a sandbox, looking for a D-Bus service to set the brightness");
+        for kind in [SinkKind::Logind, SinkKind::Upower, SinkKind::Openrazer] {
+            let config = config::Sink {
+                kind,
+                ..Default::default()
+            };
+            match open(&config) {
+                Ok(sink) => return sink,
+                Err(e) => debug!("Brightness service unavailable: {e}"),
+            }
+        }
+        warn!("No D-Bus service can set the brightness, trying sysfs");
+    }
+    #[cfg(not(feature = "dbus"))]
+    warn!("Running in a sandbox without the `dbus` feature, sysfs is probably read-only");
+    Box::new(Sysfs::new())
+}
+
+/// The backlight's sysfs file, written through the privileged [`helper`] if it is enabled.
+pub struct Sysfs {
+    led: PathBuf,
+    /// Whether every write is read back.
+    verify: bool,
+    /// When a write that didn't stick was last warned about.
+    warned: Option<Instant>,
+}
+
+impl Sysfs {
+    /// The keyboard backlight at [`BACKLIGHT`].
+    pub fn new() -> Self {
+        Self {
+            led: PathBuf::from(BACKLIGHT),
+            verify: false,
+            warned: None,
+        }
+    }
+
+    /// Reads the brightness back after every write if `verify`, warning at most once every
+    /// [`VERIFY_WARN_INTERVAL`] when it differs, e.g. because the LED's `max_brightness` is not
+    /// 100. Writes through the [`helper`] are not read back.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Warns if the brightness file doesn't hold `written`.
+    fn check(&mut self, written: u8) -> io::Result<()> {
+        let read = self.get()?;
+        if read == written as f32
+            || self
+                .warned
+                .is_some_and(|warned| warned.elapsed() < VERIFY_WARN_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.warned = Some(Instant::now());
+        let max = fs::read_to_string(self.led.join("max_brightness")).unwrap_or_default();
+        warn!(
+            "Wrote {written} to the backlight but it reads {read} (max_brightness {}), the \
+             kernel clamped or ignored the value",
+            max.trim()
+        );
+        Ok(())
+    }
+
+    /// Writes `percent` to the brightness file itself, even if the helper is enabled.
+    pub fn write(&self, percent: f32) -> io::Result<()> {
+        let level_whole: u8 = percent as u8;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(self.led.join("brightness"))?;
+        file.write_all(level_whole.to_string().as_bytes())
+    }
+}
+
+impl Default for Sysfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sink for Sysfs {
+    fn name(&self) -> String {
+        self.led.display().to_string()
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        if helper::enabled() {
+            // Only queued to the helper, reading it back now would race its write.
+            return helper::write(percent as u8);
+        }
+        self.write(percent)?;
+        // The write went through, failing to check it is not an error.
+        if self.verify
+            && let Err(e) = self.check(percent as u8)
+        {
+            debug!("Failed to read the brightness back: {e}");
+        }
+        Ok(())
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        let brightness = fs::read_to_string(self.led.join("brightness"))?;
+        brightness
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
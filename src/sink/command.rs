@@ -0,0 +1,93 @@
+use std::io;
+use std::process::{Command as Process, Stdio};
+
+use super::Sink;
+
+/// Runs an external tool for every change, e.g. `brightnessctl` or `light`, for setups where a
+/// setuid helper can write the backlight but this program can't.
+///
+/// The templates are split on whitespace, without a shell; `{device}` is replaced by the device
+/// and `{value}` by the brightness in whole percents.
+pub struct Command {
+    set: String,
+    get: Option<String>,
+    device: String,
+    /// The last value written, the tool only runs when it changes.
+    written: Option<u8>,
+}
+
+impl Command {
+    /// Sets with the `set` template and reads with the `get` one, whose output must be a percent.
+    pub fn new(set: String, get: Option<String>, device: String) -> io::Result<Self> {
+        if set.split_whitespace().next().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the command sink needs a `set` template",
+            ));
+        }
+        Ok(Self {
+            set,
+            get,
+            device,
+            written: None,
+        })
+    }
+
+    /// `template` with its placeholders replaced, as a command ready to run.
+    fn command(&self, template: &str, value: u8) -> Process {
+        let mut words = template.split_whitespace().map(|word| {
+            word.replace("{device}", &self.device)
+                .replace("{value}", &value.to_string())
+        });
+        let mut command = Process::new(words.next().unwrap_or_default());
+        command.args(words).stdin(Stdio::null());
+        command
+    }
+}
+
+impl Sink for Command {
+    fn name(&self) -> String {
+        format!("`{}`", self.set)
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        let value = percent.clamp(0.0, 100.0) as u8;
+        if self.written == Some(value) {
+            return Ok(());
+        }
+        let output = self
+            .command(&self.set, value)
+            .stdout(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "`{}` failed: {}",
+                self.set,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        self.written = Some(value);
+        Ok(())
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        let Some(get) = &self.get else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the command sink has no `get` template",
+            ));
+        };
+        let output = self.command(get, 0).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "`{get}` failed: {}",
+                output.status
+            )));
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .trim_end_matches('%')
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use zbus::blocking::Connection;
+use zbus::message::Message;
+use zbus::zvariant::{DynamicDeserialize, DynamicType};
+
+use super::Sink;
+use crate::BACKLIGHT;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_SESSION: &str = "/org/freedesktop/login1/session/auto";
+const UPOWER: &str = "org.freedesktop.UPower";
+const UPOWER_BACKLIGHT: &str = "/org/freedesktop/UPower/KbdBacklight";
+const UPOWER_INTERFACE: &str = "org.freedesktop.UPower.KbdBacklight";
+const RAZER: &str = "org.razer";
+const RAZER_BRIGHTNESS: &str = "razer.device.lighting.brightness";
+
+/// Sets the LED through systemd-logind, which lets the user of the active session do it.
+pub struct Logind {
+    connection: Connection,
+    /// Name of the LED, e.g. `chromeos::kbd_backlight`.
+    led: String,
+    /// Raw brightness of 100%.
+    max: u32,
+}
+
+impl Logind {
+    /// Connects to logind on the system bus, for the LED at [`BACKLIGHT`].
+    pub fn connect() -> io::Result<Self> {
+        let connection = Connection::system().map_err(io::Error::other)?;
+        // Fails early if logind isn't reachable, rather than on the first write.
+        call(
+            &connection,
+            LOGIND,
+            LOGIND_SESSION,
+            "org.freedesktop.DBus.Peer",
+            "Ping",
+            &(),
+        )?;
+        let led = Path::new(BACKLIGHT);
+        let max = fs::read_to_string(led.join("max_brightness"))
+            .ok()
+            .and_then(|max| max.trim().parse().ok())
+            .unwrap_or(100);
+        Ok(Self {
+            connection,
+            led: led
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            max,
+        })
+    }
+}
+
+impl Sink for Logind {
+    fn name(&self) -> String {
+        format!("logind ({})", self.led)
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        let raw = (percent.clamp(0.0, 100.0) / 100.0 * self.max as f32).round() as u32;
+        call(
+            &self.connection,
+            LOGIND,
+            LOGIND_SESSION,
+            "org.freedesktop.login1.Session",
+            "SetBrightness",
+            &("leds", self.led.as_str(), raw),
+        )?;
+        Ok(())
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        // logind only sets it, sysfs is readable even in a sandbox.
+        let raw: f32 = fs::read_to_string(Path::new(BACKLIGHT).join("brightness"))?
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(raw / self.max.max(1) as f32 * 100.0)
+    }
+}
+
+/// Sets the keyboard backlight through UPower.
+pub struct UPower {
+    connection: Connection,
+    /// Raw brightness of 100%.
+    max: i32,
+}
+
+impl UPower {
+    /// Connects to UPower's keyboard backlight on the system bus.
+    pub fn connect() -> io::Result<Self> {
+        let connection = Connection::system().map_err(io::Error::other)?;
+        let max = value(call(
+            &connection,
+            UPOWER,
+            UPOWER_BACKLIGHT,
+            UPOWER_INTERFACE,
+            "GetMaxBrightness",
+            &(),
+        )?)?;
+        Ok(Self { connection, max })
+    }
+}
+
+impl Sink for UPower {
+    fn name(&self) -> String {
+        "UPower".to_string()
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        let raw = (percent.clamp(0.0, 100.0) / 100.0 * self.max as f32).round() as i32;
+        call(
+            &self.connection,
+            UPOWER,
+            UPOWER_BACKLIGHT,
+            UPOWER_INTERFACE,
+            "SetBrightness",
+            &raw,
+        )?;
+        Ok(())
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        let raw: i32 = value(call(
+            &self.connection,
+            UPOWER,
+            UPOWER_BACKLIGHT,
+            UPOWER_INTERFACE,
+            "GetBrightness",
+            &(),
+        )?)?;
+        Ok(raw as f32 / self.max.max(1) as f32 * 100.0)
+    }
+}
+
+/// Sets the brightness of a Razer keyboard through the OpenRazer daemon.
+pub struct OpenRazer {
+    connection: Connection,
+    /// Object path of the device.
+    device: String,
+}
+
+impl OpenRazer {
+    /// Connects to the OpenRazer daemon on the session bus, for its first device.
+    pub fn connect() -> io::Result<Self> {
+        let connection = Connection::session().map_err(io::Error::other)?;
+        let serials: Vec<String> = value(call(
+            &connection,
+            RAZER,
+            "/org/razer",
+            "razer.devices",
+            "getDevices",
+            &(),
+        )?)?;
+        let serial = serials
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no Razer device"))?;
+        Ok(Self {
+            connection,
+            device: format!("/org/razer/device/{serial}"),
+        })
+    }
+}
+
+impl Sink for OpenRazer {
+    fn name(&self) -> String {
+        format!("OpenRazer ({})", self.device)
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        let percent = percent.clamp(0.0, 100.0) as f64;
+        call(
+            &self.connection,
+            RAZER,
+            &self.device,
+            RAZER_BRIGHTNESS,
+            "setBrightness",
+            &percent,
+        )?;
+        Ok(())
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        let percent: f64 = value(call(
+            &self.connection,
+            RAZER,
+            &self.device,
+            RAZER_BRIGHTNESS,
+            "getBrightness",
+            &(),
+        )?)?;
+        Ok(percent as f32)
+    }
+}
+
+/// Calls `method`, returning the reply.
+fn call<B>(
+    connection: &Connection,
+    destination: &str,
+    path: &str,
+    interface: &str,
+    method: &str,
+    body: &B,
+) -> io::Result<Message>
+where
+    B: serde::Serialize + DynamicType,
+{
+    connection
+        .call_method(Some(destination), path, Some(interface), method, body)
+        .map_err(io::Error::other)
+}
+
+/// The value returned in `reply`.
+fn value<R>(reply: Message) -> io::Result<R>
+where
+    R: for<'de> DynamicDeserialize<'de>,
+{
+    reply.body().deserialize().map_err(io::Error::other)
+}
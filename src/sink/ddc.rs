@@ -0,0 +1,118 @@
+use std::io;
+use std::process::{Command, Output};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::Sink;
+
+/// VCP feature code of the luminance.
+const LUMINANCE: &str = "10";
+/// Shortest time between two writes. Each one takes tens of milliseconds on the I²C bus, and
+/// monitors persist the setting, so the music's beat is out of reach anyway.
+const WRITE_INTERVAL: Duration = Duration::from_secs(1);
+/// Time constant of the smoothing, the monitor follows the mood rather than the beats.
+const SMOOTHING: Duration = Duration::from_secs(3);
+
+/// Sets an external monitor's brightness over DDC/CI with `ddcutil`, smoothed and at most once
+/// per [`WRITE_INTERVAL`] so the desk breathes with the music.
+///
+/// `ddcutil` runs on its own thread, a slow bus never holds up the pipeline.
+pub struct Ddc {
+    /// The monitor, as `ddcutil --display` numbers them, the first one if `None`.
+    display: Option<u32>,
+    /// Raw luminance of 100%.
+    max: u32,
+    /// Smoothed brightness, in percent, and when it was last updated.
+    smoothed: Option<(f32, Instant)>,
+    /// Last raw luminance sent to the writer.
+    sent: Option<u32>,
+    writer: Sender<u32>,
+}
+
+impl Ddc {
+    /// Queries the luminance range of `display`, failing if `ddcutil` can't reach it.
+    pub fn open(display: Option<u32>) -> io::Result<Self> {
+        let (_, max) = luminance(display)?;
+        let (writer, values) = mpsc::channel::<u32>();
+        thread::spawn(move || {
+            while let Ok(mut value) = values.recv() {
+                // Only the latest value matters after a slow write.
+                value = values.try_iter().last().unwrap_or(value);
+                let value = value.to_string();
+                if let Err(e) = ddcutil(display, &["setvcp", LUMINANCE, &value]) {
+                    warn!("Failed to set the monitor's brightness: {e}");
+                }
+                thread::sleep(WRITE_INTERVAL);
+            }
+        });
+        Ok(Self {
+            display,
+            max,
+            smoothed: None,
+            sent: None,
+            writer,
+        })
+    }
+}
+
+impl Sink for Ddc {
+    fn name(&self) -> String {
+        match self.display {
+            Some(display) => format!("DDC/CI display {display}"),
+            None => "DDC/CI".to_string(),
+        }
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        let now = Instant::now();
+        let (smoothed, updated) = self.smoothed.get_or_insert((percent, now));
+        let weight = 1.0 - (-(now - *updated).as_secs_f32() / SMOOTHING.as_secs_f32()).exp();
+        *smoothed += (percent - *smoothed) * weight;
+        *updated = now;
+
+        let value = (smoothed.clamp(0.0, 100.0) / 100.0 * self.max as f32).round() as u32;
+        if self.sent != Some(value) {
+            self.sent = Some(value);
+            self.writer
+                .send(value)
+                .map_err(|_| io::Error::other("the DDC/CI writer died"))?;
+        }
+        Ok(())
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        let (current, max) = luminance(self.display)?;
+        Ok(current as f32 / max.max(1) as f32 * 100.0)
+    }
+}
+
+/// The current and maximum raw luminance of `display`.
+fn luminance(display: Option<u32>) -> io::Result<(u32, u32)> {
+    let output = ddcutil(display, &["getvcp", LUMINANCE, "--brief"])?;
+    // e.g. `VCP 10 C 50 100`.
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut words = output.split_whitespace().skip(3).map(str::parse);
+    match (words.next(), words.next()) {
+        (Some(Ok(current)), Some(Ok(max))) => Ok((current, max)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected ddcutil output: {}", output.trim()),
+        )),
+    }
+}
+
+fn ddcutil(display: Option<u32>, args: &[&str]) -> io::Result<Output> {
+    let mut command = Command::new("ddcutil");
+    if let Some(display) = display {
+        command.arg("--display").arg(display.to_string());
+    }
+    let output = command.args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "ddcutil failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output)
+}
@@ -0,0 +1,62 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use super::Sink;
+
+/// Writes the brightness to any file, mapped onto the range its driver expects, for LEDs, PWM
+/// fans or drivers this program doesn't know about.
+pub struct File {
+    path: PathBuf,
+    /// Values written at 0% and 100%.
+    range: (f64, f64),
+    /// What is written, `{value}` replaced by the mapped value rounded to an integer.
+    format: String,
+}
+
+impl File {
+    /// Writes `format` to `path`, with the brightness mapped from 0-100% onto `min`-`max`.
+    pub fn new(path: PathBuf, min: f64, max: f64, format: String) -> Self {
+        Self {
+            path,
+            range: (min, max),
+            format,
+        }
+    }
+}
+
+impl Sink for File {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        let (min, max) = self.range;
+        let value = min + (percent.clamp(0.0, 100.0) as f64 / 100.0) * (max - min);
+        let contents = self
+            .format
+            .replace("{value}", &(value.round() as i64).to_string());
+        // Like sysfs attributes, the file is written over rather than truncated.
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        file.write_all(contents.as_bytes())
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        let contents = fs::read_to_string(&self.path)?;
+        // The first number in the file, whatever the format wraps it in.
+        let value: f64 = contents
+            .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .find_map(|word| word.parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("no number in {}", self.path.display()),
+                )
+            })?;
+        let (min, max) = self.range;
+        if max == min {
+            return Ok(0.0);
+        }
+        Ok(((value - min) / (max - min) * 100.0) as f32)
+    }
+}
@@ -0,0 +1,40 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use super::Sink;
+
+/// Records the brightness instead of setting it, to check what the pipeline writes without a
+/// backlight, e.g. in tests.
+///
+/// Clones share the recording, keep one to read it once another was [`select`](super::select)ed.
+#[derive(Clone, Default)]
+pub struct Memory {
+    writes: Arc<Mutex<Vec<f32>>>,
+}
+
+impl Memory {
+    /// An empty recording.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every brightness written so far, in percent, oldest first.
+    pub fn writes(&self) -> Vec<f32> {
+        self.writes.lock().unwrap().clone()
+    }
+}
+
+impl Sink for Memory {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        self.writes.lock().unwrap().push(percent);
+        Ok(())
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        Ok(self.writes.lock().unwrap().last().copied().unwrap_or(0.0))
+    }
+}
@@ -0,0 +1,149 @@
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::io;
+use std::path::Path;
+
+use libloading::Library;
+
+use super::Sink;
+
+/// Version of the plugin interface, bumped on every incompatible change.
+pub const ABI_VERSION: u32 = 1;
+/// Symbol every plugin exports, returning its [`Vtable`].
+const ENTRY_POINT: &[u8] = b"dynkb_sink_plugin";
+
+/// What a plugin exports, `struct dynkb_sink` in `dist/dynkb_sink.h`.
+#[repr(C)]
+struct Vtable {
+    abi: u32,
+    name: *const c_char,
+    open: Option<unsafe extern "C" fn(options: *const c_char) -> *mut c_void>,
+    set: Option<unsafe extern "C" fn(handle: *mut c_void, percent: f32) -> c_int>,
+    get: Option<unsafe extern "C" fn(handle: *mut c_void, percent: *mut f32) -> c_int>,
+    close: Option<unsafe extern "C" fn(handle: *mut c_void)>,
+}
+
+/// A sink loaded from a shared object at runtime, for hardware only a proprietary SDK drives.
+///
+/// The plugin exports `dynkb_sink_plugin()`, returning the functions opening, setting, reading
+/// and closing its device, which return 0 or a negative `errno`. See `dist/dynkb_sink.h`.
+pub struct Plugin {
+    name: String,
+    handle: *mut c_void,
+    vtable: *const Vtable,
+    /// Unloaded once the device is closed, last.
+    _library: Library,
+}
+
+// The plugin is called from one thread at a time, as the interface requires of it.
+unsafe impl Send for Plugin {}
+
+impl Plugin {
+    /// Loads the plugin at `path` and opens its device with `options`.
+    pub fn open(path: &Path, options: &str) -> io::Result<Self> {
+        let options =
+            CString::new(options).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: loading runs the library's initializers; the user chose to trust it.
+        let library = unsafe { Library::new(path) }.map_err(io::Error::other)?;
+        // SAFETY: the entry point has the signature `dist/dynkb_sink.h` declares.
+        let vtable = unsafe {
+            let entry = library
+                .get::<unsafe extern "C" fn() -> *const Vtable>(ENTRY_POINT)
+                .map_err(io::Error::other)?;
+            entry()
+        };
+        // SAFETY: a non-null vtable is static in the library, which outlives it.
+        let Some(functions) = (unsafe { vtable.as_ref() }) else {
+            return Err(invalid(path, "returned no sink"));
+        };
+        if functions.abi != ABI_VERSION {
+            return Err(invalid(
+                path,
+                &format!(
+                    "implements version {} of the interface, not {ABI_VERSION}",
+                    functions.abi
+                ),
+            ));
+        }
+        let (Some(open), Some(_)) = (functions.open, functions.set) else {
+            return Err(invalid(path, "lacks `open` or `set`"));
+        };
+        let name = if functions.name.is_null() {
+            path.display().to_string()
+        } else {
+            // SAFETY: the name is a static C string.
+            unsafe { CStr::from_ptr(functions.name) }
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        // SAFETY: `options` is a valid C string for the duration of the call.
+        let handle = unsafe { open(options.as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::other(format!(
+                "the {name} plugin failed to open"
+            )));
+        }
+        info!("Loaded the {name} sink plugin from {}", path.display());
+        Ok(Self {
+            name,
+            handle,
+            vtable,
+            _library: library,
+        })
+    }
+
+    fn functions(&self) -> &Vtable {
+        // SAFETY: checked non-null when loaded, and the library is still loaded.
+        unsafe { &*self.vtable }
+    }
+}
+
+impl Sink for Plugin {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set(&mut self, percent: f32) -> io::Result<()> {
+        let set = self.functions().set.expect("checked when loaded");
+        // SAFETY: the handle is open until dropped.
+        status(unsafe { set(self.handle, percent.clamp(0.0, 100.0)) })
+    }
+
+    fn get(&mut self) -> io::Result<f32> {
+        let Some(get) = self.functions().get else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("the {} plugin can't read the brightness", self.name),
+            ));
+        };
+        let mut percent = 0.0;
+        // SAFETY: the handle is open until dropped and `percent` outlives the call.
+        status(unsafe { get(self.handle, &mut percent) })?;
+        Ok(percent)
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if let Some(close) = self.functions().close {
+            // SAFETY: the handle is open, and never used again.
+            unsafe { close(self.handle) };
+        }
+    }
+}
+
+/// The error a plugin function returned, if any.
+fn status(code: c_int) -> io::Result<()> {
+    match code {
+        0 => Ok(()),
+        code if code < 0 => Err(io::Error::from_raw_os_error(-code)),
+        code => Err(io::Error::other(format!("the plugin returned {code}"))),
+    }
+}
+
+fn invalid(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} {reason}", path.display()),
+    )
+}
@@ -0,0 +1,477 @@
+//! Where the pipeline's samples come from.
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+use rtrb::{Consumer, RingBuffer};
+
+use crate::error::{Error, Result};
+use crate::{SAMPLE_RATE, STALL_TIMEOUT, notify};
+
+/// Capacity of the capture ring buffer, one second of audio.
+const RING_CAPACITY: usize = SAMPLE_RATE as usize;
+/// Longest time `read` waits before checking the ring buffer again.
+const POLL_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// How often the watchdog checks that the callback still runs.
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of stalled streams detected by the watchdog since startup.
+static STALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of times a stream stopped delivering audio and was rebuilt.
+pub fn stall_count() -> usize {
+    STALLS.load(Ordering::Relaxed)
+}
+
+/// Name of the device currently captured.
+pub(crate) static DEVICE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Name of the most recently opened input device.
+pub fn current_device() -> Option<String> {
+    DEVICE.lock().unwrap().clone()
+}
+
+/// Why the input can't be opened, while it is being retried.
+static UNAVAILABLE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Why the input can't be opened, while it is being retried; `None` once it is captured.
+pub fn unavailable() -> Option<String> {
+    UNAVAILABLE.lock().unwrap().clone()
+}
+
+pub(crate) fn set_unavailable(reason: Option<String>) {
+    *UNAVAILABLE.lock().unwrap() = reason;
+}
+
+/// Longest deviation of a callback from its expected period since the last `take_health`, in µs.
+static JITTER: AtomicU64 = AtomicU64::new(0);
+/// Fullest the ring buffer was found by `read` since the last `take_health`, in samples.
+static OCCUPANCY: AtomicUsize = AtomicUsize::new(0);
+/// Number of samples dropped since startup because processing fell behind.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// How well the capture keeps up, see [`take_health`].
+#[derive(Clone, Copy, Default)]
+pub struct StreamHealth {
+    /// Longest deviation of a callback from its expected period.
+    pub jitter: Duration,
+    /// Highest ring buffer fill, 0.0-1.0; close to 1.0 the processing is about to drop samples.
+    pub occupancy: f32,
+    /// Samples dropped since startup.
+    pub dropped: usize,
+}
+
+/// The capture health since the previous call.
+pub fn take_health() -> StreamHealth {
+    StreamHealth {
+        jitter: Duration::from_micros(JITTER.swap(0, Ordering::Relaxed)),
+        occupancy: OCCUPANCY.swap(0, Ordering::Relaxed) as f32 / RING_CAPACITY as f32,
+        dropped: DROPPED.load(Ordering::Relaxed),
+    }
+}
+
+/// A stream of mono `f32` samples feeding the pipeline.
+pub trait AudioSource {
+    /// Human-readable name, for logs and displays.
+    fn name(&self) -> String;
+
+    /// Samples per second delivered by [`read`](AudioSource::read).
+    fn sample_rate(&self) -> u32;
+
+    /// Blocks until samples are available and appends them to `buf`.
+    ///
+    /// Returns `Ok(false)` once the source has ended, `buf` is then left untouched.
+    fn read(&mut self, buf: &mut Vec<f32>) -> Result<bool>;
+}
+
+/// Captures from a cpal input device.
+///
+/// The realtime callback only mixes the frames down to mono into a preallocated ring buffer, it
+/// never allocates or blocks; the processing happens on the thread calling `read`.
+pub struct CpalSource {
+    name: String,
+    sample_rate: u32,
+    /// Samples written by the callback.
+    samples: Consumer<f32>,
+    /// Number of samples the callback dropped because the ring buffer was full.
+    overruns: Arc<AtomicUsize>,
+    /// The error that stopped the stream, reported by cpal or the watchdog.
+    errors: Receiver<Error>,
+    // Capture stops when the stream is dropped.
+    _stream: Stream,
+}
+
+/// What calls the capture callback, capture stops once it is dropped.
+enum Stream {
+    Cpal {
+        _stream: cpal::Stream,
+    },
+    /// The thread generating the samples of the [`NULL_DEVICE`], which ends once this is
+    /// dropped.
+    #[cfg(feature = "null")]
+    Null {
+        _alive: Arc<()>,
+    },
+}
+
+/// Name of the virtual input device of the `null` feature, capturing silence. Followed by a
+/// colon and a test signal, e.g. `null:bursts`, it captures that signal instead.
+#[cfg(feature = "null")]
+pub const NULL_DEVICE: &str = "null";
+
+/// The test signal `name` asks the [`NULL_DEVICE`] for, `None` if it names another device.
+#[cfg(feature = "null")]
+pub fn null_signal(name: &str) -> Option<crate::generator::Signal> {
+    use crate::generator::Signal;
+    match name.strip_prefix(NULL_DEVICE)? {
+        "" | ":silence" => Some(Signal::Silence),
+        ":sweep" => Some(Signal::Sweep),
+        ":bursts" => Some(Signal::Bursts),
+        ":white-noise" => Some(Signal::WhiteNoise),
+        ":pink-noise" => Some(Signal::PinkNoise),
+        _ => None,
+    }
+}
+
+/// The capture callback of a stream delivering `sample_rate` frames of `channels` interleaved
+/// samples per second, and the ring buffer it fills for `read` with the frames mixed down to mono.
+///
+/// The callback is watched, a stall is reported on `errors`.
+fn capture(
+    sample_rate: f32,
+    channels: usize,
+    errors: Sender<Error>,
+) -> (Consumer<f32>, Arc<AtomicUsize>, impl FnMut(&[f32]) + Send) {
+    let (mut producer, consumer) = RingBuffer::new(RING_CAPACITY);
+    let overruns = Arc::new(AtomicUsize::new(0));
+    let callback_overruns = Arc::clone(&overruns);
+    let callbacks = Arc::new(AtomicUsize::new(0));
+    let callback_count = Arc::clone(&callbacks);
+    let reader = thread::current();
+    let mut last_callback = None;
+    watch(callbacks, errors);
+    let callback = move |data: &[f32]| {
+        let dropped = if channels == 1 {
+            producer.push_partial_slice(data).1.len()
+        } else {
+            data.chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .filter(|&sample| producer.push(sample).is_err())
+                .count()
+        };
+        if dropped > 0 {
+            callback_overruns.fetch_add(dropped, Ordering::Relaxed);
+        }
+        callback_count.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        if let Some(last) = last_callback.replace(now) {
+            let interval = now.duration_since(last).as_secs_f32();
+            let expected = (data.len() / channels) as f32 / sample_rate;
+            let jitter = ((interval - expected).abs() * 1e6) as u64;
+            JITTER.fetch_max(jitter, Ordering::Relaxed);
+        }
+        reader.unpark();
+    };
+    (consumer, overruns, callback)
+}
+
+impl CpalSource {
+    /// Starts capturing from `device`.
+    ///
+    /// The calling thread is woken up as samples arrive, so it should be the one reading them;
+    /// other threads see new samples within `POLL_TIMEOUT`.
+    pub fn open(device: &cpal::Device) -> Result<Self> {
+        let name = device.name()?;
+
+        // A device that is listed but not available is held exclusively by another application.
+        let busy = || Error::Busy(name.clone());
+        let supported_config = device
+            .supported_input_configs()
+            .map_err(|e| match e {
+                cpal::SupportedStreamConfigsError::DeviceNotAvailable => busy(),
+                e => e.into(),
+            })?
+            .find(|range| {
+                (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&SAMPLE_RATE)
+            })
+            .ok_or(Error::NoConfig)?
+            .with_sample_rate(cpal::SampleRate(SAMPLE_RATE));
+
+        info!("sampleformat: {}", supported_config.sample_format());
+        info!("samplerate:   {}", supported_config.sample_rate().0);
+
+        let (errors_tx, errors_rx) = mpsc::channel();
+        let (consumer, overruns, mut callback) = capture(
+            supported_config.sample_rate().0 as f32,
+            (supported_config.channels() as usize).max(1),
+            errors_tx.clone(),
+        );
+        let stream = device.build_input_stream(
+            &supported_config.config(),
+            move |data: &[f32], _: &cpal::InputCallbackInfo| callback(data),
+            move |err| match err {
+                // The stream is gone, let the reader reopen it.
+                cpal::StreamError::DeviceNotAvailable => {
+                    let _ = errors_tx.send(err.into());
+                }
+                cpal::StreamError::BackendSpecific { .. } => {
+                    notify::alert("stream", "Audio input failed", &err.to_string());
+                }
+            },
+            None,
+        );
+        let stream = match stream {
+            Err(cpal::BuildStreamError::DeviceNotAvailable) => return Err(busy()),
+            stream => stream?,
+        };
+
+        stream.play()?;
+        Ok(Self::started(
+            name,
+            supported_config.sample_rate().0,
+            consumer,
+            overruns,
+            errors_rx,
+            Stream::Cpal { _stream: stream },
+        ))
+    }
+
+    /// Starts capturing `signal` from the [`NULL_DEVICE`], paced like a sound card, to run
+    /// everything but the hardware where there is none, e.g. in CI.
+    #[cfg(feature = "null")]
+    pub fn open_null(signal: crate::generator::Signal) -> Self {
+        let mut generator = crate::generator::Generator::new(signal, true);
+        let name = format!("{NULL_DEVICE} {}", generator.name());
+        let (errors_tx, errors_rx) = mpsc::channel();
+        let (consumer, overruns, mut callback) = capture(SAMPLE_RATE as f32, 1, errors_tx);
+        let alive = Arc::new(());
+        let stream_alive = Arc::clone(&alive);
+        thread::spawn(move || {
+            let mut block = Vec::new();
+            while Arc::strong_count(&stream_alive) > 1 {
+                block.clear();
+                // The generator never ends, and waits for its samples to be due.
+                let _ = generator.read(&mut block);
+                callback(&block);
+            }
+        });
+        Self::started(
+            name,
+            SAMPLE_RATE,
+            consumer,
+            overruns,
+            errors_rx,
+            Stream::Null { _alive: alive },
+        )
+    }
+
+    fn started(
+        name: String,
+        sample_rate: u32,
+        samples: Consumer<f32>,
+        overruns: Arc<AtomicUsize>,
+        errors: Receiver<Error>,
+        stream: Stream,
+    ) -> Self {
+        info!(device = %name, "Using input device: {name}");
+        *DEVICE.lock().unwrap() = Some(name.clone());
+        set_unavailable(None);
+        Self {
+            name,
+            sample_rate,
+            samples,
+            overruns,
+            errors,
+            _stream: stream,
+        }
+    }
+}
+
+/// Reports a stall on `errors` once `callbacks` hasn't moved for `STALL_TIMEOUT`.
+///
+/// The watchdog ends with the stream, which holds the only other reference to `callbacks`.
+fn watch(callbacks: Arc<AtomicUsize>, errors: Sender<Error>) {
+    thread::spawn(move || {
+        let mut last = callbacks.load(Ordering::Relaxed);
+        let mut since = Instant::now();
+
+        while Arc::strong_count(&callbacks) > 1 {
+            thread::sleep(WATCHDOG_INTERVAL);
+            let current = callbacks.load(Ordering::Relaxed);
+            if current != last {
+                last = current;
+                since = Instant::now();
+            } else if since.elapsed() >= STALL_TIMEOUT {
+                STALLS.fetch_add(1, Ordering::Relaxed);
+                let _ = errors.send(Error::Stalled(STALL_TIMEOUT));
+                return;
+            }
+        }
+    });
+}
+
+impl AudioSource for CpalSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        loop {
+            if let Ok(err) = self.errors.try_recv() {
+                return Err(err);
+            }
+
+            let overruns = self.overruns.swap(0, Ordering::Relaxed);
+            if overruns > 0 {
+                DROPPED.fetch_add(overruns, Ordering::Relaxed);
+                warn!("Dropped {overruns} samples, processing is falling behind");
+            }
+
+            let available = self.samples.slots();
+            OCCUPANCY.fetch_max(available, Ordering::Relaxed);
+            if let Ok(chunk) = self.samples.read_chunk(available)
+                && !chunk.is_empty()
+            {
+                let (first, second) = chunk.as_slices();
+                buf.extend_from_slice(first);
+                buf.extend_from_slice(second);
+                chunk.commit_all();
+                return Ok(true);
+            }
+
+            if self.samples.is_abandoned() {
+                return Ok(false);
+            }
+            thread::park_timeout(POLL_TIMEOUT);
+        }
+    }
+}
+
+/// Sample encodings of raw PCM, interleaved and little-endian.
+#[derive(Clone, Copy)]
+pub enum PcmFormat {
+    /// Signed 16-bit integers.
+    S16Le,
+    /// Signed 32-bit integers.
+    S32Le,
+    /// 32-bit floats in the -1.0-1.0 range.
+    F32Le,
+}
+
+impl PcmFormat {
+    /// Size of one sample, in bytes.
+    fn size(self) -> usize {
+        match self {
+            Self::S16Le => 2,
+            Self::S32Le | Self::F32Le => 4,
+        }
+    }
+
+    /// Decodes one sample of [`size`](Self::size) bytes to the -1.0-1.0 range.
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            Self::S16Le => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32_768.0,
+            Self::S32Le => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+                    / 2_147_483_648.0
+            }
+            Self::F32Le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Number of bytes read from stdin at once, about 10 ms of 16-bit stereo at 48 kHz.
+const STDIN_READ_SIZE: usize = 4096;
+
+/// Reads raw PCM piped to stdin, e.g. from `pw-cat`, `ffmpeg` or a network stream, or from
+/// another pipe.
+///
+/// Multichannel audio is mixed down to mono. The source ends when the pipe is closed.
+pub struct StdinSource {
+    name: String,
+    format: PcmFormat,
+    sample_rate: u32,
+    channels: usize,
+    stdin: Box<dyn Read + Send>,
+    /// Bytes read but not yet decoded, less than a frame.
+    pending: Vec<u8>,
+}
+
+impl StdinSource {
+    /// Reads `channels` interleaved channels of `format` at `sample_rate` Hz from stdin.
+    pub fn new(format: PcmFormat, sample_rate: u32, channels: u16) -> Self {
+        Self::from_pipe("stdin", io::stdin(), format, sample_rate, channels)
+    }
+
+    /// Like [`new`](Self::new), reading from `pipe` instead, named `name`.
+    pub fn from_pipe(
+        name: &str,
+        pipe: impl Read + Send + 'static,
+        format: PcmFormat,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Self {
+        *DEVICE.lock().unwrap() = Some(name.to_string());
+        Self {
+            name: name.to_string(),
+            format,
+            sample_rate,
+            channels: channels.max(1) as usize,
+            stdin: Box::new(pipe),
+            pending: Vec::with_capacity(STDIN_READ_SIZE),
+        }
+    }
+}
+
+impl AudioSource for StdinSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        let frame_size = self.format.size() * self.channels;
+        loop {
+            let start = self.pending.len();
+            self.pending.resize(start + STDIN_READ_SIZE, 0);
+            let result = self.stdin.read(&mut self.pending[start..]);
+            self.pending
+                .truncate(start + *result.as_ref().unwrap_or(&0));
+            match result {
+                // The pipe was closed, a trailing partial frame is dropped.
+                Ok(0) => return Ok(false),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::Stdin(e)),
+            }
+
+            let frames = self.pending.len() / frame_size;
+            if frames == 0 {
+                continue;
+            }
+            let sample_size = self.format.size();
+            for frame in self.pending.chunks_exact(frame_size) {
+                let sum: f32 = frame
+                    .chunks_exact(sample_size)
+                    .map(|sample| self.format.decode(sample))
+                    .sum();
+                buf.push(sum / self.channels as f32);
+            }
+            self.pending.drain(..frames * frame_size);
+            return Ok(true);
+        }
+    }
+}
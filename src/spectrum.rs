@@ -0,0 +1,330 @@
+//! FFT band analysis of audio chunks.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+use crate::config::{self, BandScale, Window};
+use crate::{SAMPLE_RATE, simd};
+
+/// Highest overlap between consecutive FFT frames, beyond it the frames barely differ.
+const MAX_OVERLAP: f32 = 0.9;
+/// Number of past spectra the sustained part of a bin is the median of.
+const SUSTAIN_SPECTRA: usize = 9;
+/// Number of bins on each side of a bin the transient part is the median of.
+const TRANSIENT_BINS: usize = 8;
+
+/// Computes band magnitudes from chunks of audio samples.
+pub struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    /// Precomputed window, one coefficient per FFT input sample.
+    window: Vec<f32>,
+    /// Which window `window` holds.
+    window_kind: Window,
+    /// Factor turning a bin's magnitude into the amplitude of a sine, for `window`.
+    gain: f32,
+    /// Share of each FFT frame also analyzed by the next one, 0.0 for a single frame per chunk.
+    overlap: f32,
+    /// The last FFT size of samples, the start of the frames overlapping the previous chunk.
+    history: Vec<f32>,
+    /// The windowed samples of the current chunk.
+    windowed: Vec<f32>,
+    /// FFT working buffer, reused between chunks.
+    buffer: Vec<Complex<f32>>,
+    /// Bin ranges `(start, end)` aggregated into each band.
+    edges: Vec<(usize, usize)>,
+    /// Scale and sample rate `edges` were computed for.
+    scale: (BandScale, u32),
+    /// The most recent band magnitudes, in the 0.0-1.0 range.
+    bands: Vec<f32>,
+    /// Magnitude of every bin of the previous chunk, scaled like the bands.
+    magnitudes: Vec<f32>,
+    /// Spectral flux of the most recent chunk.
+    flux: f32,
+    /// Seconds each rule of [`duck`](Self::duck) keeps its target bands down.
+    holds: Vec<f32>,
+    /// Bin magnitudes of the last [`SUSTAIN_SPECTRA`] chunks [`separate`](Self::separate)d.
+    past: VecDeque<Vec<f32>>,
+}
+
+impl Spectrum {
+    /// Creates an analyzer with an FFT of `size` samples split into `band_count` logarithmically
+    /// spaced bands.
+    pub fn new(size: usize, band_count: usize) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(size);
+        let window = window(Window::Hann, size);
+        let gain = gain(&window);
+
+        Self {
+            fft,
+            window,
+            window_kind: Window::Hann,
+            gain,
+            overlap: 0.0,
+            history: Vec::with_capacity(size * 2),
+            windowed: vec![0.0; size],
+            buffer: vec![Complex::default(); size],
+            edges: band_edges(size / 2, band_count, BandScale::Log, SAMPLE_RATE),
+            scale: (BandScale::Log, SAMPLE_RATE),
+            bands: vec![0.0; band_count],
+            magnitudes: vec![0.0; size / 2],
+            flux: 0.0,
+            holds: Vec::new(),
+            past: VecDeque::with_capacity(SUSTAIN_SPECTRA),
+        }
+    }
+
+    /// Applies `config`, for audio at `sample_rate` Hz.
+    pub fn configure(&mut self, config: &config::Spectrum, sample_rate: u32) {
+        if self.scale != (config.scale, sample_rate) {
+            self.scale = (config.scale, sample_rate);
+            self.edges = band_edges(
+                self.buffer.len() / 2,
+                self.bands.len(),
+                config.scale,
+                sample_rate,
+            );
+        }
+        if self.window_kind != config.window {
+            self.window_kind = config.window;
+            self.window = window(config.window, self.buffer.len());
+            self.gain = gain(&self.window);
+        }
+        // `nan` is valid TOML, and would make every sample start a frame.
+        self.overlap = if config.overlap.is_finite() {
+            config.overlap.clamp(0.0, MAX_OVERLAP)
+        } else {
+            0.0
+        };
+    }
+
+    /// Analyzes a chunk and returns the band magnitudes.
+    ///
+    /// Without overlap, chunks shorter than the FFT size are zero-padded and longer ones are
+    /// truncated. With overlap, frames end every hop through the chunk, starting into the
+    /// previous one, and each band keeps its highest magnitude among them.
+    pub fn process(&mut self, chunk: &[f32]) -> &[f32] {
+        self.bands.fill(0.0);
+        self.flux = 0.0;
+        if self.overlap == 0.0 {
+            self.history.clear();
+            self.analyze(chunk);
+            return &self.bands;
+        }
+
+        let size = self.buffer.len();
+        let hop = ((size as f32 * (1.0 - self.overlap)) as usize).max(1);
+        let mut history = std::mem::take(&mut self.history);
+        history.extend_from_slice(chunk);
+        // The oldest frame first, the flux compares each one to the one before.
+        for k in (0..chunk.len().div_ceil(hop)).rev() {
+            let end = history.len() - k * hop;
+            self.analyze(&history[end.saturating_sub(size)..end]);
+        }
+        history.drain(..history.len().saturating_sub(size));
+        self.history = history;
+        &self.bands
+    }
+
+    /// Analyzes one FFT frame, raising the bands and the flux to its own.
+    fn analyze(&mut self, frame: &[f32]) {
+        simd::apply_window(frame, &self.window, &mut self.windowed);
+        for (slot, &sample) in self.buffer.iter_mut().zip(&self.windowed) {
+            *slot = Complex::new(sample, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+
+        for (band, &(start, end)) in self.bands.iter_mut().zip(&self.edges) {
+            let peak = self.buffer[start..end]
+                .iter()
+                .map(|c| c.norm())
+                .fold(0.0f32, f32::max);
+            *band = band.max((peak * self.gain).clamp(0.0, 1.0));
+        }
+
+        // Only rising bins count, a sound fading out isn't news.
+        let mut rises = 0.0;
+        for (previous, bin) in self.magnitudes.iter_mut().zip(&self.buffer) {
+            let magnitude = bin.norm() * self.gain;
+            rises += (magnitude - *previous).max(0.0).powi(2);
+            *previous = magnitude;
+        }
+        // A sine through a Hann window spreads over three bins of relative magnitudes 1, 1/2 and
+        // 1/2: one starting from silence gives its RMS, like the level it replaces.
+        self.flux = self.flux.max((rises / 3.0).sqrt());
+    }
+
+    /// Lowers the target bands of each rule while its trigger bands hit, `chunk_secs` after the
+    /// previous chunk.
+    pub fn duck(&mut self, rules: &[config::Duck], chunk_secs: f32) {
+        self.holds.resize(rules.len(), 0.0);
+        let last = self.bands.len().saturating_sub(1);
+        // Hits are judged on the bands as analyzed, before any rule lowered them.
+        let hits: Vec<bool> = rules
+            .iter()
+            .map(|rule| {
+                let [first, end] = rule.trigger.map(|band| band.min(last));
+                self.bands[first..=end.max(first)]
+                    .iter()
+                    .any(|&band| band >= rule.threshold)
+            })
+            .collect();
+        for ((rule, hold), hit) in rules.iter().zip(&mut self.holds).zip(hits) {
+            *hold = if hit {
+                rule.hold_ms as f32 / 1000.0
+            } else {
+                (*hold - chunk_secs).max(0.0)
+            };
+            if *hold > 0.0 {
+                let [first, end] = rule.target.map(|band| band.min(last));
+                for band in &mut self.bands[first..=end.max(first)] {
+                    *band *= 1.0 - rule.depth.clamp(0.0, 1.0);
+                }
+            }
+        }
+    }
+
+    /// Splits the last chunk analyzed into its transient and sustained parts, and returns their
+    /// levels on the same scale as an RMS level.
+    ///
+    /// Like harmonic-percussive separation, a bin is sustained as far as it matches its median
+    /// over the past spectra, and transient as far as it matches its median over the
+    /// neighbouring bins: steady tones are narrow and last, hits are broadband and short.
+    pub fn separate(&mut self) -> (f32, f32) {
+        let mut spectrum = if self.past.len() == SUSTAIN_SPECTRA {
+            self.past.pop_front().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        spectrum.clone_from(&self.magnitudes);
+        self.past.push_back(spectrum);
+
+        let bins = self.magnitudes.len();
+        let mut over_time = Vec::with_capacity(SUSTAIN_SPECTRA);
+        let mut over_bins = Vec::with_capacity(TRANSIENT_BINS * 2 + 1);
+        let (mut transient, mut sustain) = (0.0, 0.0);
+        for (bin, &magnitude) in self.magnitudes.iter().enumerate() {
+            over_time.clear();
+            over_time.extend(self.past.iter().map(|spectrum| spectrum[bin]));
+            let sustained = median(&mut over_time).powi(2);
+            over_bins.clear();
+            over_bins.extend_from_slice(
+                &self.magnitudes
+                    [bin.saturating_sub(TRANSIENT_BINS)..(bin + TRANSIENT_BINS + 1).min(bins)],
+            );
+            let transients = median(&mut over_bins).powi(2);
+
+            // Soft masks, each part gets its share of the bin's energy.
+            if sustained + transients > 0.0 {
+                let energy = magnitude.powi(2);
+                transient += energy * transients / (sustained + transients);
+                sustain += energy * sustained / (sustained + transients);
+            }
+        }
+        // Scaled like the flux, a sine gives its RMS.
+        ((transient / 3.0).sqrt(), (sustain / 3.0).sqrt())
+    }
+
+    /// The spectral flux of the last chunk analyzed: how much louder the bins got since the
+    /// previous one, on the same scale as an RMS level.
+    pub fn flux(&self) -> f32 {
+        self.flux
+    }
+
+    /// The band magnitudes of the last chunk analyzed.
+    pub fn bands(&self) -> &[f32] {
+        &self.bands
+    }
+
+    /// The strongest bin of the last chunk analyzed, and the share of the energy within two bins
+    /// of it: close to 1.0 for a pure tone, low for noise or rich sounds.
+    pub fn peak(&self) -> (usize, f32) {
+        let energies: Vec<f32> = self.buffer[1..self.buffer.len() / 2]
+            .iter()
+            .map(|c| c.norm_sqr())
+            .collect();
+        let (peak, _) = energies
+            .iter()
+            .enumerate()
+            .fold((0, 0.0f32), |best, (i, &energy)| {
+                if energy > best.1 { (i, energy) } else { best }
+            });
+        let total: f32 = energies.iter().sum();
+        let around: f32 = energies[peak.saturating_sub(2)..(peak + 3).min(energies.len())]
+            .iter()
+            .sum();
+        let purity = if total > 0.0 { around / total } else { 0.0 };
+        // Skipped DC.
+        (peak + 1, purity)
+    }
+}
+
+/// Splits `bins` FFT bins of audio at `sample_rate` Hz into `count` non-empty ranges evenly spaced
+/// on `scale` (skipping DC).
+fn band_edges(
+    bins: usize,
+    count: usize,
+    scale: BandScale,
+    sample_rate: u32,
+) -> Vec<(usize, usize)> {
+    // Width of a bin, the Nyquist frequency being the last one.
+    let bin_hz = sample_rate as f32 / 2.0 / bins as f32;
+    let (low, high) = (warp(scale, bin_hz), warp(scale, bins as f32 * bin_hz));
+    let mut edges = Vec::with_capacity(count);
+    let mut start = 1;
+    for i in 1..=count {
+        let hz = unwarp(scale, low + (high - low) * i as f32 / count as f32);
+        let end = ((hz / bin_hz).round() as usize).max(start + 1).min(bins);
+        edges.push((start.min(bins - 1), end));
+        start = end;
+    }
+    edges
+}
+
+/// The middle value of `values`, which end up sorted.
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_unstable_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+/// The coefficients of `kind` for an FFT of `size` samples.
+fn window(kind: Window, size: usize) -> Vec<f32> {
+    (0..size)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / (size - 1) as f32;
+            match kind {
+                Window::Hann => 0.5 - 0.5 * phase.cos(),
+                Window::Hamming => 0.54 - 0.46 * phase.cos(),
+                Window::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+                Window::Rectangular => 1.0,
+            }
+        })
+        .collect()
+}
+
+/// Factor turning the magnitude of a bin into the amplitude of the sine it holds: a full-scale
+/// sine peaks at half the sum of the window, size / 4 for Hann.
+fn gain(window: &[f32]) -> f32 {
+    2.0 / window.iter().sum::<f32>()
+}
+
+/// `hz` on `scale`.
+fn warp(scale: BandScale, hz: f32) -> f32 {
+    match scale {
+        BandScale::Log => hz.ln(),
+        BandScale::Mel => 2595.0 * (1.0 + hz / 700.0).log10(),
+        // Traunmüller's approximation.
+        BandScale::Bark => 26.81 * hz / (1960.0 + hz) - 0.53,
+    }
+}
+
+/// The frequency in Hz of `value` on `scale`, the inverse of [`warp`].
+fn unwarp(scale: BandScale, value: f32) -> f32 {
+    match scale {
+        BandScale::Log => value.exp(),
+        BandScale::Mel => 700.0 * (10f32.powf(value / 2595.0) - 1.0),
+        BandScale::Bark => 1960.0 * (value + 0.53) / (26.28 - value),
+    }
+}
@@ -0,0 +1,168 @@
+//! Replacing the scripts and plugins of the running pipeline, e.g. from the control socket.
+//!
+//! A script is loaded and checked by the caller, off the audio thread, then handed over to be
+//! picked up with the next chunk; the audio stream keeps running throughout.
+
+#[cfg(any(feature = "lua", feature = "rhai", feature = "wasm"))]
+use std::sync::Mutex;
+
+use crate::SharedParams;
+#[cfg(not(all(feature = "lua", feature = "rhai", feature = "wasm")))]
+use crate::error::Error;
+use crate::error::Result;
+use crate::expression::Expression;
+
+/// What the pipeline picks up with the next chunk.
+#[cfg(any(feature = "lua", feature = "rhai", feature = "wasm"))]
+static PENDING: Mutex<Vec<Swap>> = Mutex::new(Vec::new());
+
+/// A replacement for one of the effects.
+#[cfg(any(feature = "lua", feature = "rhai", feature = "wasm"))]
+pub(crate) enum Swap {
+    /// Runs `map.lua`'s replacement, or no Lua mapping.
+    #[cfg(feature = "lua")]
+    Lua(Option<crate::lua::Mapping>),
+    /// Runs `effect.rhai`'s replacement, or no Rhai effect.
+    #[cfg(feature = "rhai")]
+    Rhai(Option<Box<crate::rhai::Effect>>),
+    /// Runs this plugin in the stages following the config's `plugin`.
+    #[cfg(feature = "wasm")]
+    Plugin(crate::wasm::Plugin),
+}
+
+/// The effects swapped in or out over the control socket that the config also sets, kept when
+/// the config is reloaded or another profile is selected; `Some(None)` once unloaded.
+#[derive(Clone, Default)]
+pub struct Swapped {
+    /// Replaces the profile's `mapping`.
+    pub mapping: Option<Option<Expression>>,
+    /// Replaces the config's `plugin`.
+    pub plugin: Option<Option<String>>,
+}
+
+impl Swapped {
+    /// The kinds overriding the config.
+    pub fn kinds(&self) -> impl Iterator<Item = Kind> {
+        [
+            (Kind::Mapping, self.mapping.is_some()),
+            (Kind::Plugin, self.plugin.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(kind, swapped)| swapped.then_some(kind))
+    }
+}
+
+/// The effects that can be replaced.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// The Lua mapping, from a script.
+    Lua,
+    /// The Rhai effect, from a script.
+    Rhai,
+    /// The WASM plugin, by name.
+    Plugin,
+    /// The mapping expression of [`Params::mapping`](crate::Params::mapping).
+    Mapping,
+}
+
+impl Kind {
+    /// The kind called `name`, as in the control socket's requests.
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "lua" => Self::Lua,
+            "rhai" => Self::Rhai,
+            "plugin" => Self::Plugin,
+            "mapping" => Self::Mapping,
+            _ => return None,
+        })
+    }
+
+    /// Name of the kind, as in the control socket's requests.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lua => "lua",
+            Self::Rhai => "rhai",
+            Self::Plugin => "plugin",
+            Self::Mapping => "mapping",
+        }
+    }
+}
+
+/// Loads `source` as the new effect of `kind`: a script's path, a plugin's name or an
+/// expression. Failing to load leaves the running one alone. A plugin or mapping is kept over
+/// the config's, see [`Swapped`].
+pub fn load(kind: Kind, source: &str, params: &SharedParams) -> Result<()> {
+    match kind {
+        #[cfg(feature = "lua")]
+        Kind::Lua => push(Swap::Lua(Some(crate::lua::Mapping::open(source.into())?))),
+        #[cfg(feature = "rhai")]
+        Kind::Rhai => push(Swap::Rhai(Some(Box::new(crate::rhai::Effect::open(
+            source.into(),
+        )?)))),
+        #[cfg(feature = "wasm")]
+        Kind::Plugin => {
+            let plugin = crate::wasm::Plugin::load(source)?;
+            // Selected first, the stages then keep it rather than loading it themselves.
+            let mut params = params.lock().unwrap();
+            params.swapped.plugin = Some(Some(source.to_string()));
+            params.plugin = Some(source.to_string());
+            push(Swap::Plugin(plugin));
+        }
+        Kind::Mapping => {
+            let mapping = Expression::parse(source)?;
+            let mut params = params.lock().unwrap();
+            params.swapped.mapping = Some(Some(mapping.clone()));
+            params.mapping = Some(mapping);
+        }
+        #[cfg(not(all(feature = "lua", feature = "rhai", feature = "wasm")))]
+        _ => return Err(unavailable(kind)),
+    }
+    info!("Swapped in the {} {source}", kind.name());
+    Ok(())
+}
+
+/// Stops the effect of `kind`, the pipeline passing the brightness through it. A plugin or
+/// mapping stays stopped when the config sets one, see [`Swapped`].
+pub fn unload(kind: Kind, params: &SharedParams) -> Result<()> {
+    match kind {
+        #[cfg(feature = "lua")]
+        Kind::Lua => push(Swap::Lua(None)),
+        #[cfg(feature = "rhai")]
+        Kind::Rhai => push(Swap::Rhai(None)),
+        #[cfg(feature = "wasm")]
+        Kind::Plugin => {
+            let mut params = params.lock().unwrap();
+            params.swapped.plugin = Some(None);
+            params.plugin = None;
+        }
+        Kind::Mapping => {
+            let mut params = params.lock().unwrap();
+            params.swapped.mapping = Some(None);
+            params.mapping = None;
+        }
+        #[cfg(not(all(feature = "lua", feature = "rhai", feature = "wasm")))]
+        _ => return Err(unavailable(kind)),
+    }
+    info!("Swapped out the {}", kind.name());
+    Ok(())
+}
+
+/// The replacements handed over since the last call.
+#[cfg(any(feature = "lua", feature = "rhai", feature = "wasm"))]
+pub(crate) fn take() -> Vec<Swap> {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.is_empty() {
+        return Vec::new();
+    }
+    std::mem::take(&mut *pending)
+}
+
+#[cfg(any(feature = "lua", feature = "rhai", feature = "wasm"))]
+fn push(swap: Swap) {
+    PENDING.lock().unwrap().push(swap);
+}
+
+#[cfg(not(all(feature = "lua", feature = "rhai", feature = "wasm")))]
+fn unavailable(kind: Kind) -> Error {
+    Error::Swap(format!("{} effects are not built in", kind.name()))
+}
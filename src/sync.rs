@@ -0,0 +1,101 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use backlight_mic::{Levels, RmsState, process_brightness};
+
+/// How long a follower waits for the leader before letting the keyboard go dark.
+const LEADER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// One chunk of the leader's analysis, as sent to the followers.
+#[derive(Serialize, Deserialize)]
+struct Frame {
+    /// Audio-driven brightness in percent, before each follower's own mix and maximum.
+    brightness: f32,
+    beat: bool,
+}
+
+/// Sends every chunk's brightness and beat to the multicast `group` from its own thread,
+/// returning where to send the levels.
+///
+/// Failing to resolve the group is logged, it never brings down the rest of the program.
+pub fn lead(group: String) -> Sender<Levels> {
+    let (levels_tx, levels_rx) = mpsc::channel();
+    let socket = match group
+        .parse::<SocketAddrV4>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        .and_then(|group| {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            socket.connect(group)?;
+            Ok(socket)
+        }) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to lead on {group}: {e}");
+            return levels_tx;
+        }
+    };
+    info!("Leading the followers on {group}");
+
+    thread::spawn(move || send(levels_rx, &socket));
+    levels_tx
+}
+
+fn send(levels: Receiver<Levels>, socket: &UdpSocket) {
+    for levels in levels {
+        let frame = Frame {
+            brightness: levels.brightness,
+            beat: levels.beat,
+        };
+        // Nobody following is not an error.
+        if let Ok(bytes) = serde_json::to_vec(&frame) {
+            let _ = socket.send(&bytes);
+        }
+    }
+}
+
+/// Drives the pipeline from the frames a leader sends to the multicast `group`, forever.
+pub fn follow(group: &str, state: &mut RmsState) -> io::Result<()> {
+    let group: SocketAddrV4 = group
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, group.port()))?;
+    socket.join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_read_timeout(Some(LEADER_TIMEOUT))?;
+    info!("Following the leader on {group}");
+
+    let mut buf = [0; 512];
+    let mut leading = false;
+    loop {
+        match socket.recv(&mut buf) {
+            Ok(len) => match serde_json::from_slice::<Frame>(&buf[..len]) {
+                Ok(frame) => {
+                    if !leading {
+                        info!("Following a leader");
+                        leading = true;
+                    }
+                    process_brightness(frame.brightness, frame.beat, state);
+                }
+                Err(e) => warn!("Ignoring a malformed frame: {e}"),
+            },
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if leading {
+                    warn!("The leader went quiet, waiting for it");
+                    leading = false;
+                }
+                process_brightness(0.0, false, state);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
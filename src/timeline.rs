@@ -0,0 +1,32 @@
+use std::io::{self, Write};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use backlight_mic::Levels;
+
+/// First line of the timelines.
+const HEADER: &str = "time,rms,normalized,brightness,beat";
+
+/// Writes every chunk's levels to `out` as CSV until the pipeline stops.
+///
+/// Chunks are timed by their position in the audio rather than by the clock, and the levels
+/// rounded, so the same input always gives the same timeline.
+pub fn write(
+    levels: Receiver<Levels>,
+    chunk_size: Duration,
+    mut out: impl Write,
+) -> io::Result<()> {
+    writeln!(out, "{HEADER}")?;
+    for (i, levels) in levels.into_iter().enumerate() {
+        writeln!(
+            out,
+            "{:.3},{:.4},{:.4},{:.2},{}",
+            i as f64 * chunk_size.as_secs_f64(),
+            levels.rms,
+            levels.normalized,
+            levels.brightness,
+            levels.beat as u8
+        )?;
+    }
+    out.flush()
+}
@@ -0,0 +1,101 @@
+use ksni::blocking::TrayMethods;
+use ksni::menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem, SubMenu};
+use ksni::{MenuItem, Tray};
+
+use backlight_mic::SharedParams;
+use backlight_mic::config::Config;
+
+/// The tray icon and its menu.
+struct Backlight {
+    params: SharedParams,
+    /// The loaded config, written back when a profile is selected.
+    config: Config,
+}
+
+/// Shows the tray icon; its menu runs on a background thread owned by ksni.
+pub fn spawn(params: SharedParams, config: Config) {
+    if let Err(e) = (Backlight { params, config }).spawn() {
+        error!("Failed to show the tray icon: {e}");
+    }
+}
+
+impl Backlight {
+    fn select_profile(&mut self, index: usize) {
+        let Some((name, profile)) = self.config.profiles.iter().nth(index) else {
+            return;
+        };
+        profile.apply(&mut self.params.lock().unwrap());
+        info!("Switched to profile {name}");
+
+        self.config.profile = name.clone();
+        if let Err(e) = self.config.save() {
+            error!("Failed to save the selected profile: {e}");
+        }
+    }
+}
+
+impl Tray for Backlight {
+    fn id(&self) -> String {
+        "dynkbacklight".into()
+    }
+
+    fn title(&self) -> String {
+        "DynKBacklight".into()
+    }
+
+    fn icon_name(&self) -> String {
+        "keyboard-brightness-symbolic".into()
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let paused = self.params.lock().unwrap().paused;
+        let selected = self
+            .config
+            .profiles
+            .keys()
+            .position(|name| *name == self.config.profile)
+            .unwrap_or(0);
+
+        vec![
+            CheckmarkItem {
+                label: "Paused".into(),
+                checked: paused,
+                activate: Box::new(|tray: &mut Self| {
+                    let mut params = tray.params.lock().unwrap();
+                    params.paused = !params.paused;
+                }),
+                ..Default::default()
+            }
+            .into(),
+            SubMenu {
+                label: "Profile".into(),
+                submenu: vec![
+                    RadioGroup {
+                        selected,
+                        select: Box::new(|tray: &mut Self, index| tray.select_profile(index)),
+                        options: self
+                            .config
+                            .profiles
+                            .keys()
+                            .map(|name| RadioItem {
+                                label: name.clone(),
+                                ..Default::default()
+                            })
+                            .collect(),
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            StandardItem {
+                label: "Quit".into(),
+                icon_name: "application-exit".into(),
+                activate: Box::new(|_| crate::quit()),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
@@ -0,0 +1,92 @@
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use backlight_mic::config::color_brightness;
+use backlight_mic::{Forced, SharedParams};
+
+/// How long `level` and `color` hold when no duration is given.
+const HOLD: Duration = Duration::from_secs(1);
+/// Longest a command holds, longer durations are cut down to it.
+const MAX_HOLD: Duration = Duration::from_secs(60 * 60);
+
+/// Applies the commands received as UDP datagrams on `address` from its own thread.
+///
+/// Each datagram is one command, its brightness replaces the audio-driven one until it expires:
+/// `level 0.8 [duration]`, `flash <duration>` (full brightness) or `color #rrggbb [duration]`
+/// (the color's brightness, the backlight has a single color). Durations are like `200ms` or
+/// `1.5s`, at most [`MAX_HOLD`]. Failing to listen is logged, it never brings down the rest of the program.
+pub fn spawn(address: &str, params: SharedParams) {
+    let socket = match UdpSocket::bind(address) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to listen for UDP commands on {address}: {e}");
+            return;
+        }
+    };
+    info!("Listening for UDP commands on {address}");
+
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        loop {
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("Failed to receive a UDP command: {e}");
+                    continue;
+                }
+            };
+            let command = String::from_utf8_lossy(&buf[..len]);
+            match parse(command.trim()) {
+                Ok((brightness, duration)) => {
+                    params.lock().unwrap().forced = Some(Forced {
+                        brightness,
+                        until: Instant::now() + duration,
+                    });
+                }
+                Err(e) => warn!("Ignoring UDP command `{}`: {e}", command.trim()),
+            }
+        }
+    });
+}
+
+/// The brightness in percent a command sets, and for how long.
+fn parse(command: &str) -> Result<(f32, Duration), String> {
+    let mut words = command.split_whitespace();
+    let verb = words.next().unwrap_or_default();
+    let value = words.next();
+    let duration = match words.next() {
+        Some(duration) => Some(parse_duration(duration)?),
+        None => None,
+    };
+
+    match (verb, value) {
+        ("level", Some(level)) => {
+            let level: f32 = level.parse().map_err(|_| "the level is not a number")?;
+            Ok((level.clamp(0.0, 1.0) * 100.0, duration.unwrap_or(HOLD)))
+        }
+        ("flash", Some(duration)) => Ok((100.0, parse_duration(duration)?)),
+        ("color", Some(color)) => {
+            let brightness = color_brightness(color).ok_or("expected a color like #ff0000")?;
+            Ok((brightness, duration.unwrap_or(HOLD)))
+        }
+        _ => Err("expected `level`, `flash` or `color` followed by a value".to_string()),
+    }
+}
+
+/// Parses `200ms` or `1.5s`, capped at [`MAX_HOLD`].
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = text.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = text.strip_suffix('s') {
+        (s, 1.0)
+    } else {
+        return Err(format!("`{text}` has no unit, use ms or s"));
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|number| Duration::try_from_secs_f64(number * scale).ok())
+        .map(|duration| duration.min(MAX_HOLD))
+        .ok_or_else(|| format!("`{text}` is not a duration"))
+}
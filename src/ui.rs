@@ -1,42 +1,555 @@
-use gtk4::{Application, ApplicationWindow, glib};
-use gtk4::{Button, prelude::*};
+use std::io;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
 
-const APP_ID: &str = "org.gtk_rs.HelloWorld2";
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::symbols::Marker;
+use ratatui::text::Line;
+use ratatui::widgets::{
+    Axis, Bar, BarChart, BarGroup, Block, Chart, Dataset, Gauge, GraphType, List, ListState,
+    Paragraph,
+};
+use ratatui::{DefaultTerminal, Frame};
 
-pub fn run_ui() -> glib::ExitCode {
-    // Create a new application
-    let app = Application::builder().application_id(APP_ID).build();
+use backlight_mic::calibration::{Calibrated, Calibration, Step};
+use backlight_mic::config::{Config, ThresholdMode};
+use backlight_mic::log::{self, Level};
+use backlight_mic::source::{stall_count, unavailable};
+use backlight_mic::{
+    Levels, Params, SharedParams, Timing, brightness_for, input_device_names, restore,
+};
 
-    // Connect to "activate" signal of `app`
-    app.connect_activate(build_ui);
+/// How long to wait for a key press before redrawing.
+const FRAME_TIME: Duration = Duration::from_millis(33);
+/// Step applied to the threshold factor by the sensitivity keys.
+const SENSITIVITY_STEP: f32 = 0.05;
+/// Step applied to the boost exponent by the bracket keys.
+const BOOST_STEP: f32 = 0.1;
+/// Factor applied to attack and release by their keys.
+const ENVELOPE_STEP: f32 = 1.25;
+/// Lines scrolled through the log pane per page key press.
+const LOG_PAGE: usize = 5;
 
-    // Run the application
-    app.run()
+/// Whether the UI currently owns the terminal.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Parameters displayed alongside the live levels.
+pub struct Settings {
+    pub device: String,
+    /// Starts on the device picker, for when no device was configured yet.
+    pub pick_device: bool,
+    pub sample_rate: u32,
+    pub timing: Timing,
+    /// The live mapping parameters, updated by calibration.
+    pub params: SharedParams,
+    /// The loaded config, written back when calibration succeeds or a device is picked.
+    pub config: Config,
+    /// Where to send the name of a newly picked device.
+    pub switch_device: Sender<String>,
+}
+
+/// What the UI is currently showing.
+enum Screen {
+    Monitor,
+    Calibrating(Calibration),
+    /// The outcome of the last calibration, with the save error if any.
+    Calibrated(Result<Calibrated, String>),
+    PickDevice(Picker),
+}
+
+/// The input devices offered by the device picker.
+struct Picker {
+    devices: Vec<String>,
+    list: ListState,
 }
 
-fn build_ui(app: &Application) {
-    // Create a button with label and margins
-    let button = Button::builder()
-        .label("Press me!")
-        .margin_top(12)
-        .margin_bottom(12)
-        .margin_start(12)
-        .margin_end(12)
-        .build();
+impl Picker {
+    /// Enumerates the input devices, preselecting the one in use.
+    fn new(current: &str) -> Self {
+        let devices = input_device_names();
+        let selected = devices.iter().position(|name| name == current).unwrap_or(0);
+        Self {
+            devices,
+            list: ListState::default().with_selected(Some(selected)),
+        }
+    }
 
-    // Connect to "clicked" signal of `button`
-    button.connect_clicked(|button| {
-        // Set the label to "Hello World!" after the button has been clicked on
-        button.set_label("Hello World!");
+    fn selected(&self) -> Option<&String> {
+        self.list.selected().and_then(|i| self.devices.get(i))
+    }
+}
+
+/// Starts the UI on its own thread and returns where to send the levels.
+///
+/// Quitting the UI exits the whole process.
+pub fn spawn(settings: Settings) -> Sender<Levels> {
+    let (levels_tx, levels_rx) = mpsc::channel();
+    thread::spawn(move || {
+        if let Err(e) = run_ui(levels_rx, settings) {
+            error!("UI error: {e}");
+        }
+        restore::restore();
+        exit(0);
     });
+    levels_tx
+}
+
+/// Takes over the terminal and renders the levels until the user quits.
+pub fn run_ui(levels: Receiver<Levels>, mut settings: Settings) -> io::Result<()> {
+    log::set_capture(true);
+    let mut terminal = ratatui::init();
+    ACTIVE.store(true, Ordering::SeqCst);
+    let result = event_loop(&mut terminal, &levels, &mut settings);
+    release_terminal();
+    result
+}
+
+/// Restores the terminal if the UI had taken it over, so the process can exit from any thread.
+pub fn release_terminal() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        ratatui::restore();
+        log::set_capture(false);
+    }
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    levels: &Receiver<Levels>,
+    settings: &mut Settings,
+) -> io::Result<()> {
+    let mut current = Levels::default();
+    let mut screen = if settings.pick_device {
+        Screen::PickDevice(Picker::new(&settings.device))
+    } else {
+        Screen::Monitor
+    };
+    // How many lines the log pane is scrolled up from the newest entry.
+    let mut log_scroll: usize = 0;
+
+    loop {
+        // Only the latest snapshot is drawn, but calibration needs to see every one of them.
+        loop {
+            match levels.try_recv() {
+                Ok(next) => {
+                    if let Screen::Calibrating(calibration) = &mut screen
+                        && let Some(outcome) = calibration.push(next.rms)
+                    {
+                        screen = Screen::Calibrated(outcome.and_then(|c| apply(c, settings)));
+                    }
+                    current = next;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        terminal.draw(|frame| match &mut screen {
+            Screen::Monitor => draw_monitor(frame, &current, settings, log_scroll),
+            Screen::Calibrating(calibration) => draw_calibrating(frame, &current, calibration),
+            Screen::Calibrated(outcome) => draw_calibrated(frame, outcome, settings),
+            Screen::PickDevice(picker) => draw_picker(frame, picker),
+        })?;
+
+        if event::poll(FRAME_TIME)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            if key.code == KeyCode::Char('q')
+                || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+            {
+                return Ok(());
+            }
+
+            match &mut screen {
+                Screen::Monitor => match key.code {
+                    KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('c') => screen = Screen::Calibrating(Calibration::new()),
+                    KeyCode::Char('d') => {
+                        screen = Screen::PickDevice(Picker::new(&settings.device))
+                    }
+                    KeyCode::PageUp => log_scroll = (log_scroll + LOG_PAGE).min(log::count()),
+                    KeyCode::PageDown => log_scroll = log_scroll.saturating_sub(LOG_PAGE),
+                    KeyCode::End => log_scroll = 0,
+                    code => tune(code, &mut settings.params.lock().unwrap()),
+                },
+                Screen::PickDevice(picker) => match key.code {
+                    KeyCode::Up | KeyCode::Char('k') => picker.list.select_previous(),
+                    KeyCode::Down | KeyCode::Char('j') => picker.list.select_next(),
+                    KeyCode::Enter => {
+                        if let Some(name) = picker.selected()
+                            && let Err(e) = pick_device(name.clone(), settings)
+                        {
+                            error!("{e}");
+                        }
+                        screen = Screen::Monitor;
+                    }
+                    KeyCode::Esc => screen = Screen::Monitor,
+                    _ => {}
+                },
+                Screen::Calibrating(_) | Screen::Calibrated(_) => {
+                    if matches!(key.code, KeyCode::Esc | KeyCode::Enter) {
+                        screen = Screen::Monitor;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Switches the capture to `name` and persists the choice to the config.
+fn pick_device(name: String, settings: &mut Settings) -> Result<(), String> {
+    settings
+        .switch_device
+        .send(name.clone())
+        .map_err(|_| "The audio thread is not accepting device changes".to_string())?;
+
+    settings.device = name.clone();
+    settings.config.device = Some(name);
+    settings.config.save().map_err(|e| {
+        format!(
+            "Device selected but not saved to {}: {e}",
+            Config::path().display()
+        )
+    })
+}
+
+/// Adjusts the live parameters for a tuning hotkey, ignoring any other key.
+fn tune(code: KeyCode, params: &mut Params) {
+    match code {
+        KeyCode::Char('+') | KeyCode::Char('=') => {
+            params.threshold_factor = (params.threshold_factor - SENSITIVITY_STEP).max(0.5);
+        }
+        KeyCode::Char('-') => {
+            params.threshold_factor = (params.threshold_factor + SENSITIVITY_STEP).min(5.0);
+        }
+        KeyCode::Char('[') => params.boost = (params.boost - BOOST_STEP).max(0.1),
+        KeyCode::Char(']') => params.boost = (params.boost + BOOST_STEP).min(5.0),
+        KeyCode::Char('a') => params.attack = (params.attack * ENVELOPE_STEP).min(10_000.0),
+        KeyCode::Char('A') => params.attack = (params.attack / ENVELOPE_STEP).max(1.0),
+        KeyCode::Char('r') => params.release = (params.release * ENVELOPE_STEP).min(10_000.0),
+        KeyCode::Char('R') => params.release = (params.release / ENVELOPE_STEP).max(1.0),
+        KeyCode::Char(' ') => params.paused = !params.paused,
+        _ => {}
+    }
+}
+
+/// Uses a calibration for the live mapping and persists it to the config.
+fn apply(calibrated: Calibrated, settings: &mut Settings) -> Result<Calibrated, String> {
+    {
+        let mut params = settings.params.lock().unwrap();
+        params.min_rms = calibrated.noise_floor;
+        params.max_rms = calibrated.peak;
+        params.boost = calibrated.boost;
+    }
+
+    settings.config.calibrate(&calibrated);
+    settings.config.save().map_err(|e| {
+        format!(
+            "Calibration applied but not saved to {}: {e}",
+            Config::path().display()
+        )
+    })?;
+
+    Ok(calibrated)
+}
+
+fn draw_monitor(frame: &mut Frame, levels: &Levels, settings: &Settings, log_scroll: usize) {
+    let params = settings.params.lock().unwrap();
+    let [
+        vu_area,
+        brightness_area,
+        spectrum_area,
+        settings_area,
+        log_area,
+        help_area,
+    ] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Min(6),
+        Constraint::Length(9),
+        Constraint::Min(5),
+        Constraint::Length(2),
+    ])
+    .areas(frame.area());
+
+    draw_vu(frame, vu_area, levels, &params);
+
+    let brightness_title = if params.paused {
+        " Brightness (paused) "
+    } else {
+        " Brightness "
+    };
+    let brightness = Gauge::default()
+        .block(Block::bordered().title(brightness_title))
+        .gauge_style(Style::default().fg(Color::Yellow))
+        .ratio(ratio(levels.brightness, 100.0))
+        .label(format!("{:.0}%", levels.brightness));
+    frame.render_widget(brightness, brightness_area);
+
+    draw_spectrum(frame, spectrum_area, &levels.bands);
+
+    let lines = vec![
+        Line::from(match unavailable() {
+            Some(reason) => format!("Device:      {} (unavailable: {reason})", settings.device),
+            None => format!("Device:      {}", settings.device),
+        }),
+        Line::from(format!(
+            "Sample rate: {} Hz, chunk {} ms, output {}, {} stalls",
+            settings.sample_rate,
+            settings.timing.chunk_size.as_millis(),
+            settings
+                .timing
+                .update_rate
+                .map_or("per chunk".to_string(), |rate| format!("{rate:.0} Hz")),
+            stall_count()
+        )),
+        Line::from(format!(
+            "Measured:    {:.1} chunks/s, {:.1} writes/s{}",
+            levels.chunk_rate,
+            levels.write_rate,
+            if params.low_power { " (low power)" } else { "" }
+        )),
+        Line::from(format!(
+            "Health:      jitter {:.1} ms, buffer {:.0}%, {} samples dropped, write {:.1} ms",
+            levels.health.stream.jitter.as_secs_f32() * 1000.0,
+            levels.health.stream.occupancy * 100.0,
+            levels.health.stream.dropped,
+            levels.health.write_latency.as_secs_f32() * 1000.0
+        )),
+        Line::from(format!(
+            "RMS range:   {:.3} - {:.3}, boost {:.2}",
+            params.min_rms, params.max_rms, params.boost
+        )),
+        Line::from(match params.threshold_mode {
+            ThresholdMode::Factor => format!(
+                "Threshold:   {:.2}x moving average over {} ms",
+                params.threshold_factor,
+                params.average.as_millis()
+            ),
+            ThresholdMode::Adaptive => format!(
+                "Threshold:   moving average + {:.2} standard deviations over {} ms",
+                params.threshold_factor,
+                params.average.as_millis()
+            ),
+        }),
+        Line::from(format!(
+            "Envelope:    attack {:.0} %/s, release {:.0} %/s",
+            params.attack, params.release
+        )),
+    ];
+    let settings_widget = Paragraph::new(lines).block(Block::bordered().title(" Settings "));
+    frame.render_widget(settings_widget, settings_area);
+
+    draw_log(frame, log_area, log_scroll);
 
-    // Create a window
-    let window = ApplicationWindow::builder()
-        .application(app)
-        .title("My GTK App")
-        .child(&button)
-        .build();
+    let help_text = Paragraph::new(vec![
+        help(" +/-: sensitivity  [/]: boost  a/A: attack  r/R: release  space: pause"),
+        help(" c: calibrate  d: pick device  pgup/pgdn/end: scroll log  q: quit"),
+    ]);
+    frame.render_widget(help_text, help_area);
+}
+
+/// Shows the captured log messages, `scroll` lines up from the newest one.
+fn draw_log(frame: &mut Frame, area: Rect, scroll: usize) {
+    let entries = log::entries();
+    let height = area.height.saturating_sub(2) as usize;
+    let end = entries
+        .len()
+        .saturating_sub(scroll)
+        .max(height.min(entries.len()));
+    let start = end.saturating_sub(height);
+
+    let lines: Vec<Line> = entries[start..end]
+        .iter()
+        .map(|entry| {
+            let color = match entry.level {
+                Level::ERROR => Color::Red,
+                Level::WARN => Color::Yellow,
+                Level::INFO => Color::Reset,
+                _ => Color::DarkGray,
+            };
+            Line::from(format!(
+                "{:>8.1}s {:<5} {}",
+                entry.elapsed.as_secs_f32(),
+                entry.level,
+                entry.message
+            ))
+            .style(color)
+        })
+        .collect();
+
+    let title = if end < entries.len() {
+        format!(" Log (+{} newer) ", entries.len() - end)
+    } else {
+        " Log ".to_string()
+    };
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+/// Walks the user through the calibration steps while showing the level being recorded.
+fn draw_calibrating(frame: &mut Frame, levels: &Levels, calibration: &Calibration) {
+    let [instructions_area, level_area, help_area] = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let instruction = match calibration.step() {
+        Step::Quiet => "Step 1/2: be quiet, measuring the noise floor...",
+        Step::Loud => "Step 2/2: now play loud music, measuring the peak level...",
+    };
+    let lines = vec![
+        Line::from(instruction),
+        Line::from(""),
+        Line::from(format!("{} s left", calibration.remaining().as_secs() + 1)),
+    ];
+    let instructions = Paragraph::new(lines).block(Block::bordered().title(" Calibration "));
+    frame.render_widget(instructions, instructions_area);
+
+    let level =
+        Paragraph::new(format!("rms {:.3}", levels.rms)).block(Block::bordered().title(" Level "));
+    frame.render_widget(level, level_area);
+
+    frame.render_widget(help(" esc: cancel"), help_area);
+}
+
+/// Shows the calibration outcome and the brightness curve it produces.
+fn draw_calibrated(frame: &mut Frame, outcome: &Result<Calibrated, String>, settings: &Settings) {
+    let [result_area, curve_area, help_area] = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Min(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let lines = match outcome {
+        Ok(calibrated) => vec![
+            Line::from(format!("Noise floor: {:.3}", calibrated.noise_floor)),
+            Line::from(format!("Peak:        {:.3}", calibrated.peak)),
+            Line::from(format!("Boost:       {:.2}", calibrated.boost)),
+        ],
+        Err(e) => vec![Line::from(e.as_str()).style(Color::Red)],
+    };
+    let result = Paragraph::new(lines).block(Block::bordered().title(" Calibration result "));
+    frame.render_widget(result, result_area);
+
+    draw_curve(frame, curve_area, &settings.params.lock().unwrap());
+
+    frame.render_widget(help(" enter: back"), help_area);
+}
+
+/// Lists the input devices with the selected one highlighted.
+fn draw_picker(frame: &mut Frame, picker: &mut Picker) {
+    let [list_area, help_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+
+    let list = List::new(picker.devices.iter().map(String::as_str))
+        .block(Block::bordered().title(" Input device "))
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::Yellow))
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(list, list_area, &mut picker.list);
+
+    frame.render_widget(help(" up/down: move  enter: select  esc: back"), help_area);
+}
+
+/// Plots brightness against RMS for the current mapping parameters.
+fn draw_curve(frame: &mut Frame, area: Rect, params: &Params) {
+    let max_x = (params.max_rms * 1.2).max(0.001) as f64;
+    let points: Vec<(f64, f64)> = (0..=100)
+        .map(|i| {
+            let rms = max_x * i as f64 / 100.0;
+            (rms, brightness_for(rms as f32, params) as f64)
+        })
+        .collect();
+
+    let dataset = Dataset::default()
+        .marker(Marker::Braille)
+        .graph_type(GraphType::Line)
+        .style(Style::default().fg(Color::Yellow))
+        .data(&points);
+
+    let chart = Chart::new(vec![dataset])
+        .block(Block::bordered().title(" Mapping curve "))
+        .x_axis(
+            Axis::default()
+                .title("rms")
+                .bounds([0.0, max_x])
+                .labels(["0".to_string(), format!("{max_x:.3}")]),
+        )
+        .y_axis(
+            Axis::default()
+                .title("%")
+                .bounds([0.0, 100.0])
+                .labels(["0", "100"]),
+        );
+    frame.render_widget(chart, area);
+}
+
+fn help(text: &str) -> Line<'_> {
+    Line::from(text).style(Color::DarkGray)
+}
+
+/// Draws the RMS level relative to the calibrated range, green below the threshold and red above.
+fn draw_vu(frame: &mut Frame, area: Rect, levels: &Levels, params: &Params) {
+    let range = params.max_rms - params.min_rms;
+    let color = if levels.rms > levels.threshold {
+        Color::Red
+    } else {
+        Color::Green
+    };
+
+    let vu = Gauge::default()
+        .block(Block::bordered().title(" Level "))
+        .gauge_style(Style::default().fg(color))
+        .ratio(ratio(levels.rms - params.min_rms, range))
+        .label(format!(
+            "rms {:.3} / threshold {:.3}",
+            levels.rms, levels.threshold
+        ));
+    frame.render_widget(vu, area);
+}
+
+fn draw_spectrum(frame: &mut Frame, area: Rect, bands: &[f32]) {
+    let block = Block::bordered().title(" Spectrum ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    if bands.is_empty() {
+        return;
+    }
+
+    let gap = 1;
+    let width = (inner.width / bands.len() as u16)
+        .saturating_sub(gap)
+        .max(1);
+    let bars: Vec<Bar> = bands
+        .iter()
+        .map(|&band| Bar::default().value((band * 100.0) as u64).text_value(""))
+        .collect();
+
+    let chart = BarChart::default()
+        .data(BarGroup::default().bars(&bars))
+        .bar_width(width)
+        .bar_gap(gap)
+        .bar_style(Style::default().fg(Color::Cyan))
+        .max(100);
+    frame.render_widget(chart, inner);
+}
 
-    // Present window
-    window.present();
+/// Converts `value / max` to a gauge ratio, clamped to 0.0-1.0.
+fn ratio(value: f32, max: f32) -> f64 {
+    if max > 0.0 {
+        (value / max).clamp(0.0, 1.0) as f64
+    } else {
+        0.0
+    }
 }
@@ -0,0 +1,42 @@
+//! Telling speech apart from other sounds, for the voice-only mode.
+//!
+//! A chunk is taken as speech when it is loud enough and its zero-crossing rate lies where voices
+//! do: rumble and bass cross zero far less often, hiss and cymbals far more. A short hangover keeps
+//! the pauses between syllables from counting as silence.
+
+/// Lowest normalized level of speech, quieter chunks are background noise.
+const MIN_LEVEL: f32 = 0.05;
+/// Range of zero crossings per second of speech, voiced and unvoiced.
+const CROSSING_RATE: (f32, f32) = (200.0, 4000.0);
+/// How long speech is assumed to go on after the last chunk that sounded like it, in seconds.
+const HANGOVER: f32 = 0.3;
+
+/// Follows whether someone is talking, chunk after chunk.
+#[derive(Default)]
+pub struct VoiceDetector {
+    /// Time left before a pause is taken as the end of speech, in seconds.
+    hangover: f32,
+}
+
+impl VoiceDetector {
+    /// Records `chunk`, lasting `chunk_secs` with a `normalized` level, and returns whether
+    /// someone is talking.
+    pub fn update(&mut self, chunk: &[f32], normalized: f32, chunk_secs: f32) -> bool {
+        if normalized >= MIN_LEVEL && is_voiced(chunk, chunk_secs) {
+            self.hangover = HANGOVER;
+            return true;
+        }
+        self.hangover = (self.hangover - chunk_secs).max(0.0);
+        self.hangover > 0.0
+    }
+}
+
+/// Whether the zero-crossing rate of `chunk`, lasting `chunk_secs`, is within [`CROSSING_RATE`].
+fn is_voiced(chunk: &[f32], chunk_secs: f32) -> bool {
+    let crossings = chunk
+        .windows(2)
+        .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+        .count();
+    let rate = crossings as f32 / chunk_secs;
+    (CROSSING_RATE.0..=CROSSING_RATE.1).contains(&rate)
+}
@@ -0,0 +1,316 @@
+//! Sandboxed effect plugins compiled to WebAssembly.
+//!
+//! A plugin is `plugins/NAME.wasm` next to the config file (or `NAME.wat`, in the text format),
+//! selected with [`Params::plugin`](crate::Params::plugin). It exports `frame()`, called for
+//! every chunk, and optionally `init()`, called once it is loaded. It imports nothing but the
+//! host API of the `dynkb` module:
+//!
+//! - `rms() -> f32`, `normalized() -> f32`, `threshold() -> f32`, `beat() -> i32`,
+//!   `brightness() -> f32` and `chunk_secs() -> f32` read the levels of the chunk, see
+//!   [`Chunk`];
+//! - `bands() -> i32` and `band(i32) -> f32` read the band magnitudes, lowest first;
+//! - `write(f32)` sets the chunk's brightness in percent, the pipeline's is kept without it;
+//! - `load(i32) -> f32` and `store(i32, f32)` read and write one of [`SLOTS`] numbers, kept when
+//!   the plugin is reloaded.
+//!
+//! Plugins can't reach the filesystem or the network, get [`MEMORY_LIMIT`] of memory and
+//! [`FUEL_PER_FRAME`] instructions per frame. A file that changes is reloaded.
+//!
+//! Compiling a plugin takes a while, it is done on a thread of its own and the audio passes
+//! through meanwhile.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use wasmtime::{Caller, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use crate::Chunk;
+use crate::config::Config;
+use crate::error::Result;
+use crate::notify;
+use crate::watch::Watched;
+
+/// Number of numbers a plugin keeps with `load` and `store`.
+pub const SLOTS: usize = 64;
+/// Memory a plugin may grow to.
+pub const MEMORY_LIMIT: usize = 16 << 20;
+/// Instructions a plugin may run per frame, far more than an effect needs.
+pub const FUEL_PER_FRAME: u64 = 10_000_000;
+
+/// What a plugin sees of the host.
+struct Host {
+    rms: f32,
+    normalized: f32,
+    threshold: f32,
+    beat: bool,
+    brightness: f32,
+    chunk_secs: f32,
+    bands: Vec<f32>,
+    /// What the plugin wrote this frame.
+    written: Option<f32>,
+    slots: [f32; SLOTS],
+    limits: StoreLimits,
+}
+
+impl Host {
+    fn new(slots: [f32; SLOTS]) -> Self {
+        Self {
+            rms: 0.0,
+            normalized: 0.0,
+            threshold: 0.0,
+            beat: false,
+            brightness: 0.0,
+            chunk_secs: 0.0,
+            bands: Vec::new(),
+            written: None,
+            slots,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MEMORY_LIMIT)
+                .instances(1)
+                .build(),
+        }
+    }
+}
+
+/// A loaded plugin.
+pub struct Plugin {
+    name: String,
+    file: Watched,
+    engine: Engine,
+    instance: Running,
+    /// The new version being compiled, see [`compile_aside`].
+    reloading: Option<Receiver<Result<Running>>>,
+}
+
+/// A started instance of a plugin.
+struct Running {
+    store: Store<Host>,
+    frame: TypedFunc<(), ()>,
+}
+
+impl Plugin {
+    /// Location of the plugins, `plugins/` next to the config file.
+    pub fn dir() -> PathBuf {
+        Config::path().with_file_name("plugins")
+    }
+
+    /// Loads the plugin called `name`, `NAME.wasm` or `NAME.wat` in [`dir`](Self::dir).
+    pub fn load(name: &str) -> Result<Self> {
+        let dir = Self::dir();
+        let wat = dir.join(format!("{name}.wat"));
+        let path = match dir.join(format!("{name}.wasm")) {
+            wasm if !wasm.exists() && wat.exists() => wat,
+            wasm => wasm,
+        };
+        let mut config = wasmtime::Config::new();
+        // A trap is reported in one line, plugins rarely have symbols anyway.
+        config.consume_fuel(true).wasm_backtrace_max_frames(None);
+        let engine = Engine::new(&config)?;
+        let file = Watched::new(path);
+        let instance = instantiate(&engine, file.path(), [0.0; SLOTS])?;
+        info!("Loaded the plugin {}", file.path().display());
+        Ok(Self {
+            name: name.to_string(),
+            file,
+            engine,
+            instance,
+            reloading: None,
+        })
+    }
+
+    /// The name it was loaded by.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runs a frame of the plugin on `chunk`, returning the brightness it wrote, in percent.
+    pub fn frame(&mut self, chunk: &Chunk) -> Result<Option<f32>> {
+        self.reload();
+        let Running { store, frame } = &mut self.instance;
+        let host = store.data_mut();
+        host.rms = chunk.rms;
+        host.normalized = chunk.normalized;
+        host.threshold = chunk.threshold;
+        host.beat = chunk.beat;
+        host.brightness = chunk.brightness;
+        host.chunk_secs = chunk.chunk_secs;
+        host.bands.clear();
+        host.bands.extend_from_slice(chunk.bands);
+        host.written = None;
+        store.set_fuel(FUEL_PER_FRAME)?;
+        frame.call(&mut *store, ())?;
+        Ok(store
+            .data()
+            .written
+            .map(|brightness| brightness.clamp(0.0, 100.0)))
+    }
+
+    /// Loads the plugin again if it changed, keeping its slots, or the previous version if the
+    /// new one fails to load. The previous version runs until the new one is compiled.
+    fn reload(&mut self) {
+        if let Some(reloading) = &self.reloading {
+            match reloading.try_recv() {
+                Ok(Ok(instance)) => {
+                    info!("Reloaded the plugin {}", self.file.path().display());
+                    self.instance = instance;
+                }
+                Ok(Err(e)) => notify::alert("plugin", "The plugin failed to load", &e.to_string()),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {}
+            }
+            self.reloading = None;
+        }
+        if !self.file.changed() {
+            return;
+        }
+        let slots = self.instance.store.data().slots;
+        let (engine, path) = (self.engine.clone(), self.file.path().to_path_buf());
+        self.reloading = Some(compile_aside(move || instantiate(&engine, &path, slots)));
+    }
+}
+
+/// Runs `compile` on a thread of its own, away from the audio, and returns where its result
+/// arrives.
+fn compile_aside<T: Send + 'static>(
+    compile: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Receiver<Result<T>> {
+    let (result_tx, result_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = result_tx.send(compile());
+    });
+    result_rx
+}
+
+/// Compiles and starts the plugin at `path` with `slots`, running its `init`.
+fn instantiate(engine: &Engine, path: &Path, slots: [f32; SLOTS]) -> Result<Running> {
+    let module = Module::from_file(engine, path)?;
+    let mut store = Store::new(engine, Host::new(slots));
+    store.limiter(|host| &mut host.limits);
+    store.set_fuel(FUEL_PER_FRAME)?;
+    let instance = linker(engine)?.instantiate(&mut store, &module)?;
+    if let Some(init) = instance.get_func(&mut store, "init") {
+        init.typed::<(), ()>(&store)?.call(&mut store, ())?;
+    }
+    let frame = instance.get_typed_func(&mut store, "frame")?;
+    Ok(Running { store, frame })
+}
+
+/// The host API, see the [module](self) documentation.
+fn linker(engine: &Engine) -> wasmtime::Result<Linker<Host>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap("dynkb", "rms", |caller: Caller<Host>| caller.data().rms)?;
+    linker.func_wrap("dynkb", "normalized", |caller: Caller<Host>| {
+        caller.data().normalized
+    })?;
+    linker.func_wrap("dynkb", "threshold", |caller: Caller<Host>| {
+        caller.data().threshold
+    })?;
+    linker.func_wrap("dynkb", "beat", |caller: Caller<Host>| {
+        caller.data().beat as i32
+    })?;
+    linker.func_wrap("dynkb", "brightness", |caller: Caller<Host>| {
+        caller.data().brightness
+    })?;
+    linker.func_wrap("dynkb", "chunk_secs", |caller: Caller<Host>| {
+        caller.data().chunk_secs
+    })?;
+    linker.func_wrap("dynkb", "bands", |caller: Caller<Host>| {
+        caller.data().bands.len() as i32
+    })?;
+    linker.func_wrap("dynkb", "band", |caller: Caller<Host>, i: i32| {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| caller.data().bands.get(i).copied())
+            .unwrap_or(0.0)
+    })?;
+    linker.func_wrap(
+        "dynkb",
+        "write",
+        |mut caller: Caller<Host>, brightness: f32| {
+            caller.data_mut().written = Some(brightness);
+        },
+    )?;
+    linker.func_wrap("dynkb", "load", |caller: Caller<Host>, slot: i32| {
+        usize::try_from(slot)
+            .ok()
+            .and_then(|slot| caller.data().slots.get(slot).copied())
+            .unwrap_or(0.0)
+    })?;
+    linker.func_wrap(
+        "dynkb",
+        "store",
+        |mut caller: Caller<Host>, slot: i32, value: f32| {
+            if let Some(slot) = usize::try_from(slot)
+                .ok()
+                .and_then(|slot| caller.data_mut().slots.get_mut(slot))
+            {
+                *slot = value;
+            }
+        },
+    )?;
+    Ok(linker)
+}
+
+/// The plugin the parameters select, loaded when it is first selected.
+#[derive(Default)]
+pub struct Slot {
+    plugin: Option<Plugin>,
+    /// Name of a plugin that failed to load, not tried again until another is selected.
+    failed: Option<String>,
+    /// Name of the plugin being loaded, and where it arrives, see [`compile_aside`].
+    loading: Option<(String, Receiver<Result<Plugin>>)>,
+}
+
+impl Slot {
+    /// Runs `plugin` from now on, as long as it stays selected.
+    pub fn install(&mut self, plugin: Plugin) {
+        self.plugin = Some(plugin);
+        self.failed = None;
+        self.loading = None;
+    }
+
+    /// Runs a frame of the plugin called `name` on `chunk`, returning the brightness it wrote;
+    /// `None` without a plugin, while it loads, or when it fails.
+    pub fn frame(&mut self, name: Option<&str>, chunk: &Chunk) -> Option<f32> {
+        let Some(name) = name else {
+            self.plugin = None;
+            self.failed = None;
+            self.loading = None;
+            return None;
+        };
+        if let Some((loading, plugin)) = &self.loading
+            && loading == name
+        {
+            match plugin.try_recv() {
+                Ok(Ok(plugin)) => self.install(plugin),
+                Ok(Err(e)) => {
+                    notify::alert("plugin", "The plugin failed to load", &e.to_string());
+                    self.failed = Some(name.to_string());
+                }
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {}
+            }
+            self.loading = None;
+        }
+        if self
+            .plugin
+            .as_ref()
+            .is_none_or(|plugin| plugin.name != name)
+            && self.failed.as_deref() != Some(name)
+        {
+            self.plugin = None;
+            let owned = name.to_string();
+            self.loading = Some((
+                name.to_string(),
+                compile_aside(move || Plugin::load(&owned)),
+            ));
+            return None;
+        }
+        let plugin = self.plugin.as_mut()?;
+        plugin.frame(chunk).unwrap_or_else(|e| {
+            notify::alert("plugin", "The plugin failed", &e.to_string());
+            None
+        })
+    }
+}
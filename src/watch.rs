@@ -0,0 +1,54 @@
+//! Noticing that a script or plugin file changed, for its host to reload it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often a watched file is checked for changes.
+const POLL: Duration = Duration::from_secs(1);
+
+/// A file polled for changes.
+pub struct Watched {
+    path: PathBuf,
+    /// When the version last seen was written.
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Watched {
+    /// Watches `path`, its current version counting as seen.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            modified: modified(&path),
+            path,
+            checked: Instant::now(),
+        }
+    }
+
+    /// The file watched.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file was written since the last version seen, checked at most every
+    /// [`POLL`]. A version is only reported once, whether it then loads or not.
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < POLL {
+            return false;
+        }
+        self.checked = Instant::now();
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+/// When the file at `path` was written.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
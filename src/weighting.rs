@@ -0,0 +1,94 @@
+//! Frequency weighting of the level, for a loudness closer to what ears and broadcast meters
+//! report than the plain RMS.
+//!
+//! K-weighting is the pre-filter of ITU-R BS.1770 loudness (LUFS): a high shelf of about +4 dB
+//! above 2 kHz modelling the head, then a high-pass removing what lies below 40 Hz or so.
+
+use std::f32::consts::PI;
+
+/// Centre frequency of the K-weighting shelf, in Hz.
+const SHELF_HZ: f32 = 1_681.974_5;
+/// Gain of the shelf, in dB.
+const SHELF_GAIN_DB: f32 = 3.999_844;
+/// Quality factor of the shelf.
+const SHELF_Q: f32 = 0.707_175_2;
+/// Cutoff of the K-weighting high-pass, in Hz.
+const HIGH_PASS_HZ: f32 = 38.135_47;
+/// Quality factor of the high-pass.
+const HIGH_PASS_Q: f32 = 0.500_327;
+
+/// The K-weighting filter, keeping its state from chunk to chunk.
+#[derive(Default)]
+pub struct KWeighting {
+    /// Sample rate the stages were designed for, 0 before the first chunk.
+    sample_rate: u32,
+    /// The shelf then the high-pass.
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    /// Writes `samples`, at `sample_rate` Hz, K-weighted to `out`.
+    pub fn filter(&mut self, samples: &[f32], sample_rate: u32, out: &mut Vec<f32>) {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            self.stages = [
+                Biquad::shelf(sample_rate as f32),
+                Biquad::high_pass(sample_rate as f32),
+            ];
+        }
+        out.clear();
+        out.extend(samples.iter().map(|&sample| {
+            let shelved = self.stages[0].process(sample);
+            self.stages[1].process(shelved)
+        }));
+    }
+}
+
+/// A second-order IIR section, in transposed direct form II.
+#[derive(Default)]
+struct Biquad {
+    b: [f32; 3],
+    /// Feedback coefficients, `a0` normalized to 1.
+    a: [f32; 2],
+    state: [f32; 2],
+}
+
+impl Biquad {
+    /// The shelf stage at `rate` Hz, as BS.1770 derives it for any sample rate.
+    fn shelf(rate: f32) -> Self {
+        let k = (PI * SHELF_HZ / rate).tan();
+        let vh = 10f32.powf(SHELF_GAIN_DB / 20.0);
+        let vb = vh.powf(0.499_666_77);
+        let a0 = 1.0 + k / SHELF_Q + k * k;
+        Self {
+            b: [
+                (vh + vb * k / SHELF_Q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / SHELF_Q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / SHELF_Q + k * k) / a0],
+            state: [0.0; 2],
+        }
+    }
+
+    /// The high-pass stage at `rate` Hz.
+    fn high_pass(rate: f32) -> Self {
+        let k = (PI * HIGH_PASS_HZ / rate).tan();
+        let a0 = 1.0 + k / HIGH_PASS_Q + k * k;
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [
+                2.0 * (k * k - 1.0) / a0,
+                (1.0 - k / HIGH_PASS_Q + k * k) / a0,
+            ],
+            state: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
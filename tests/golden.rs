@@ -0,0 +1,104 @@
+//! Test signals processed offline by `process`, compared with the timelines in `tests/golden`.
+//!
+//! A change to the analysis or the mapping that moves the brightness shows up as a diff of these
+//! files; once it is intended, `UPDATE_GOLDEN=1 cargo test --features file` rewrites them.
+
+#![cfg(feature = "file")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use backlight_mic::SAMPLE_RATE;
+use backlight_mic::generator::{Generator, Signal};
+use backlight_mic::source::AudioSource;
+
+/// Length of the processed signals.
+const SECONDS: u32 = 3;
+
+/// Writes `seconds` of `signal` to `path` as a 16-bit mono WAV file.
+fn write_wav(path: &Path, signal: Signal) {
+    let mut generator = Generator::new(signal, false);
+    let mut samples = Vec::new();
+    while samples.len() < (SECONDS * SAMPLE_RATE) as usize {
+        generator.read(&mut samples).unwrap();
+    }
+    samples.truncate((SECONDS * SAMPLE_RATE) as usize);
+
+    let data = samples.len() as u32 * 2;
+    let mut wav = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend((36 + data).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    // PCM, one channel.
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(SAMPLE_RATE.to_le_bytes());
+    wav.extend((SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend(2u16.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data.to_le_bytes());
+    for sample in samples {
+        wav.extend(((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    fs::write(path, wav).unwrap();
+}
+
+/// The timeline `process` prints for `signal`, with the default config.
+fn process(name: &str, signal: Signal) -> String {
+    let dir = std::env::temp_dir().join(format!(
+        "dynkbacklight-golden-{name}-{}",
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    let wav = dir.join(format!("{name}.wav"));
+    write_wav(&wav, signal);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_backlight_mic"))
+        .arg("process")
+        .arg(&wav)
+        // Not the user's config.
+        .env("XDG_CONFIG_HOME", &dir)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn check(name: &str, signal: Signal) {
+    let timeline = process(name, signal);
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.csv"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &timeline).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden).unwrap();
+    for (line, (got, want)) in timeline.lines().zip(expected.lines()).enumerate() {
+        assert_eq!(got, want, "{name}.csv differs on line {}", line + 1);
+    }
+    assert_eq!(timeline.lines().count(), expected.lines().count());
+}
+
+#[test]
+fn bursts() {
+    check("bursts", Signal::Bursts);
+}
+
+#[test]
+fn pink_noise() {
+    check("pink_noise", Signal::PinkNoise);
+}
+
+#[test]
+fn sweep() {
+    check("sweep", Signal::Sweep);
+}
@@ -0,0 +1,174 @@
+//! Known signals fed through the whole pipeline, checking the brightness it writes.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use backlight_mic::config::{Config, Stage};
+use backlight_mic::expression::Expression;
+use backlight_mic::generator::{Generator, Signal};
+use backlight_mic::preset;
+use backlight_mic::sink::{self, Memory};
+use backlight_mic::source::AudioSource;
+use backlight_mic::swap::{self, Kind};
+use backlight_mic::{CHUNK_SIZE, Params, RELEASE, RmsState, Timing};
+
+/// The sink is global, the tests take turns writing to it.
+static SINK: Mutex<()> = Mutex::new(());
+
+/// Feeds `seconds` of `signal` through the pipeline with the default settings as changed by
+/// `configure`, returning every brightness written.
+fn run(signal: Signal, seconds: f32, configure: impl FnOnce(&mut Params)) -> Vec<f32> {
+    let _turn = turn();
+    let mut params = Config::default().params();
+    configure(&mut params);
+    let memory = Memory::new();
+    sink::select(Box::new(memory.clone()));
+
+    let params = Arc::new(Mutex::new(params));
+    let mut state = RmsState::new(Timing::default(), Vec::new(), params);
+    let mut generator = Generator::new(signal, false);
+    let mut samples = Vec::new();
+    while (samples.len() as f32) < seconds * generator.sample_rate() as f32 {
+        generator.read(&mut samples).unwrap();
+    }
+    state.feed(&samples, generator.sample_rate());
+    memory.writes()
+}
+
+fn turn() -> MutexGuard<'static, ()> {
+    // A failed test must not fail the others.
+    SINK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Number of chunks in `seconds`.
+fn chunks(seconds: f32) -> usize {
+    (seconds / CHUNK_SIZE.as_secs_f32()).round() as usize
+}
+
+#[test]
+fn silence_stays_dark() {
+    let writes = run(Signal::Silence, 2.0, |_| {});
+    assert_eq!(writes.len(), chunks(2.0));
+    assert!(
+        writes.iter().all(|&brightness| brightness == 0.0),
+        "{writes:?}"
+    );
+}
+
+#[test]
+fn bursts_flash_and_fade() {
+    let writes = run(Signal::Bursts, 2.0, |_| {});
+    assert_eq!(writes.len(), chunks(2.0));
+
+    // The first burst is the moving average itself, the following ones stand out of it.
+    let period = chunks(0.5);
+    let fade = RELEASE * CHUNK_SIZE.as_secs_f32();
+    for burst in writes.chunks(period).skip(1) {
+        let (flash, rest) = burst.split_at(chunks(0.1));
+        assert!(
+            flash.iter().all(|&brightness| brightness == 100.0),
+            "{burst:?}"
+        );
+        let mut previous = 100.0;
+        for &brightness in rest {
+            assert!((previous - brightness - fade).abs() < 1e-3, "{burst:?}");
+            previous = brightness;
+        }
+    }
+}
+
+#[test]
+fn max_brightness_scales_the_flashes() {
+    let writes = run(Signal::Bursts, 1.0, |params| params.max_brightness = 40.0);
+    let peak = writes.iter().copied().fold(0.0, f32::max);
+    assert_eq!(peak, 40.0);
+}
+
+#[test]
+fn mapping_replaces_the_curve() {
+    let writes = run(Signal::Bursts, 1.0, |params| {
+        params.mapping = Some(Expression::parse("if(normalized > 0, max / 4, 0)").unwrap());
+    });
+    let peak = writes.iter().copied().fold(0.0, f32::max);
+    assert_eq!(peak, 25.0);
+}
+
+#[test]
+fn swapped_mapping_survives_reloads() {
+    let params = Arc::new(Mutex::new(Config::default().params()));
+    let mut config = Config::default();
+    config
+        .profiles
+        .entry(config.profile.clone())
+        .or_default()
+        .mapping = Some(Expression::parse("max / 2").unwrap());
+
+    let swapped = Expression::parse("if(normalized > 0, max / 4, 0)").unwrap();
+    swap::load(Kind::Mapping, &swapped.to_string(), &params).unwrap();
+    config.apply(&mut params.lock().unwrap());
+    assert_eq!(params.lock().unwrap().mapping, Some(swapped));
+
+    swap::unload(Kind::Mapping, &params).unwrap();
+    config.apply(&mut params.lock().unwrap());
+    assert_eq!(params.lock().unwrap().mapping, None);
+}
+
+#[test]
+fn built_in_presets_flash_on_bursts() {
+    for name in preset::BUILT_IN {
+        let mut config = Config::default();
+        preset::built_in(name).unwrap().apply(name, &mut config);
+        let writes = run(Signal::Bursts, 2.0, |params| config.apply(params));
+        let peak = writes.iter().copied().fold(0.0, f32::max);
+        assert!(peak > 50.0, "{name} peaks at {peak}");
+    }
+}
+
+#[test]
+fn pipeline_without_smoother_goes_dark_at_once() {
+    let writes = run(Signal::Bursts, 1.0, |params| {
+        params.pipeline = vec![Stage::Mapper {
+            boost: None,
+            mapping: None,
+        }];
+    });
+    let (flash, rest) = writes[chunks(0.5)..].split_at(chunks(0.1));
+    assert!(
+        flash.iter().all(|&brightness| brightness > 0.0),
+        "{writes:?}"
+    );
+    assert!(
+        rest.iter().all(|&brightness| brightness == 0.0),
+        "{writes:?}"
+    );
+}
+
+#[test]
+fn paused_writes_nothing() {
+    let writes = run(Signal::Bursts, 1.0, |params| params.paused = true);
+    assert!(writes.is_empty(), "{writes:?}");
+}
+
+#[test]
+fn off_stays_dark() {
+    let writes = run(Signal::Bursts, 1.0, |params| params.off = true);
+    assert!(
+        writes.iter().all(|&brightness| brightness == 0.0),
+        "{writes:?}"
+    );
+}
+
+#[test]
+fn tapped_tempo_sets_the_beats() {
+    // Steady noise has no beats of its own.
+    let writes = run(Signal::WhiteNoise, 2.0, |params| params.tempo = Some(120.0));
+    let mut previous = 0.0;
+    let mut rises = Vec::new();
+    for (i, &brightness) in writes.iter().enumerate() {
+        if brightness > previous {
+            rises.push(i);
+        }
+        previous = brightness;
+    }
+    let period = chunks(0.5);
+    assert_eq!(rises, [0, period, 2 * period, 3 * period], "{writes:?}");
+}
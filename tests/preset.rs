@@ -0,0 +1,69 @@
+//! Presets shared as JSON, which must keep importing across releases, and the one started with,
+//! which must outlive config reloads.
+
+use std::fs;
+use std::path::PathBuf;
+
+use backlight_mic::config::{Config, Stage};
+use backlight_mic::control;
+use backlight_mic::preset::{self, FORMAT_VERSION};
+
+/// A fresh file called `name` in the temporary directory.
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dynkbacklight-{}-{name}", std::process::id()))
+}
+
+#[test]
+fn exported_presets_import() {
+    let path = temp_file("exported.json");
+    let ambient = preset::built_in("ambient").unwrap();
+    preset::export(&ambient, "ambient", &path).unwrap();
+    let (name, imported) = preset::import(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(name.as_deref(), Some("ambient"));
+    assert_eq!(imported.description, ambient.description);
+    assert_eq!(imported.profile.release, ambient.profile.release);
+    assert_eq!(imported.layers.map(|layers| layers.glow), Some(60.0));
+}
+
+#[test]
+fn newer_presets_leave_out_what_they_add() {
+    let path = temp_file("newer.json");
+    let newer = serde_json::json!({
+        "version": FORMAT_VERSION + 1,
+        "preset": {
+            "profile": { "boost": 2.5, "gamma": 2.2 },
+            "sparkles": { "rate": 3 },
+            "pipeline": [{ "stage": "strobe", "hz": 8 }, { "stage": "glow" }],
+        },
+    });
+    fs::write(&path, newer.to_string()).unwrap();
+    let (name, imported) = preset::import(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(name, None);
+    assert_eq!(imported.profile.boost, 2.5);
+    assert!(matches!(imported.pipeline[..], [Stage::Glow]));
+}
+
+#[test]
+fn started_preset_survives_reloads() {
+    let edm = preset::built_in("edm").unwrap();
+    let mut config = Config::default();
+    edm.apply("edm", &mut config);
+    let mut params = config.params();
+
+    // The config file doesn't have the preset, it was only applied in memory.
+    let mut reloaded = Config::default();
+    control::apply_reloaded(&mut reloaded, Some("edm"), &mut params);
+    assert_eq!(reloaded.profile, "edm");
+    assert_eq!(params.boost, edm.profile.boost);
+    assert_eq!(params.release, edm.profile.release);
+
+    // Until another profile is selected.
+    let mut reloaded = Config::default();
+    control::apply_reloaded(&mut reloaded, None, &mut params);
+    assert_eq!(reloaded.profile, Config::default().profile);
+    assert_eq!(params.boost, Config::default().params().boost);
+}